@@ -1,5 +1,6 @@
 use types::{
-    BackBuffer, ClientIndex, Cmd, GlobalData, Msg, Point, Rope, Utils,
+    insert_at_points, remove_ranges, BackBuffer, ClientIndex, Cmd, GlobalData, Msg, Point, Rope,
+    Utils,
 };
 
 #[derive(Debug, Default)]
@@ -48,21 +49,67 @@ pub fn update(
     match cmd {
         Msg::Cmd(client_index, cmd) => {
             let client = &global_data.clients[*client_index];
-            let current_buffer = &mut global_data.buffers[client.buffer];
+            let current_buffer = &mut global_data.buffers[client.buffer()];
             let rope = &mut current_buffer.rope;
             match cmd {
                 InsertCharAtPoint(c, point) => {
                     let index = get_ropey_index_from_point(point, &rope);
+                    current_buffer.marks.follow_insert(rope, &[index], &c.to_string());
                     rope.insert_char(index, *c);
+                    current_buffer.version += 1;
                     send_cmd(*client_index, BufferModified);
                 }
                 InsertStringAtPoint(string, point) => {
                     let index = get_ropey_index_from_point(point, &rope);
+                    current_buffer.marks.follow_insert(rope, &[index], &string);
                     rope.insert(index, &string);
+                    current_buffer.version += 1;
                     send_cmd(*client_index, BufferModified);
                 }
                 DeleteCharRange(start, end) => {
-                    rope.remove(get_char_range_from_points(start, end, &rope));
+                    let range = get_char_range_from_points(start, end, &rope);
+                    current_buffer.marks.follow_remove(rope, &[*range.start()..*range.end() + 1]);
+                    rope.remove(range);
+                    current_buffer.version += 1;
+                    send_cmd(*client_index, BufferModified);
+                }
+                InsertStringAtPoints(string, points) => {
+                    let indices: Vec<usize> = points
+                        .iter()
+                        .map(|point| {
+                            std::cmp::min(get_ropey_index_from_point(point, &rope), rope.len_chars())
+                        })
+                        .collect();
+                    current_buffer.marks.follow_insert(rope, &indices, &string);
+                    insert_at_points(rope, &string, &points);
+                    current_buffer.version += 1;
+                    send_cmd(*client_index, BufferModified);
+                }
+                DeleteCharRanges(ranges) => {
+                    let removed: Vec<std::ops::Range<usize>> = ranges
+                        .iter()
+                        .map(|(start, end)| {
+                            let range = get_char_range_from_points(start, end, &rope);
+                            *range.start()..*range.end() + 1
+                        })
+                        .collect();
+                    current_buffer.marks.follow_remove(rope, &removed);
+                    remove_ranges(rope, &ranges);
+                    current_buffer.version += 1;
+                    send_cmd(*client_index, BufferModified);
+                }
+                ReplaceLines(first_line, last_line, text) => {
+                    let end_line = std::cmp::min(*last_line + 1, rope.len_lines());
+                    let start = rope.line_to_char(*first_line);
+                    let end = rope.line_to_char(end_line);
+                    rope.remove(start..end);
+                    rope.insert(start, &text);
+                    current_buffer.marks.follow_replaced_lines(
+                        *first_line,
+                        std::cmp::max(*first_line + 1, end_line) - 1,
+                        text.matches('\n').count(),
+                    );
+                    current_buffer.version += 1;
                     send_cmd(*client_index, BufferModified);
                 }
                 _ => {}
@@ -129,25 +176,21 @@ mod tests {
     #[test]
     fn test_update_delete_char_range() {
         // Definitely abstract a ton of this into another module cause it is a huge PITA to set up
-        use types::{Buffer, Client, Mode, Utils};
+        use types::{Buffer, Client, Utils};
         use crossbeam_channel::{bounded, Sender};
         let mut global_data = GlobalData::default();
         let rope = Rope::from_str( "test someting with \nlots of test data");
         let new_buffer_index = global_data.buffer_keys.insert(());
         let new_buffer = Buffer {
             rope,
-            source: std::path::PathBuf::default(),
-            start_line: 0,
+            ..Buffer::default()
         };
         global_data.buffers.insert(new_buffer_index, new_buffer);
         let new_client_index = global_data.client_keys.insert(());
-        let new_client = Client {
-            stream: std::os::unix::net::UnixStream::pair().unwrap().0,
-            back_buffer: std::default::Default::default(),
-            buffer: new_buffer_index,
-            mode: Mode::Normal,
-            size: std::default::Default::default(),
-        };
+        let new_client = Client::new(
+            std::os::unix::net::UnixStream::pair().unwrap().0,
+            new_buffer_index,
+        );
         global_data.clients.insert(new_client_index, new_client);
         let start_point = Point {
             x: 5,
@@ -166,7 +209,9 @@ mod tests {
             warn: |msg|{},
             debug: |msg|{},
             style_range: |_,_,_,_,_,_|{},
-            style_rope_slice_range: |_,_,_,_,_,_|{},
+            clear_range: |_,_,_|{},
+            style_rope_slice_range: |_,_,_,_,_,_,_|{},
+            gutter_layout: |_,_| Default::default(),
         };
         let c_ptr = init();
         let cmd_handler: Box<Fn(ClientIndex, Cmd)> =
@@ -187,5 +232,6 @@ mod tests {
             Err(_) => panic!("There was no value waiting in the reciever")
         }
         assert_eq!(String::from(global_data.buffers[new_buffer_index].rope.clone()).as_str(), "testof test data");
+        assert!(global_data.buffers[new_buffer_index].is_modified());
     }
 }
@@ -1,20 +1,451 @@
 use ropey::Rope;
-use types::{Buffer, ClientIndex, Cmd, GlobalData, KeyData, Msg, Utils};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+use types::{
+    Backup, Buffer, BufferIndex, ClientIndex, Cmd, GlobalData, Mode, Msg, NotifyLevel, OptionScope,
+    OptionSpec, OptionValue, Options, Picker, PickerItem, PickerSource, SecondaryMap,
+    SplitDirection, Utils, WriteOptions,
+};
+
+#[derive(Debug, Default)]
+struct State {
+    /// Buffer each client is being asked about, which changed on disk while
+    /// it had unsaved changes
+    conflicts: SecondaryMap<ClientIndex, BufferIndex>,
+}
 #[no_mangle]
 pub fn render(_global_data: &GlobalData) {}
 
-fn load_buffer(global_data: &mut GlobalData, client: ClientIndex, file_path: std::path::PathBuf) {
-    let buffer_key = global_data.buffer_keys.insert(());
-    global_data.buffers.insert(
-        buffer_key,
-        Buffer {
-            rope: Rope::from_reader(std::fs::File::open(&file_path).expect("loading file"))
-                .expect("building rope"),
-            source: file_path,
-            start_line: 0,
-        },
+// Turns the io errors users run into every day into something readable
+fn describe_error(err: &std::io::Error) -> String {
+    use std::io::ErrorKind;
+    match err.kind() {
+        ErrorKind::PermissionDenied => "permission denied".into(),
+        ErrorKind::NotFound => "no such file or directory".into(),
+        _ => err.to_string(),
+    }
+}
+
+fn modified_time(path: &Path) -> Option<SystemTime> {
+    std::fs::metadata(path)
+        .and_then(|metadata| metadata.modified())
+        .ok()
+}
+
+// Someone else wrote to the file since we last loaded or saved it
+fn changed_since_load(buffer: &Buffer) -> bool {
+    let on_disk = modified_time(&buffer.source);
+    on_disk.is_some() && on_disk != buffer.disk_modified
+}
+
+/// Opens `file_path` into a new buffer. A path that doesn't exist yet gives an
+/// empty buffer that will create the file when it is first written.
+fn load_buffer(
+    global_data: &mut GlobalData,
+    client: ClientIndex,
+    file_path: std::path::PathBuf,
+) -> Result<bool, String> {
+    if file_path.as_os_str().is_empty() {
+        return Err("no file name".into());
+    }
+    if file_path.is_dir() {
+        return Err("is a directory".into());
+    }
+    let (rope, is_new) = match std::fs::File::open(&file_path) {
+        Ok(file) => (
+            Rope::from_reader(file).map_err(|err| describe_error(&err))?,
+            false,
+        ),
+        Err(ref err) if err.kind() == std::io::ErrorKind::NotFound => (Rope::new(), true),
+        Err(err) => return Err(describe_error(&err)),
+    };
+    let buffer_key = global_data.add_buffer(Buffer {
+        rope,
+        disk_modified: modified_time(&file_path),
+        source: file_path,
+        ..Buffer::default()
+    });
+    global_data.clients[client]
+        .window_mut()
+        .show_buffer(buffer_key, &global_data.buffers[buffer_key].rope);
+    Ok(is_new)
+}
+
+fn backup_path(target: &Path, backup: &Backup) -> Option<PathBuf> {
+    let mut file_name = target.file_name()?.to_os_string();
+    file_name.push("~");
+    match backup {
+        Backup::Off => None,
+        Backup::NextToFile => Some(target.with_file_name(file_name)),
+        Backup::Directory(directory) => Some(directory.join(file_name)),
+    }
+}
+
+// From the backup and backupdir options, an empty backupdir keeps backups next to the file
+fn backup_setting(options: &Options) -> Backup {
+    match (
+        options.get_bool("backup", None),
+        options.get_str("backupdir", None),
+    ) {
+        (Some(true), Some(directory)) if !directory.is_empty() => {
+            Backup::Directory(PathBuf::from(directory))
+        }
+        (Some(true), _) => Backup::NextToFile,
+        _ => Backup::Off,
+    }
+}
+
+// Written next to the target so the final rename never crosses file systems
+fn temp_path(target: &Path) -> PathBuf {
+    let file_name = target
+        .file_name()
+        .map(|file_name| file_name.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    target.with_file_name(format!(".{}.{}.tmp", file_name, std::process::id()))
+}
+
+fn write_temp_file(
+    rope: &Rope,
+    temp: &Path,
+    original: Option<&std::fs::Metadata>,
+) -> io::Result<()> {
+    // Left over from an earlier save that was interrupted
+    let _ = std::fs::remove_file(temp);
+    let file = std::fs::OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(temp)?;
+    if let Some(metadata) = original {
+        use std::os::unix::fs::MetadataExt;
+        file.set_permissions(metadata.permissions())?;
+        // Only root may give files away, keeping our own ownership is fine otherwise
+        let _ = std::os::unix::fs::fchown(&file, Some(metadata.uid()), Some(metadata.gid()));
+    }
+    let mut writer = io::BufWriter::new(file);
+    rope.write_to(&mut writer)?;
+    let file = writer.into_inner().map_err(|err| err.into_error())?;
+    file.sync_all()
+}
+
+// Puts `rope` in place of `target` through a synced temp file next to it,
+// which is gone again whether or not that worked
+fn replace_file(
+    rope: &Rope,
+    target: &Path,
+    original: Option<&std::fs::Metadata>,
+) -> io::Result<()> {
+    let temp = temp_path(target);
+    if let Err(err) =
+        write_temp_file(rope, &temp, original).and_then(|()| std::fs::rename(&temp, target))
+    {
+        let _ = std::fs::remove_file(&temp);
+        return Err(err);
+    }
+    // Make the rename itself durable
+    if let Some(parent) = target.parent() {
+        if let Ok(directory) = std::fs::File::open(parent) {
+            let _ = directory.sync_all();
+        }
+    }
+    Ok(())
+}
+
+/// Writes `rope` to a temp file, syncs it and renames it over `path` so a
+/// failure part way through never leaves a half written file behind.
+/// Returns the number of bytes and lines written.
+fn write_buffer(
+    rope: &Rope,
+    path: &Path,
+    options: &WriteOptions,
+    backup: &Backup,
+) -> Result<(usize, usize), String> {
+    if path.as_os_str().is_empty() {
+        return Err("no file name".into());
+    }
+    if path.is_dir() {
+        return Err("is a directory".into());
+    }
+    if let Some(parent) = path
+        .parent()
+        .filter(|parent| !parent.as_os_str().is_empty())
+    {
+        if !parent.exists() {
+            if options.create_directories {
+                std::fs::create_dir_all(parent).map_err(|err| describe_error(&err))?;
+            } else {
+                return Err(format!(
+                    "directory {} does not exist (add ! to create it)",
+                    parent.display()
+                ));
+            }
+        }
+    }
+    // Write through symlinks instead of replacing them
+    let target = std::fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf());
+    let original = std::fs::metadata(&target).ok();
+    if original.is_some() {
+        if let Some(backup_file) = backup_path(&target, backup) {
+            std::fs::copy(&target, &backup_file).map_err(|err| {
+                format!(
+                    "could not write backup {}: {}",
+                    backup_file.display(),
+                    describe_error(&err)
+                )
+            })?;
+        }
+    }
+    replace_file(rope, &target, original.as_ref()).map_err(|err| describe_error(&err))?;
+    let ends_with_newline = rope.len_chars() > 0 && rope.char(rope.len_chars() - 1) == '\n';
+    let lines = if ends_with_newline {
+        rope.len_lines() - 1
+    } else {
+        rope.len_lines()
+    };
+    Ok((rope.len_bytes(), lines))
+}
+
+/// Writes the buffer and records that it is now in sync with `path`
+fn save_buffer(
+    buffer: &mut Buffer,
+    path: &Path,
+    options: &WriteOptions,
+    backup: &Backup,
+) -> Result<(usize, usize), String> {
+    let is_source = options.set_source || buffer.source == path;
+    if is_source && !options.overwrite_changed && changed_since_load(buffer) {
+        return Err("file changed on disk since it was loaded (add ! to overwrite)".into());
+    }
+    let written = write_buffer(&buffer.rope, path, options, backup)?;
+    if is_source {
+        buffer.source = path.to_path_buf();
+        buffer.saved_version = buffer.version;
+        buffer.disk_modified = modified_time(path);
+        buffer.changed_on_disk = false;
+    }
+    Ok(written)
+}
+
+fn clients_showing(global_data: &GlobalData, buffer_index: BufferIndex) -> Vec<ClientIndex> {
+    global_data
+        .clients
+        .iter()
+        .filter(|(_index, client)| {
+            client.tabs.iter().any(|tab| {
+                tab.windows
+                    .values()
+                    .any(|window| window.buffer == buffer_index)
+            })
+        })
+        .map(|(index, _client)| index)
+        .collect()
+}
+
+/// Replaces the buffer contents with what is on disk, keeping every cursor as
+/// close to where it was as the new contents allow
+fn reload_buffer(global_data: &mut GlobalData, buffer_index: BufferIndex) -> Result<(), String> {
+    let buffer = &mut global_data.buffers[buffer_index];
+    let file = std::fs::File::open(&buffer.source).map_err(|err| describe_error(&err))?;
+    buffer.rope = Rope::from_reader(file).map_err(|err| describe_error(&err))?;
+    buffer.version += 1;
+    buffer.saved_version = buffer.version;
+    buffer.disk_modified = modified_time(&buffer.source);
+    buffer.changed_on_disk = false;
+    let rope = &global_data.buffers[buffer_index].rope;
+    for (_client_index, client) in global_data.clients.iter_mut() {
+        for tab in client.tabs.iter_mut() {
+            for (_window_index, window) in tab.windows.iter_mut() {
+                if window.buffer == buffer_index {
+                    window.clamp_to(rope);
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+// Reloads the buffer and tells `client` how that went
+fn reload_and_report(
+    global_data: &mut GlobalData,
+    client: ClientIndex,
+    buffer_index: BufferIndex,
+    send_cmd: &Box<Fn(ClientIndex, Cmd)>,
+) {
+    let name = global_data.buffers[buffer_index].display_name();
+    let notification = match reload_buffer(global_data, buffer_index) {
+        Ok(()) => {
+            send_cmd(client, Cmd::BufferReloaded(buffer_index));
+            Cmd::Notify(NotifyLevel::Info, format!("\"{}\" reloaded", name))
+        }
+        Err(err) => Cmd::Notify(
+            NotifyLevel::Error,
+            format!("Could not reload {}: {}", name, err),
+        ),
+    };
+    send_cmd(client, notification);
+}
+
+fn keep_buffer(buffer: &mut Buffer) {
+    if buffer.changed_on_disk {
+        // The next save may go over the version on disk
+        buffer.disk_modified = modified_time(&buffer.source);
+        buffer.changed_on_disk = false;
+    }
+}
+
+// Opens the differences with the file on disk in a split of the client's window
+fn show_diff(
+    global_data: &mut GlobalData,
+    client: ClientIndex,
+    buffer_index: BufferIndex,
+    send_cmd: &Box<Fn(ClientIndex, Cmd)>,
+) {
+    match diff_with_disk(&global_data.buffers[buffer_index]) {
+        Ok(Some(diff)) => {
+            let diff_buffer = global_data.add_buffer(Buffer {
+                rope: Rope::from_str(&diff),
+                ..Buffer::default()
+            });
+            send_cmd(client, Cmd::SplitWindow(SplitDirection::Vertical));
+            send_cmd(client, Cmd::ShowBuffer(diff_buffer));
+        }
+        Ok(None) => send_cmd(
+            client,
+            Cmd::Notify(NotifyLevel::Info, "No differences".into()),
+        ),
+        Err(err) => send_cmd(
+            client,
+            Cmd::Notify(NotifyLevel::Error, format!("Could not diff: {}", err)),
+        ),
+    }
+}
+
+fn ask_about_conflict(name: &str, client: ClientIndex, send_cmd: &Box<Fn(ClientIndex, Cmd)>) {
+    send_cmd(
+        client,
+        Cmd::Notify(
+            NotifyLevel::Warning,
+            format!(
+                "{} changed on disk and has unsaved changes: (r)eload, (k)eep yours, (d)iff?",
+                name
+            ),
+        ),
     );
-    global_data.clients[client].buffer = buffer_key;
+}
+
+fn file_changed(
+    data: &mut State,
+    global_data: &mut GlobalData,
+    path: &Path,
+    send_cmd: &Box<Fn(ClientIndex, Cmd)>,
+) {
+    let changed: Vec<BufferIndex> = global_data
+        .buffers
+        .iter()
+        .filter(|(_index, buffer)| {
+            !buffer.source.as_os_str().is_empty()
+                && std::fs::canonicalize(&buffer.source)
+                    .map(|source| source == path)
+                    .unwrap_or(false)
+        })
+        // Our own saves show up here too
+        .filter(|(_index, buffer)| changed_since_load(buffer))
+        .map(|(index, _buffer)| index)
+        .collect();
+    for buffer_index in changed {
+        let name = global_data.buffers[buffer_index].display_name();
+        let showing = clients_showing(global_data, buffer_index);
+        if global_data.buffers[buffer_index].is_modified() {
+            global_data.buffers[buffer_index].changed_on_disk = true;
+            for client in showing {
+                // Already answering something, :e!, :keep and :diffdisk do the same
+                if global_data.clients[client].mode == Mode::Confirm
+                    || data.conflicts.contains_key(client)
+                {
+                    send_cmd(
+                        client,
+                        Cmd::Notify(
+                            NotifyLevel::Warning,
+                            format!(
+                                "{} changed on disk: :e! to load it, :keep to keep yours, :diffdisk to compare",
+                                name
+                            ),
+                        ),
+                    );
+                } else {
+                    data.conflicts.insert(client, buffer_index);
+                    ask_about_conflict(&name, client, send_cmd);
+                    send_cmd(client, Cmd::ChangeMode(Mode::Confirm));
+                }
+            }
+            continue;
+        }
+        let notification = match reload_buffer(global_data, buffer_index) {
+            Ok(()) => {
+                // Language plugins keep their own copy of every buffer, shown or not
+                if let Some(client) = global_data.clients.keys().next() {
+                    send_cmd(client, Cmd::BufferReloaded(buffer_index));
+                }
+                Cmd::Notify(NotifyLevel::Info, format!("\"{}\" reloaded", name))
+            }
+            Err(err) => Cmd::Notify(
+                NotifyLevel::Error,
+                format!("Could not reload {}: {}", name, err),
+            ),
+        };
+        for client in showing {
+            send_cmd(client, notification.clone());
+        }
+    }
+}
+
+// Unified diff from the file on disk to the buffer, `None` when they are the same
+fn diff_with_disk(buffer: &Buffer) -> Result<Option<String>, String> {
+    // Created fresh next to the file the way saves are, never through whatever is already there
+    let temp = temp_path(&buffer.source);
+    if let Err(err) = write_temp_file(&buffer.rope, &temp, None) {
+        let _ = std::fs::remove_file(&temp);
+        return Err(describe_error(&err));
+    }
+    let output = std::process::Command::new("diff")
+        .arg("-u")
+        .arg(&buffer.source)
+        .arg(&temp)
+        .output();
+    let _ = std::fs::remove_file(&temp);
+    let output = output.map_err(|err| format!("could not run diff: {}", err))?;
+    match output.status.code() {
+        Some(0) => Ok(None),
+        Some(1) => Ok(Some(String::from_utf8_lossy(&output.stdout).into_owned())),
+        _ => Err(String::from_utf8_lossy(&output.stderr).trim().to_string()),
+    }
+}
+
+fn buffers_picker(global_data: &GlobalData) -> Picker {
+    Picker {
+        title: "Buffers".into(),
+        items: global_data
+            .buffers_by_number()
+            .into_iter()
+            .map(|index| {
+                let buffer = &global_data.buffers[index];
+                PickerItem {
+                    label: format!(
+                        "{} {}{}",
+                        buffer.number,
+                        buffer.display_name(),
+                        if buffer.is_modified() { " [+]" } else { "" }
+                    ),
+                    action: Cmd::ShowBuffer(index),
+                    preview: if buffer.source.as_os_str().is_empty() {
+                        None
+                    } else {
+                        Some(buffer.source.clone())
+                    },
+                }
+            })
+            .collect(),
+    }
 }
 
 #[no_mangle]
@@ -23,8 +454,10 @@ pub fn update(
     msg: &Msg,
     utils: &Utils,
     send_cmd: &Box<Fn(ClientIndex, Cmd)>,
+    data_ptr: *mut c_void,
 ) {
     use Cmd::*;
+    let mut data: Box<State> = unsafe { Box::from_raw(data_ptr as *mut State) };
     match msg {
         Msg::Cmd(ref client, cmd) => match cmd {
             LoadFile(file_path) => {
@@ -38,42 +471,591 @@ pub fn update(
                     .find(|(_index, buffer)| buffer.source == file_path.as_path())
                     .map(|(index, _buffer)| index);
                 if let Some(index) = maybe_index {
-                    global_data.clients[*client].buffer = index;
+                    global_data.clients[*client]
+                        .window_mut()
+                        .show_buffer(index, &global_data.buffers[index].rope);
+                    send_cmd(*client, Cmd::BufferLoaded);
                 } else {
-                    load_buffer(global_data, *client, file_path.clone());
+                    match load_buffer(global_data, *client, file_path.clone()) {
+                        Ok(is_new) => {
+                            if is_new {
+                                send_cmd(
+                                    *client,
+                                    Cmd::Notify(
+                                        NotifyLevel::Info,
+                                        format!("\"{}\" [New File]", file_path.display()),
+                                    ),
+                                );
+                            }
+                            send_cmd(*client, Cmd::BufferLoaded);
+                        }
+                        Err(err) => send_cmd(
+                            *client,
+                            Cmd::Notify(
+                                NotifyLevel::Error,
+                                format!("Could not open {}: {}", file_path.display(), err),
+                            ),
+                        ),
+                    }
                 }
-                send_cmd(*client, Cmd::BufferLoaded);
             }
-            WriteBuffer(path) => {
-                let file = std::fs::File::create(path).expect("opening file");
-                global_data.buffers[global_data.clients[*client].buffer]
-                    .rope
-                    .write_to(file)
-                    .expect("writing to file");
+            WriteBuffer(path, options) => {
+                let buffer = &mut global_data.buffers[global_data.clients[*client].buffer()];
+                let backup = backup_setting(&global_data.options);
+                match save_buffer(buffer, path, options, &backup) {
+                    Ok((bytes, lines)) => send_cmd(
+                        *client,
+                        Cmd::Notify(
+                            NotifyLevel::Info,
+                            format!("\"{}\" {}L, {}B written", path.display(), lines, bytes),
+                        ),
+                    ),
+                    Err(err) => send_cmd(
+                        *client,
+                        Cmd::Notify(
+                            NotifyLevel::Error,
+                            format!("Could not write {}: {}", path.display(), err),
+                        ),
+                    ),
+                }
+            }
+            WriteAllBuffers => {
+                let backup = &backup_setting(&global_data.options);
+                for (_index, buffer) in global_data.buffers.iter_mut() {
+                    if !buffer.is_modified() {
+                        continue;
+                    }
+                    let source = buffer.source.clone();
+                    if let Err(err) = save_buffer(buffer, &source, &WriteOptions::default(), backup)
+                    {
+                        send_cmd(
+                            *client,
+                            Cmd::Notify(
+                                NotifyLevel::Error,
+                                format!("Could not write {}: {}", buffer.display_name(), err),
+                            ),
+                        );
+                    }
+                }
+            }
+            ShowBuffer(buffer_index) => {
+                if global_data.buffer_keys.contains_key(*buffer_index) {
+                    global_data.clients[*client]
+                        .window_mut()
+                        .show_buffer(*buffer_index, &global_data.buffers[*buffer_index].rope);
+                    send_cmd(*client, Cmd::BufferLoaded);
+                }
+            }
+            AlternateBuffer => {
+                let window = global_data.clients[*client].window_mut();
+                match window.alternate {
+                    Some(alternate) if global_data.buffer_keys.contains_key(alternate) => {
+                        window.show_buffer(alternate, &global_data.buffers[alternate].rope);
+                        send_cmd(*client, Cmd::BufferLoaded);
+                    }
+                    _ => send_cmd(
+                        *client,
+                        Cmd::Notify(NotifyLevel::Error, "No alternate file".into()),
+                    ),
+                }
+            }
+            DeleteBuffer(buffer_index, _force)
+                if !global_data.buffer_keys.contains_key(*buffer_index) => {}
+            DeleteBuffer(buffer_index, force) => {
+                let buffer = &global_data.buffers[*buffer_index];
+                if buffer.is_modified() && !force {
+                    send_cmd(
+                        *client,
+                        Cmd::Notify(
+                            NotifyLevel::Error,
+                            format!(
+                                "No write since last change for {} (add ! to override)",
+                                buffer.display_name()
+                            ),
+                        ),
+                    );
+                } else {
+                    global_data.remove_buffer(*buffer_index);
+                    send_cmd(*client, Cmd::BufferDeleted(*buffer_index));
+                    send_cmd(*client, Cmd::BufferLoaded);
+                }
+            }
+            ReloadBuffer => {
+                let buffer_index = global_data.clients[*client].buffer();
+                reload_and_report(global_data, *client, buffer_index, send_cmd);
+            }
+            KeepBuffer => {
+                keep_buffer(&mut global_data.buffers[global_data.clients[*client].buffer()])
             }
-            SearchFiles => {
-                use std::process::Command;
-                let key_data: KeyData = KeyData::from(client.clone());
-                Command::new("tmux")
-                    .args(&["split-pane", &format!("sk | xargs -0 -I {{}} cargo run --release -- --target {} --command \"edit {{}}\"", key_data.as_ffi())])
-                    .spawn().expect("spawning sk in tmux pane");
+            DiffWithDisk => {
+                let buffer_index = global_data.clients[*client].buffer();
+                show_diff(global_data, *client, buffer_index, send_cmd);
+            }
+            Confirm(answer) => {
+                if let Some(buffer_index) = data.conflicts.remove(*client) {
+                    let answered = !global_data.buffer_keys.contains_key(buffer_index)
+                        || match answer {
+                            'r' => {
+                                reload_and_report(global_data, *client, buffer_index, send_cmd);
+                                true
+                            }
+                            'k' => {
+                                keep_buffer(&mut global_data.buffers[buffer_index]);
+                                true
+                            }
+                            'd' => {
+                                show_diff(global_data, *client, buffer_index, send_cmd);
+                                true
+                            }
+                            // Leaves the buffer flagged, for :e!, :keep or :diffdisk later
+                            'q' => true,
+                            _ => false,
+                        };
+                    if answered {
+                        send_cmd(*client, Cmd::ChangeMode(Mode::Normal));
+                    } else {
+                        let name = global_data.buffers[buffer_index].display_name();
+                        ask_about_conflict(&name, *client, send_cmd);
+                        data.conflicts.insert(*client, buffer_index);
+                    }
+                }
+            }
+            Pick(PickerSource::Buffers) => {
+                send_cmd(*client, Cmd::OpenPicker(buffers_picker(global_data)));
             }
             _ => {}
         },
+        Msg::FileChanged(path) => file_changed(&mut data, global_data, path, send_cmd),
         _ => {}
     }
+    std::mem::forget(data);
 }
 
 use std::ffi::c_void;
 #[no_mangle]
-pub fn init() -> *mut c_void {
-    0 as *mut c_void
+pub fn init(global_data: &mut GlobalData) -> *mut c_void {
+    global_data.options.declare(OptionSpec {
+        abbreviation: Some("bk".into()),
+        ..OptionSpec::new("backup", OptionScope::Global, OptionValue::Bool(false))
+    });
+    global_data.options.declare(OptionSpec {
+        abbreviation: Some("bdir".into()),
+        ..OptionSpec::new(
+            "backupdir",
+            OptionScope::Global,
+            OptionValue::String(String::new()),
+        )
+    });
+    Box::into_raw(Box::new(State::default())) as *mut c_void
 }
 
 #[no_mangle]
 pub fn cleanup(data: *mut c_void) {
     unsafe {
-        let ptr = Box::from_raw(data);
+        let ptr = Box::from_raw(data as *mut State);
         drop(ptr);
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::unix::net::UnixStream;
+
+    // A fresh directory under the system temp dir for each test
+    fn test_dir(name: &str) -> PathBuf {
+        let dir =
+            std::env::temp_dir().join(format!("buffer-manager-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn buffer_for(path: &Path, text: &str) -> Buffer {
+        Buffer {
+            rope: Rope::from_str(text),
+            source: path.to_path_buf(),
+            disk_modified: modified_time(path),
+            ..Buffer::default()
+        }
+    }
+
+    #[test]
+    fn test_load_errors() {
+        let dir = test_dir("load");
+        let mut global_data = GlobalData::default();
+        global_data.add_buffer(Buffer::default());
+        let (stream, _other_end) = UnixStream::pair().unwrap();
+        let client = global_data.attach_client(stream);
+        assert_eq!(
+            load_buffer(&mut global_data, client, PathBuf::new()),
+            Err("no file name".into())
+        );
+        assert_eq!(
+            load_buffer(&mut global_data, client, dir.clone()),
+            Err("is a directory".into())
+        );
+        // A file that isn't there yet opens as a new, empty buffer
+        let new_file = dir.join("new.txt");
+        assert_eq!(
+            load_buffer(&mut global_data, client, new_file.clone()),
+            Ok(true)
+        );
+        let buffer = &global_data.buffers[global_data.clients[client].buffer()];
+        assert_eq!(buffer.source, new_file);
+        assert_eq!(buffer.rope.len_chars(), 0);
+        std::fs::write(dir.join("old.txt"), "one\ntwo\n").unwrap();
+        assert_eq!(
+            load_buffer(&mut global_data, client, dir.join("old.txt")),
+            Ok(false)
+        );
+        let buffer = &global_data.buffers[global_data.clients[client].buffer()];
+        assert_eq!(buffer.rope.to_string(), "one\ntwo\n");
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_write() {
+        let dir = test_dir("write");
+        let path = dir.join("file.txt");
+        let mut buffer = buffer_for(&path, "one\ntwo\n");
+        buffer.version = 3;
+        assert_eq!(
+            save_buffer(&mut buffer, &path, &WriteOptions::default(), &Backup::Off),
+            Ok((8, 2))
+        );
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "one\ntwo\n");
+        assert!(!buffer.is_modified());
+        // Missing directories are only made with !
+        let nested = dir.join("a/b/file.txt");
+        assert!(
+            save_buffer(&mut buffer, &nested, &WriteOptions::default(), &Backup::Off)
+                .unwrap_err()
+                .contains("add ! to create it")
+        );
+        let create = WriteOptions {
+            create_directories: true,
+            ..WriteOptions::default()
+        };
+        assert!(save_buffer(&mut buffer, &nested, &create, &Backup::Off).is_ok());
+        assert_eq!(std::fs::read_to_string(&nested).unwrap(), "one\ntwo\n");
+        assert_eq!(
+            save_buffer(&mut buffer, &dir, &WriteOptions::default(), &Backup::Off),
+            Err("is a directory".into())
+        );
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_refuse_to_overwrite_changed_file() {
+        let dir = test_dir("changed");
+        let path = dir.join("file.txt");
+        std::fs::write(&path, "theirs\n").unwrap();
+        let mut buffer = buffer_for(&path, "ours\n");
+        // Loaded before someone else wrote the file
+        buffer.disk_modified = Some(std::time::UNIX_EPOCH);
+        buffer.version = 1;
+        assert!(
+            save_buffer(&mut buffer, &path, &WriteOptions::default(), &Backup::Off)
+                .unwrap_err()
+                .contains("add ! to overwrite")
+        );
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "theirs\n");
+        assert!(buffer.is_modified());
+        // :w!
+        let force = WriteOptions {
+            overwrite_changed: true,
+            ..WriteOptions::default()
+        };
+        assert!(save_buffer(&mut buffer, &path, &force, &Backup::Off).is_ok());
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "ours\n");
+        assert!(!buffer.is_modified());
+        assert_eq!(buffer.disk_modified, modified_time(&path));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_write_elsewhere_and_saveas() {
+        let dir = test_dir("saveas");
+        let path = dir.join("file.txt");
+        let copy = dir.join("copy.txt");
+        let mut buffer = buffer_for(&path, "text\n");
+        buffer.version = 1;
+        // :w other keeps the buffer on its own file, still unsaved
+        assert!(save_buffer(&mut buffer, &copy, &WriteOptions::default(), &Backup::Off).is_ok());
+        assert_eq!(buffer.source, path);
+        assert!(buffer.is_modified());
+        // :saveas moves it over
+        let moved = dir.join("moved.txt");
+        let saveas = WriteOptions {
+            set_source: true,
+            ..WriteOptions::default()
+        };
+        assert!(save_buffer(&mut buffer, &moved, &saveas, &Backup::Off).is_ok());
+        assert_eq!(buffer.source, moved);
+        assert!(!buffer.is_modified());
+        assert_eq!(std::fs::read_to_string(&moved).unwrap(), "text\n");
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_temp_file_is_removed_on_failure() {
+        let dir = test_dir("temp");
+        let path = dir.join("file.txt");
+        std::fs::write(&path, "old\n").unwrap();
+        let metadata = std::fs::metadata(&path).unwrap();
+        assert!(replace_file(&Rope::from_str("new\n"), &path, Some(&metadata)).is_ok());
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "new\n");
+        assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 1);
+        // A file can't be renamed over a directory that has something in it
+        let blocked = dir.join("blocked");
+        std::fs::create_dir(&blocked).unwrap();
+        std::fs::write(blocked.join("inside"), "").unwrap();
+        assert!(replace_file(&Rope::from_str("new\n"), &blocked, None).is_err());
+        assert!(!temp_path(&blocked).exists());
+        assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 2);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_backup() {
+        let dir = test_dir("backup");
+        let backups = dir.join("backups");
+        std::fs::create_dir(&backups).unwrap();
+        let path = dir.join("file.txt");
+        std::fs::write(&path, "old\n").unwrap();
+        let mut buffer = buffer_for(&path, "new\n");
+        let backup = Backup::Directory(backups.clone());
+        assert!(save_buffer(&mut buffer, &path, &WriteOptions::default(), &backup).is_ok());
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "new\n");
+        assert_eq!(
+            std::fs::read_to_string(backups.join("file.txt~")).unwrap(),
+            "old\n"
+        );
+        assert!(!dir.join("file.txt~").exists());
+        // The backupdir option picks the directory, next to the file when it is empty
+        let mut global_data = GlobalData::default();
+        init(&mut global_data);
+        let options = &mut global_data.options;
+        assert_eq!(backup_setting(options), Backup::Off);
+        options
+            .set("backup", OptionValue::Bool(true), None)
+            .unwrap();
+        assert_eq!(backup_setting(options), Backup::NextToFile);
+        let backupdir = OptionValue::String(backups.display().to_string());
+        options.set("backupdir", backupdir, None).unwrap();
+        assert_eq!(backup_setting(options), Backup::Directory(backups));
+        // A file that is new has nothing to back up yet
+        let new_file = dir.join("new.txt");
+        let mut buffer = buffer_for(&new_file, "text\n");
+        assert!(save_buffer(
+            &mut buffer,
+            &new_file,
+            &WriteOptions::default(),
+            &Backup::NextToFile
+        )
+        .is_ok());
+        assert!(!dir.join("new.txt~").exists());
+        assert!(save_buffer(
+            &mut buffer,
+            &new_file,
+            &WriteOptions::default(),
+            &Backup::NextToFile
+        )
+        .is_ok());
+        assert_eq!(
+            std::fs::read_to_string(dir.join("new.txt~")).unwrap(),
+            "text\n"
+        );
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    type Sent = std::rc::Rc<std::cell::RefCell<Vec<(ClientIndex, Cmd)>>>;
+
+    // Keeps whatever the plugin sends instead of passing it on
+    fn recorder() -> (Sent, Box<Fn(ClientIndex, Cmd)>) {
+        let sent = Sent::default();
+        let log = sent.clone();
+        let send_cmd: Box<Fn(ClientIndex, Cmd)> =
+            Box::new(move |client, cmd| log.borrow_mut().push((client, cmd)));
+        (sent, send_cmd)
+    }
+
+    fn test_utils() -> Utils {
+        Utils {
+            write_to_buffer: |_, _, _, _, _, _| {},
+            style_range: |_, _, _, _, _, _| {},
+            clear_range: |_, _, _| {},
+            style_rope_slice_range: |_, _, _, _, _, _, _| {},
+            gutter_layout: |_, _| Default::default(),
+            info: |_| {},
+            debug: |_| {},
+            warn: |_| {},
+        }
+    }
+
+    // A client showing `file.txt` from a new test directory, which then changes on disk
+    fn changed_file(name: &str, modified: bool) -> (PathBuf, GlobalData, ClientIndex, BufferIndex) {
+        let dir = test_dir(name);
+        let path = dir.join("file.txt");
+        std::fs::write(&path, "theirs\n").unwrap();
+        let mut buffer = buffer_for(&path, "ours\n");
+        // Loaded before someone else wrote the file
+        buffer.disk_modified = Some(std::time::UNIX_EPOCH);
+        if modified {
+            buffer.version = 1;
+        }
+        let mut global_data = GlobalData::default();
+        let buffer_index = global_data.add_buffer(buffer);
+        let (stream, _other_end) = UnixStream::pair().unwrap();
+        let client = global_data.attach_client(stream);
+        (dir, global_data, client, buffer_index)
+    }
+
+    #[test]
+    fn test_reload_when_clean() {
+        let (dir, mut global_data, client, buffer_index) = changed_file("clean", false);
+        let (sent, send_cmd) = recorder();
+        let mut state = State::default();
+        let path = std::fs::canonicalize(dir.join("file.txt")).unwrap();
+        file_changed(&mut state, &mut global_data, &path, &send_cmd);
+        let buffer = &global_data.buffers[buffer_index];
+        assert_eq!(buffer.rope.to_string(), "theirs\n");
+        assert!(!buffer.is_modified());
+        assert!(!buffer.changed_on_disk);
+        let sent = sent.borrow();
+        assert_eq!(sent[0], (client, Cmd::BufferReloaded(buffer_index)));
+        assert_eq!(sent.len(), 2);
+        // Seeing our own save come back does nothing
+        drop(sent);
+        let (sent, send_cmd) = recorder();
+        file_changed(&mut state, &mut global_data, &path, &send_cmd);
+        assert!(sent.borrow().is_empty());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_flag_when_dirty() {
+        let (dir, mut global_data, client, buffer_index) = changed_file("dirty", true);
+        let (sent, send_cmd) = recorder();
+        let utils = test_utils();
+        let data_ptr = init(&mut global_data);
+        let path = std::fs::canonicalize(dir.join("file.txt")).unwrap();
+        update(
+            &mut global_data,
+            &Msg::FileChanged(path),
+            &utils,
+            &send_cmd,
+            data_ptr,
+        );
+        let buffer = &global_data.buffers[buffer_index];
+        assert_eq!(buffer.rope.to_string(), "ours\n");
+        assert!(buffer.changed_on_disk);
+        assert!(sent
+            .borrow()
+            .contains(&(client, Cmd::ChangeMode(Mode::Confirm))));
+        // Anything but an answer asks again
+        sent.borrow_mut().clear();
+        let answer = |answer| Msg::Cmd(client, Cmd::Confirm(answer));
+        update(&mut global_data, &answer('x'), &utils, &send_cmd, data_ptr);
+        assert!(global_data.buffers[buffer_index].changed_on_disk);
+        assert_eq!(sent.borrow().len(), 1);
+        // Keeping ours lets the next save go over theirs
+        update(&mut global_data, &answer('k'), &utils, &send_cmd, data_ptr);
+        let buffer = &global_data.buffers[buffer_index];
+        assert!(!buffer.changed_on_disk);
+        assert_eq!(buffer.disk_modified, modified_time(&buffer.source));
+        assert_eq!(
+            sent.borrow().last(),
+            Some(&(client, Cmd::ChangeMode(Mode::Normal)))
+        );
+        cleanup(data_ptr);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_reload_from_prompt() {
+        let (dir, mut global_data, client, buffer_index) = changed_file("prompt", true);
+        let (sent, send_cmd) = recorder();
+        let utils = test_utils();
+        let data_ptr = init(&mut global_data);
+        let path = std::fs::canonicalize(dir.join("file.txt")).unwrap();
+        update(
+            &mut global_data,
+            &Msg::FileChanged(path),
+            &utils,
+            &send_cmd,
+            data_ptr,
+        );
+        sent.borrow_mut().clear();
+        let msg = Msg::Cmd(client, Cmd::Confirm('r'));
+        update(&mut global_data, &msg, &utils, &send_cmd, data_ptr);
+        let buffer = &global_data.buffers[buffer_index];
+        assert_eq!(buffer.rope.to_string(), "theirs\n");
+        assert!(!buffer.is_modified());
+        assert!(!buffer.changed_on_disk);
+        assert_eq!(
+            sent.borrow()[0],
+            (client, Cmd::BufferReloaded(buffer_index))
+        );
+        // The answer was used up, a stray one does nothing
+        sent.borrow_mut().clear();
+        update(&mut global_data, &msg, &utils, &send_cmd, data_ptr);
+        assert!(sent.borrow().is_empty());
+        cleanup(data_ptr);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_delete_buffer() {
+        let mut global_data = GlobalData::default();
+        let first = global_data.add_buffer(Buffer::default());
+        let (stream, _other_end) = UnixStream::pair().unwrap();
+        let client = global_data.attach_client(stream);
+        global_data.options.declare(OptionSpec::new(
+            "number",
+            OptionScope::Buffer,
+            OptionValue::Bool(true),
+        ));
+        let second = global_data.add_buffer(Buffer {
+            rope: Rope::from_str("text\n"),
+            version: 1,
+            ..Buffer::default()
+        });
+        global_data.clients[client]
+            .window_mut()
+            .show_buffer(second, &Rope::from_str("text\n"));
+        global_data.set_mark(second, 'A', Default::default());
+        let local = OptionValue::Bool(false);
+        global_data
+            .options
+            .set("number", local, Some(second))
+            .unwrap();
+        let (sent, send_cmd) = recorder();
+        let utils = test_utils();
+        let data_ptr = init(&mut global_data);
+        // Unsaved changes need !
+        let msg = Msg::Cmd(client, Cmd::DeleteBuffer(second, false));
+        update(&mut global_data, &msg, &utils, &send_cmd, data_ptr);
+        assert!(global_data.buffer_keys.contains_key(second));
+        let msg = Msg::Cmd(client, Cmd::DeleteBuffer(second, true));
+        update(&mut global_data, &msg, &utils, &send_cmd, data_ptr);
+        assert!(!global_data.buffer_keys.contains_key(second));
+        assert!(sent
+            .borrow()
+            .contains(&(client, Cmd::BufferDeleted(second))));
+        // The window went back to the alternate buffer, which has nothing to go back to
+        let window = global_data.clients[client].window();
+        assert_eq!(window.buffer, first);
+        assert_eq!(window.alternate, None);
+        assert_eq!(global_data.find_mark(first, 'A'), None);
+        assert_eq!(
+            global_data.options.get_bool("number", Some(second)),
+            Some(true)
+        );
+        // The number isn't given out again
+        let third = global_data.add_buffer(Buffer::default());
+        assert_eq!(global_data.buffers[third].number, 3);
+        cleanup(data_ptr);
+    }
+}
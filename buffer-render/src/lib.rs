@@ -1,13 +1,61 @@
 use std::ffi::c_void;
 use std::sync::mpsc::Sender;
 
-use types::{BackBuffer, ClientIndex, GlobalData, Msg, Point, Rect, Utils};
+use types::{BackBuffer, ClientIndex, GlobalData, Msg, Point, Utils, Window, WindowRegion};
 
 #[derive(Debug, Default)]
 struct Data {
     val: bool,
 }
 
+fn render_window(
+    global_data: &GlobalData,
+    window: &Window,
+    region: &WindowRegion,
+    back_buffer: &mut BackBuffer,
+    utils: &Utils,
+) {
+    let buffer = &global_data.buffers[window.buffer];
+    let gutter = (utils.gutter_layout)(global_data, window.buffer);
+    let text_width = gutter.text_width(region.size.w);
+    let start_line = window.start_line;
+    for screen_line in 0..region.size.h {
+        let buffer_line = screen_line as usize + start_line;
+        if buffer_line + 1 >= buffer.rope.len_lines() {
+            break;
+        }
+        let line = buffer.rope.line(buffer_line);
+        if let Some(line_number_x) = gutter.line_number_x {
+            if let Some(position) = region.to_screen(&Point {
+                x: line_number_x,
+                y: screen_line,
+            }) {
+                (utils.write_to_buffer)(
+                    back_buffer,
+                    &position,
+                    &gutter.line_number_label(buffer_line, window.cursor.position.y as usize),
+                    None,
+                    global_data.theme.fg("ui.linenumber"),
+                    None,
+                );
+            }
+        }
+        // Only the part of the line that fits between the gutter and the right edge
+        let visible: String = line
+            .chars()
+            .filter(|c| *c != '\n' && *c != '\r')
+            .skip(window.start_column)
+            .take(text_width)
+            .collect();
+        if let Some(position) = region.to_screen(&Point {
+            x: gutter.width,
+            y: screen_line,
+        }) {
+            (utils.write_to_buffer)(back_buffer, &position, &visible, None, None, None);
+        }
+    }
+}
+
 #[no_mangle]
 pub fn render(
     global_data: &GlobalData,
@@ -17,38 +65,16 @@ pub fn render(
     data_ptr: *mut c_void,
 ) {
     let data: Box<Data> = unsafe { Box::from_raw(data_ptr as *mut Data) };
-    let buffer = &global_data.buffers[global_data.clients[*client].buffer];
-    if let Some(Rect { w: _, h }) = global_data.clients[*client].size {
-        //(100, 50);//termion::terminal_size().unwrap();
-        let start_line = buffer.start_line;
-        let lines_to_render = std::cmp::min(buffer.rope.len_lines() - 1, h as usize - 1);
-        for screen_line in 0..lines_to_render {
-            let buffer_line = screen_line + start_line;
-            let line = buffer.rope.line(buffer_line);
-            (utils.write_to_buffer)(
+    let client = &global_data.clients[*client];
+    if client.size.is_some() {
+        for region in client.window_regions() {
+            render_window(
+                global_data,
+                &client.tab().windows[region.window],
+                &region,
                 back_buffer,
-                &Point {
-                    x: 0,
-                    y: screen_line as u16,
-                },
-                &format!("{}", buffer_line + 1),
-                None,
-                None,
-                None,
+                utils,
             );
-            if let Some(line) = line.as_str() {
-                (utils.write_to_buffer)(
-                    back_buffer,
-                    &Point {
-                        x: 4,
-                        y: screen_line as u16,
-                    },
-                    line,
-                    None,
-                    None,
-                    None,
-                );
-            }
         }
     } else {
         (utils.warn)("Missing client size");
@@ -0,0 +1,198 @@
+use types::LineEdit;
+use unicode_segmentation::UnicodeSegmentation;
+use unicode_width::UnicodeWidthStr;
+
+/// Text typed on the command line and where the cursor is in it
+#[derive(Debug, Default)]
+pub struct CommandBuffer {
+    pub text: String,
+    /// Byte offset into `text`, always between two graphemes
+    pub index: usize,
+}
+
+impl CommandBuffer {
+    /// Replaces the text, leaving the cursor at the end
+    pub fn set(&mut self, text: &str) {
+        self.text = text.into();
+        self.index = self.text.len();
+    }
+
+    pub fn clear(&mut self) {
+        self.set("");
+    }
+
+    fn previous_boundary(&self) -> usize {
+        self.text[..self.index]
+            .grapheme_indices(true)
+            .next_back()
+            .map(|(start, _)| start)
+            .unwrap_or(0)
+    }
+
+    fn next_boundary(&self) -> usize {
+        self.text[self.index..]
+            .graphemes(true)
+            .next()
+            .map(|grapheme| self.index + grapheme.len())
+            .unwrap_or(self.index)
+    }
+
+    pub fn insert(&mut self, c: char) {
+        self.text.insert(self.index, c);
+        // A combining mark joins the grapheme before it, which still ends here
+        self.index += c.len_utf8();
+    }
+
+    pub fn delete_before(&mut self) {
+        let start = self.previous_boundary();
+        self.text.replace_range(start..self.index, "");
+        self.index = start;
+    }
+
+    pub fn delete_after(&mut self) {
+        let end = self.next_boundary();
+        self.text.replace_range(self.index..end, "");
+    }
+
+    pub fn move_left(&mut self) {
+        self.index = self.previous_boundary();
+    }
+
+    pub fn move_right(&mut self) {
+        self.index = self.next_boundary();
+    }
+
+    pub fn edit(&mut self, edit: &LineEdit) {
+        match edit {
+            LineEdit::StartOfLine => self.index = 0,
+            LineEdit::EndOfLine => self.index = self.text.len(),
+            LineEdit::DeleteWordBefore => {
+                // Like readline, spaces before the cursor go along with the word
+                let before = self.text[..self.index].trim_end();
+                let word_start = before
+                    .char_indices()
+                    .rev()
+                    .find(|(_, c)| c.is_whitespace())
+                    .map(|(space, c)| space + c.len_utf8())
+                    .unwrap_or(0);
+                self.text.replace_range(word_start..self.index, "");
+                self.index = word_start;
+            }
+            LineEdit::DeleteToStart => {
+                self.text.replace_range(..self.index, "");
+                self.index = 0;
+            }
+        }
+    }
+
+    /// Terminal columns taken up by the text before the cursor
+    pub fn cursor_column(&self) -> usize {
+        self.text[..self.index].width()
+    }
+
+    /// Where each grapheme goes in terminal columns, with the text to draw there.
+    /// Wide graphemes leave the column after them free.
+    pub fn layout(&self) -> Vec<(usize, &str)> {
+        let mut column = 0;
+        let mut cells = Vec::new();
+        for grapheme in self.text.graphemes(true) {
+            let width = grapheme.width();
+            // Zero width graphemes, control characters mostly, would cover up the one before
+            if width > 0 {
+                cells.push((column, grapheme));
+            }
+            column += width;
+        }
+        cells
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn buffer(text: &str, index: usize) -> CommandBuffer {
+        CommandBuffer {
+            text: text.into(),
+            index,
+        }
+    }
+
+    fn typed(text: &str) -> CommandBuffer {
+        let mut command_buffer = CommandBuffer::default();
+        for c in text.chars() {
+            command_buffer.insert(c);
+        }
+        command_buffer
+    }
+
+    #[test]
+    fn test_delete_word_before() {
+        let mut command_buffer = buffer("e src/lib.rs  ", 14);
+        command_buffer.edit(&LineEdit::DeleteWordBefore);
+        assert_eq!(command_buffer.text, "e ");
+        assert_eq!(command_buffer.index, 2);
+        command_buffer.edit(&LineEdit::DeleteWordBefore);
+        assert_eq!(command_buffer.text, "");
+    }
+
+    #[test]
+    fn test_delete_to_start() {
+        let mut command_buffer = buffer("write file", 6);
+        command_buffer.edit(&LineEdit::DeleteToStart);
+        assert_eq!(command_buffer.text, "file");
+        assert_eq!(command_buffer.index, 0);
+        command_buffer.edit(&LineEdit::EndOfLine);
+        command_buffer.delete_after();
+        assert_eq!(command_buffer.text, "file");
+    }
+
+    #[test]
+    fn test_edit_multibyte_text() {
+        let mut command_buffer = typed("e résumé.txt");
+        command_buffer.edit(&LineEdit::StartOfLine);
+        for _ in 0..4 {
+            command_buffer.move_right();
+        }
+        assert_eq!(command_buffer.cursor_column(), 4);
+        command_buffer.delete_before();
+        command_buffer.insert('ü');
+        assert_eq!(command_buffer.text, "e rüsumé.txt");
+        command_buffer.edit(&LineEdit::EndOfLine);
+        for _ in 0..4 {
+            command_buffer.move_left();
+        }
+        command_buffer.delete_before();
+        assert_eq!(command_buffer.text, "e rüsum.txt");
+        command_buffer.delete_after();
+        assert_eq!(command_buffer.text, "e rüsumtxt");
+    }
+
+    #[test]
+    fn test_combining_marks_and_emoji_are_one_grapheme() {
+        // e followed by a combining acute accent, then a family emoji joined with ZWJs
+        let mut command_buffer = typed("e\u{301}👨\u{200d}👩\u{200d}👧!");
+        command_buffer.move_left();
+        assert_eq!(command_buffer.cursor_column(), 3);
+        command_buffer.delete_before();
+        assert_eq!(command_buffer.text, "e\u{301}!");
+        command_buffer.move_left();
+        assert_eq!(command_buffer.index, 0);
+        command_buffer.delete_after();
+        assert_eq!(command_buffer.text, "!");
+    }
+
+    #[test]
+    fn test_wide_characters() {
+        let mut command_buffer = typed("e 日本語\u{3000}ファイル");
+        assert_eq!(command_buffer.cursor_column(), 2 + 6 + 2 + 8);
+        assert_eq!(
+            command_buffer.layout()[..4],
+            [(0, "e"), (1, " "), (2, "日"), (4, "本")]
+        );
+        command_buffer.edit(&LineEdit::DeleteWordBefore);
+        assert_eq!(command_buffer.text, "e 日本語\u{3000}");
+        command_buffer.edit(&LineEdit::DeleteWordBefore);
+        assert_eq!(command_buffer.text, "e ");
+    }
+}
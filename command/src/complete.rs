@@ -0,0 +1,39 @@
+use std::path::Path;
+
+/// Files and directories that could finish `argument`, directories ending in a
+/// slash. Also returns the directory part of `argument` the names go after.
+pub fn complete_path(argument: &str) -> (String, Vec<String>) {
+    let (directory, file_prefix) = match argument.rfind('/') {
+        Some(slash) => argument.split_at(slash + 1),
+        None => ("", argument),
+    };
+    let search_directory = if directory.is_empty() {
+        Path::new(".")
+    } else {
+        Path::new(directory)
+    };
+    let entries = match std::fs::read_dir(search_directory) {
+        Ok(entries) => entries,
+        Err(_) => return (directory.into(), Vec::new()),
+    };
+    let mut names: Vec<String> = entries
+        .filter_map(|entry| entry.ok())
+        .filter_map(|entry| {
+            let name = entry.file_name().into_string().ok()?;
+            // Hidden files only show up once a dot has been typed
+            if !name.starts_with(file_prefix)
+                || (name.starts_with('.') && !file_prefix.starts_with('.'))
+            {
+                return None;
+            }
+            let is_directory = entry.file_type().map(|kind| kind.is_dir()).unwrap_or(false);
+            Some(if is_directory {
+                format!("{}/", name)
+            } else {
+                name
+            })
+        })
+        .collect();
+    names.sort();
+    (directory.into(), names)
+}
@@ -0,0 +1,131 @@
+use crate::range::LineRange;
+use types::{ArgKind, Args, ExCall, ExCommand, ExCommands};
+
+/// Splits the command name off the front of `text`, the rest starts right after it
+pub fn split_name(text: &str) -> (&str, &str) {
+    let text = text.trim_start();
+    let name_end = text
+        .find(|c: char| !c.is_ascii_alphabetic())
+        .unwrap_or_else(|| text.len());
+    text.split_at(name_end)
+}
+
+fn check_argument(command: &ExCommand, argument: &str) -> Result<(), String> {
+    match (&command.args, argument.is_empty()) {
+        (Args::None, false) => Err(format!("Trailing characters: {}", argument)),
+        (Args::Required(_), true) => Err("Argument required".into()),
+        (Args::Optional(ArgKind::Number), false) | (Args::Required(ArgKind::Number), false) => {
+            argument
+                .trim_start_matches(|c| c == '+' || c == '-')
+                .parse::<u64>()
+                .map(|_| ())
+                .map_err(|_| format!("Number required: {}", argument))
+        }
+        (Args::Optional(ArgKind::Choice(choices)), false)
+        | (Args::Required(ArgKind::Choice(choices)), false) => {
+            if choices.iter().any(|choice| choice == argument) {
+                Ok(())
+            } else {
+                Err(format!("Invalid argument: {}", argument))
+            }
+        }
+        _ => Ok(()),
+    }
+}
+
+/// Looks up the command `text` names and checks what it was given against
+/// what the command takes
+pub fn parse_call(
+    commands: &ExCommands,
+    text: &str,
+    range: Option<LineRange>,
+) -> Result<ExCall, String> {
+    let (word, rest) = split_name(text);
+    let command = commands
+        .find(word)
+        .ok_or_else(|| format!("Not an editor command: {}", text.trim()))?;
+    // Commands that can't be forced are free to use ! in their argument, like s!a!b!
+    let (bang, rest) = if command.bang && rest.starts_with('!') {
+        (true, &rest[1..])
+    } else {
+        (false, rest)
+    };
+    if range.is_some() && !command.range {
+        return Err("No range allowed".into());
+    }
+    let argument = rest.trim();
+    check_argument(command, argument)?;
+    Ok(ExCall {
+        name: command.name.clone(),
+        bang,
+        range: range.map(|range| (range.first, range.last)),
+        argument: argument.into(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn commands() -> ExCommands {
+        let mut commands = ExCommands::default();
+        commands.register(ExCommand {
+            bang: true,
+            ..ExCommand::new("write", "w", Args::Optional(ArgKind::Path))
+        });
+        commands.register(ExCommand {
+            range: true,
+            ..ExCommand::new("substitute", "s", Args::Optional(ArgKind::Text))
+        });
+        commands.register(ExCommand::new(
+            "resize",
+            "res",
+            Args::Required(ArgKind::Number),
+        ));
+        commands
+    }
+
+    #[test]
+    fn test_parse_call() {
+        let commands = commands();
+        assert_eq!(
+            parse_call(&commands, "w! some file", None),
+            Ok(ExCall {
+                name: "write".into(),
+                bang: true,
+                range: None,
+                argument: "some file".into(),
+            })
+        );
+        let range = Some(LineRange { first: 1, last: 3 });
+        let call = parse_call(&commands, "s!a!b!", range).unwrap();
+        assert_eq!((call.bang, call.range), (false, Some((1, 3))));
+        assert_eq!(call.argument, "!a!b!");
+        assert_eq!(
+            parse_call(&commands, "res -4", None).unwrap().argument,
+            "-4"
+        );
+    }
+
+    #[test]
+    fn test_parse_call_errors() {
+        let commands = commands();
+        let range = Some(LineRange { first: 0, last: 0 });
+        assert_eq!(
+            parse_call(&commands, "frobnicate now", None),
+            Err("Not an editor command: frobnicate now".to_string())
+        );
+        assert_eq!(
+            parse_call(&commands, "w", range),
+            Err("No range allowed".to_string())
+        );
+        assert_eq!(
+            parse_call(&commands, "resize", None),
+            Err("Argument required".to_string())
+        );
+        assert_eq!(
+            parse_call(&commands, "resize big", None),
+            Err("Number required: big".to_string())
+        );
+    }
+}
@@ -0,0 +1,117 @@
+use std::path::PathBuf;
+
+// Oldest command lines are dropped once the history has this many
+const MAX_HISTORY: usize = 200;
+
+/// Where the command history is kept between runs, under the XDG data directory
+pub fn history_path() -> Option<PathBuf> {
+    let data_directory = std::env::var_os("XDG_DATA_HOME")
+        .map(PathBuf::from)
+        .or_else(|| {
+            std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".local/share"))
+        })?;
+    Some(data_directory.join("myedit").join("command_history"))
+}
+
+/// One command line per line, oldest first
+pub fn load_history(path: &PathBuf) -> Vec<String> {
+    std::fs::read_to_string(path)
+        .map(|contents| contents.lines().map(String::from).collect())
+        .unwrap_or_default()
+}
+
+pub fn save_history(path: &PathBuf, history: &[String]) -> std::io::Result<()> {
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    let mut contents = history.join("\n");
+    contents.push('\n');
+    std::fs::write(path, contents)
+}
+
+/// Adds `line` as the newest entry, moving it there if it was already in
+pub fn add_to_history(history: &mut Vec<String>, line: &str) {
+    history.retain(|entry| entry != line);
+    history.push(line.into());
+    if history.len() > MAX_HISTORY {
+        history.remove(0);
+    }
+}
+
+/// How far Up and Down have gone through a history
+#[derive(Debug, Default)]
+pub struct HistoryBrowser {
+    position: Option<usize>,
+    /// What was on the command line before the first step, entries have to start with it
+    typed: String,
+}
+
+impl HistoryBrowser {
+    /// The entry to show after stepping to an older or newer one, if there is
+    /// one. Stepping newer than the newest gives back what was typed.
+    pub fn step(&mut self, history: &[String], current: &str, older: bool) -> Option<String> {
+        let start = match self.position {
+            Some(position) => position,
+            None => {
+                self.typed = current.into();
+                history.len()
+            }
+        };
+        let typed = &self.typed;
+        let found = if older {
+            history[..start]
+                .iter()
+                .rposition(|entry| entry.starts_with(typed.as_str()))
+        } else {
+            history
+                .iter()
+                .enumerate()
+                .skip(start + 1)
+                .find(|(_, entry)| entry.starts_with(typed.as_str()))
+                .map(|(index, _)| index)
+        };
+        match found {
+            Some(index) => {
+                self.position = Some(index);
+                Some(history[index].clone())
+            }
+            None if !older && self.position.is_some() => {
+                self.position = None;
+                Some(self.typed.clone())
+            }
+            None => None,
+        }
+    }
+
+    /// Starts over, for when the line was edited or left
+    pub fn reset(&mut self) {
+        self.position = None;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_step_through_matching_entries() {
+        let history: Vec<String> = vec!["e one", "w", "e two", "q"]
+            .into_iter()
+            .map(String::from)
+            .collect();
+        let mut browser = HistoryBrowser::default();
+        assert_eq!(browser.step(&history, "e", true), Some("e two".into()));
+        assert_eq!(browser.step(&history, "e two", true), Some("e one".into()));
+        assert_eq!(browser.step(&history, "e one", true), None);
+        assert_eq!(browser.step(&history, "e one", false), Some("e two".into()));
+        assert_eq!(browser.step(&history, "e two", false), Some("e".into()));
+        assert_eq!(browser.step(&history, "e", false), None);
+    }
+
+    #[test]
+    fn test_add_to_history() {
+        let mut history = vec!["w".to_string(), "q".to_string()];
+        add_to_history(&mut history, "w");
+        assert_eq!(history, vec!["q", "w"]);
+    }
+}
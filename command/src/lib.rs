@@ -1,21 +1,54 @@
+mod command_line;
+mod complete;
+mod ex;
+mod history;
+mod range;
+mod set;
+mod substitute;
+
+use command_line::CommandBuffer;
+use complete::complete_path;
+use ex::{parse_call, split_name};
+use history::{add_to_history, history_path, load_history, save_history, HistoryBrowser};
+use range::{parse_range, LineRange, RangeContext};
 use ropey::Rope;
+use set::{describe, run_set, SetOutcome, SetScope};
 use std::ffi::c_void;
+use substitute::parse_substitute;
 
 use termion::cursor::{Goto, Show};
 use types::{
-    BackBuffer, ClientIndex, Cmd, DeleteDirection, Direction, GlobalData, Mode, Msg, Point, Rect,
-    Utils,
+    ArgKind, Args, BackBuffer, BufferIndex, ClientIndex, Cmd, DeleteDirection, Direction, ExCall,
+    ExCommand, ExCommands, GlobalData, JumpType, Mode, Msg, NotifyLevel, Picker, PickerItem,
+    PickerSource, Point, SplitDirection, Utils, WriteOptions,
 };
 
+// Candidates for the argument being completed, cycled through by repeated tabs
 #[derive(Debug, Default)]
-struct CommandBuffer {
-    pub text: String,
-    pub index: usize,
+struct Completion {
+    prefix: String,
+    candidates: Vec<String>,
+    index: usize,
+}
+
+impl Completion {
+    fn text(&self) -> String {
+        format!("{}{}", self.prefix, self.candidates[self.index])
+    }
 }
 
 #[derive(Debug, Default)]
 struct Data {
     command_buffer: CommandBuffer,
+    completion: Option<Completion>,
+    /// Commands that were run, oldest first
+    history: Vec<String>,
+    /// Where the history is saved, none if there's no home directory to put it in
+    history_path: Option<std::path::PathBuf>,
+    /// Up and Down go through the command history, or the search history when searching
+    history_browser: HistoryBrowser,
+    /// Set while the command line holds a search instead of a command, true for `?`
+    search_backward: Option<bool>,
 }
 
 #[no_mangle]
@@ -28,27 +61,49 @@ pub fn render(
 ) {
     let mode = &global_data.clients[*client].mode;
     let data = unsafe { Box::from_raw(data_ptr as *mut Data) };
-    if let Some(Rect { w: _, h }) = global_data.clients[*client].size {
-        let status_row_y = h - 1;
+    if let Some(command_row) = global_data.clients[*client].command_row() {
         if *mode == Mode::Command {
+            let prompt = match data.search_backward {
+                None => ":",
+                Some(false) => "/",
+                Some(true) => "?",
+            };
             (utils.write_to_buffer)(
                 back_buffer,
                 &Point {
                     x: 0,
-                    y: status_row_y,
+                    y: command_row,
                 },
-                &format!(":{}", data.command_buffer.text),
+                prompt,
                 None,
                 None,
                 None,
             );
+            for (column, grapheme) in data.command_buffer.layout() {
+                // A cell holds a single char, so combining marks can't be drawn
+                let base_len = grapheme.chars().next().map_or(0, char::len_utf8);
+                (utils.write_to_buffer)(
+                    back_buffer,
+                    &Point {
+                        x: column as u16 + 1,
+                        y: command_row,
+                    },
+                    &grapheme[..base_len],
+                    None,
+                    None,
+                    None,
+                );
+            }
             use std::io::Write;
             let mut stream = global_data.clients[*client].stream.try_clone().unwrap();
             write!(
                 stream,
                 "{}{}",
                 Show,
-                Goto(data.command_buffer.index as u16 + 2, status_row_y + 1)
+                Goto(
+                    data.command_buffer.cursor_column() as u16 + 2,
+                    command_row + 1
+                )
             );
         }
     }
@@ -67,6 +122,532 @@ fn get_new_x_position(position: &Point, rope: &Rope) -> u16 {
     )
 }
 
+/// Finds a buffer by its number or by a unique part of its name
+fn find_buffer(global_data: &GlobalData, query: &str) -> Result<BufferIndex, String> {
+    if let Ok(number) = query.parse::<usize>() {
+        return global_data
+            .buffers
+            .iter()
+            .find(|(_index, buffer)| buffer.number == number)
+            .map(|(index, _buffer)| index)
+            .ok_or_else(|| format!("Buffer {} does not exist", number));
+    }
+    let matches: Vec<BufferIndex> = global_data
+        .buffers_by_number()
+        .into_iter()
+        .filter(|index| global_data.buffers[*index].display_name().contains(query))
+        .collect();
+    // An exact name wins over names that merely contain it
+    if let Some(exact) = matches
+        .iter()
+        .find(|index| global_data.buffers[**index].display_name() == query)
+    {
+        return Ok(*exact);
+    }
+    match matches.len() {
+        0 => Err(format!("No matching buffer for {}", query)),
+        1 => Ok(matches[0]),
+        _ => Err(format!("More than one match for {}", query)),
+    }
+}
+
+// One line per buffer in the style of vim's :ls
+fn list_buffers(global_data: &GlobalData, client: ClientIndex) -> Vec<String> {
+    let window = global_data.clients[client].window();
+    global_data
+        .buffers_by_number()
+        .into_iter()
+        .map(|index| {
+            let buffer = &global_data.buffers[index];
+            let flag = if index == window.buffer {
+                '%'
+            } else if Some(index) == window.alternate {
+                '#'
+            } else {
+                ' '
+            };
+            format!(
+                "{:>3} {} {} \"{}\"",
+                buffer.number,
+                flag,
+                if buffer.is_modified() { '+' } else { ' ' },
+                buffer.display_name()
+            )
+        })
+        .collect()
+}
+
+// Next or previous buffer in number order, wrapping around at either end
+fn cycle_buffer(global_data: &GlobalData, client: ClientIndex, forward: bool) -> BufferIndex {
+    let buffers = global_data.buffers_by_number();
+    let current = global_data.clients[client].buffer();
+    let position = buffers
+        .iter()
+        .position(|index| *index == current)
+        .unwrap_or(0);
+    let next = if forward {
+        (position + 1) % buffers.len()
+    } else {
+        (position + buffers.len() - 1) % buffers.len()
+    };
+    buffers[next]
+}
+
+fn complete_command(
+    global_data: &GlobalData,
+    client: ClientIndex,
+    text: &str,
+) -> Option<Completion> {
+    let (_range, rest) = parse_range(text, &range_context(global_data, client)).ok()?;
+    let (word, after_name) = split_name(rest);
+    if after_name.is_empty() {
+        let candidates = global_data.ex_commands.names_starting_with(word);
+        if candidates.is_empty() {
+            return None;
+        }
+        return Some(Completion {
+            prefix: text[..text.len() - word.len()].into(),
+            candidates,
+            index: 0,
+        });
+    }
+    let command = global_data.ex_commands.find(word)?;
+    let argument = after_name.trim_start_matches('!').trim_start();
+    let (argument_prefix, candidates) = match &command.args {
+        Args::Optional(ArgKind::Path) | Args::Required(ArgKind::Path) => complete_path(argument),
+        Args::Optional(ArgKind::Buffer) | Args::Required(ArgKind::Buffer) => (
+            String::new(),
+            global_data
+                .buffers_by_number()
+                .into_iter()
+                .map(|index| global_data.buffers[index].display_name())
+                .filter(|name| name.contains(argument))
+                .collect(),
+        ),
+        // Only the last of several settings gets completed
+        Args::Optional(ArgKind::Option) | Args::Required(ArgKind::Option) => {
+            let word_start = argument.rfind(' ').map(|space| space + 1).unwrap_or(0);
+            let word = &argument[word_start..];
+            (
+                argument[..word_start].to_string(),
+                global_data
+                    .options
+                    .specs()
+                    .into_iter()
+                    .map(|spec| spec.name.clone())
+                    .filter(|name| name.starts_with(word))
+                    .collect(),
+            )
+        }
+        Args::Optional(ArgKind::Choice(choices)) | Args::Required(ArgKind::Choice(choices)) => (
+            String::new(),
+            choices
+                .iter()
+                .filter(|choice| choice.starts_with(argument))
+                .cloned()
+                .collect(),
+        ),
+        _ => return None,
+    };
+    if candidates.is_empty() {
+        None
+    } else {
+        Some(Completion {
+            prefix: format!(
+                "{}{}",
+                &text[..text.len() - argument.len()],
+                argument_prefix
+            ),
+            candidates,
+            index: 0,
+        })
+    }
+}
+
+fn first_modified_buffer(global_data: &GlobalData) -> Option<String> {
+    global_data
+        .buffers
+        .values()
+        .find(|buffer| buffer.is_modified())
+        .map(|buffer| buffer.display_name())
+}
+
+fn unsaved_changes_error(buffer_name: &str) -> Cmd {
+    Cmd::Notify(
+        NotifyLevel::Error,
+        format!(
+            "No write since last change for {} (add ! to override)",
+            buffer_name
+        ),
+    )
+}
+
+// Closes the active window or tab, quitting the client once there is nothing left to close
+fn close_or_quit(
+    global_data: &GlobalData,
+    client: ClientIndex,
+    force: bool,
+    send_cmd: &Box<Fn(ClientIndex, Cmd)>,
+) {
+    let client_data = &global_data.clients[client];
+    if client_data.tab().windows.len() > 1 {
+        send_cmd(client, Cmd::CloseWindow);
+    } else if client_data.tabs.len() > 1 {
+        send_cmd(client, Cmd::CloseTab);
+    } else {
+        send_cmd(client, Cmd::QuitAll(force));
+    }
+}
+
+// Selecting a command or an old command line puts it on the command line to finish or run
+fn command_line_picker<'a>(title: &str, lines: impl Iterator<Item = &'a str>) -> Picker {
+    Picker {
+        title: title.into(),
+        items: lines
+            .map(|line| PickerItem {
+                label: line.into(),
+                action: Cmd::EditCommandLine(line.into()),
+                preview: None,
+            })
+            .collect(),
+    }
+}
+
+// Accepts "+N", "-N" and plain "N" (treated as growing by N)
+fn parse_resize_delta(word: &str) -> Option<i16> {
+    word.trim_start_matches('+').parse().ok()
+}
+
+fn range_context(global_data: &GlobalData, client: ClientIndex) -> RangeContext {
+    let client = &global_data.clients[client];
+    let cursor = &client.window().cursor;
+    let buffer = &global_data.buffers[client.buffer()];
+    RangeContext {
+        current_line: cursor.position.y as usize,
+        last_line: buffer.rope.len_lines().saturating_sub(2),
+        marks: buffer
+            .marks
+            .iter()
+            .map(|(name, point)| (*name, point.y as usize))
+            .collect(),
+        selection: cursor.selection_anchor.as_ref().map(|anchor| {
+            (
+                std::cmp::min(anchor.y, cursor.position.y) as usize,
+                std::cmp::max(anchor.y, cursor.position.y) as usize,
+            )
+        }),
+    }
+}
+
+fn register_commands(commands: &mut ExCommands) {
+    use ArgKind::*;
+    let commands_with_bang = vec![
+        ExCommand::new("write", "w", Args::Optional(Path)),
+        ExCommand::new("saveas", "sav", Args::Required(Path)),
+        ExCommand::new("edit", "e", Args::Optional(Path)),
+        ExCommand::new("quit", "q", Args::None),
+        ExCommand::new("qall", "qa", Args::None),
+        ExCommand::new("kill", "kill", Args::None),
+        ExCommand::new("bdelete", "bd", Args::Optional(Buffer)),
+        ExCommand::new("bwipeout", "bw", Args::Optional(Buffer)),
+    ];
+    for command in commands_with_bang {
+        commands.register(ExCommand {
+            bang: true,
+            ..command
+        });
+    }
+    commands.register(ExCommand::new("wall", "wa", Args::None));
+    commands.register(ExCommand::new("wq", "wq", Args::None));
+    commands.register(ExCommand::new("wqall", "wqa", Args::None));
+    commands.register(ExCommand::new("xall", "xa", Args::None));
+    commands.register(ExCommand::new("keep", "keep", Args::None));
+    commands.register(ExCommand::new("diffdisk", "diffdisk", Args::None));
+    commands.register(ExCommand::new("ls", "ls", Args::None));
+    commands.register(ExCommand::new("buffers", "buffers", Args::None));
+    commands.register(ExCommand::new("files", "files", Args::None));
+    commands.register(ExCommand::new("buffer", "b", Args::Optional(Buffer)));
+    commands.register(ExCommand::new("bnext", "bn", Args::None));
+    commands.register(ExCommand::new("bprevious", "bp", Args::None));
+    commands.register(ExCommand::new("split", "sp", Args::Optional(Path)));
+    commands.register(ExCommand::new("vsplit", "vs", Args::Optional(Path)));
+    commands.register(ExCommand::new("tabnew", "tabnew", Args::Optional(Path)));
+    commands.register(ExCommand::new("tabnext", "tabn", Args::None));
+    commands.register(ExCommand::new("tabprevious", "tabp", Args::None));
+    commands.register(ExCommand::new("tabclose", "tabc", Args::None));
+    commands.register(ExCommand::new(
+        "tabrename",
+        "tabrename",
+        Args::Optional(Text),
+    ));
+    commands.register(ExCommand::new("close", "clo", Args::None));
+    commands.register(ExCommand::new("resize", "res", Args::Required(Number)));
+    // Only `vertical resize N` for now
+    commands.register(ExCommand::new("vertical", "vert", Args::Required(Text)));
+    commands.register(ExCommand::new("messages", "mes", Args::None));
+    commands.register(ExCommand::new("set", "se", Args::Optional(Option)));
+    commands.register(ExCommand::new("setlocal", "setl", Args::Optional(Option)));
+    commands.register(ExCommand::new("setglobal", "setg", Args::Optional(Option)));
+    commands.register(ExCommand {
+        range: true,
+        ..ExCommand::new("substitute", "s", Args::Optional(Text))
+    });
+}
+
+fn optional_path(
+    global_data: &GlobalData,
+    client: ClientIndex,
+    call: &ExCall,
+) -> std::path::PathBuf {
+    if call.argument.is_empty() {
+        global_data.buffers[global_data.clients[client].buffer()]
+            .source
+            .clone()
+    } else {
+        std::path::PathBuf::from(&call.argument)
+    }
+}
+
+// Each word of the argument is a separate setting, with no argument every option is listed
+fn set_options(
+    global_data: &mut GlobalData,
+    client: ClientIndex,
+    argument: &str,
+    scope: SetScope,
+    send_cmd: &Box<Fn(ClientIndex, Cmd)>,
+) {
+    let buffer = global_data.clients[client].buffer();
+    if argument.is_empty() {
+        let options = &global_data.options;
+        let lines = options
+            .specs()
+            .into_iter()
+            .filter_map(|spec| {
+                let value = options.get(&spec.name, Some(buffer))?;
+                Some(describe(spec, value))
+            })
+            .collect();
+        send_cmd(client, Cmd::ShowOutput(lines));
+        return;
+    }
+    let mut shown = Vec::new();
+    for word in argument.split_whitespace() {
+        match run_set(&mut global_data.options, word, buffer, scope) {
+            Ok(SetOutcome::Changed(name)) => send_cmd(client, Cmd::OptionChanged(name)),
+            Ok(SetOutcome::Shown(value)) => shown.push(value),
+            Err(err) => {
+                send_cmd(client, Cmd::Notify(NotifyLevel::Error, err));
+                return;
+            }
+        }
+    }
+    if !shown.is_empty() {
+        send_cmd(client, Cmd::Notify(NotifyLevel::Info, shown.join("  ")));
+    }
+}
+
+/// Parses a command line and sends on the ex command it names
+fn run_command_line(
+    global_data: &GlobalData,
+    client: ClientIndex,
+    text: &str,
+    send_cmd: &Box<Fn(ClientIndex, Cmd)>,
+) {
+    match parse_range(text, &range_context(global_data, client)) {
+        // A range on its own goes to its last line, like :12 or :$
+        Ok((Some(range), rest)) if rest.trim().is_empty() => {
+            send_cmd(client, Cmd::RememberJump);
+            send_cmd(
+                client,
+                Cmd::Jump(JumpType::Position(Point {
+                    x: 1,
+                    y: range.last as u16,
+                })),
+            );
+        }
+        Ok((None, rest)) if rest.trim().is_empty() => {}
+        Ok((range, rest)) => match parse_call(&global_data.ex_commands, rest, range) {
+            Ok(call) => send_cmd(client, Cmd::RunExCommand(call)),
+            Err(err) => send_cmd(client, Cmd::Notify(NotifyLevel::Error, err)),
+        },
+        Err(err) => send_cmd(client, Cmd::Notify(NotifyLevel::Error, err)),
+    }
+}
+
+// The commands registered above, the rest belong to other plugins
+fn run_command(
+    global_data: &mut GlobalData,
+    client: &ClientIndex,
+    call: &ExCall,
+    send_cmd: &Box<Fn(ClientIndex, Cmd)>,
+) {
+    match call.name.as_str() {
+        "write" => {
+            let options = WriteOptions {
+                create_directories: call.bang,
+                overwrite_changed: call.bang,
+                ..WriteOptions::default()
+            };
+            send_cmd(
+                *client,
+                Cmd::WriteBuffer(optional_path(global_data, *client, call), options),
+            );
+        }
+        "saveas" => send_cmd(
+            *client,
+            Cmd::WriteBuffer(
+                std::path::PathBuf::from(&call.argument),
+                WriteOptions {
+                    create_directories: call.bang,
+                    set_source: true,
+                    overwrite_changed: call.bang,
+                },
+            ),
+        ),
+        "edit" if call.bang && call.argument.is_empty() => send_cmd(*client, Cmd::ReloadBuffer),
+        "edit" => {
+            let path = optional_path(global_data, *client, call);
+            let current = &global_data.buffers[global_data.clients[*client].buffer()];
+            if current.is_modified() && current.source != path && !call.bang {
+                send_cmd(
+                    *client,
+                    Cmd::Notify(
+                        NotifyLevel::Warning,
+                        format!("{} has unsaved changes", current.display_name()),
+                    ),
+                );
+            }
+            send_cmd(*client, Cmd::LoadFile(path));
+        }
+        "ls" | "buffers" | "files" => {
+            send_cmd(*client, Cmd::ShowOutput(list_buffers(global_data, *client)));
+        }
+        "buffer" => {
+            if call.argument.is_empty() {
+                // Nothing to switch to, just report where we are
+                let buffer = &global_data.buffers[global_data.clients[*client].buffer()];
+                send_cmd(
+                    *client,
+                    Cmd::Notify(
+                        NotifyLevel::Info,
+                        format!("{} \"{}\"", buffer.number, buffer.display_name()),
+                    ),
+                );
+            } else {
+                match find_buffer(global_data, &call.argument) {
+                    Ok(index) => send_cmd(*client, Cmd::ShowBuffer(index)),
+                    Err(err) => send_cmd(*client, Cmd::Notify(NotifyLevel::Error, err)),
+                }
+            }
+        }
+        "bnext" => {
+            send_cmd(
+                *client,
+                Cmd::ShowBuffer(cycle_buffer(global_data, *client, true)),
+            );
+        }
+        "bprevious" => {
+            send_cmd(
+                *client,
+                Cmd::ShowBuffer(cycle_buffer(global_data, *client, false)),
+            );
+        }
+        // Deleted buffers aren't kept around unlisted, so :bd wipes them out as :bw does
+        "bdelete" | "bwipeout" => {
+            let target = if call.argument.is_empty() {
+                Ok(global_data.clients[*client].buffer())
+            } else {
+                find_buffer(global_data, &call.argument)
+            };
+            match target {
+                Ok(index) => send_cmd(*client, Cmd::DeleteBuffer(index, call.bang)),
+                Err(err) => send_cmd(*client, Cmd::Notify(NotifyLevel::Error, err)),
+            }
+        }
+        "keep" => send_cmd(*client, Cmd::KeepBuffer),
+        "diffdisk" => send_cmd(*client, Cmd::DiffWithDisk),
+        "quit" => close_or_quit(global_data, *client, call.bang, send_cmd),
+        "qall" => send_cmd(*client, Cmd::QuitAll(call.bang)),
+        "wall" => send_cmd(*client, Cmd::WriteAllBuffers),
+        "wqall" | "xall" => {
+            send_cmd(*client, Cmd::WriteAllBuffers);
+            send_cmd(*client, Cmd::QuitAll(false));
+        }
+        "split" | "vsplit" | "tabnew" => {
+            let split = match call.name.as_str() {
+                "split" => Cmd::SplitWindow(SplitDirection::Horizontal),
+                "vsplit" => Cmd::SplitWindow(SplitDirection::Vertical),
+                _ => Cmd::NewTab,
+            };
+            send_cmd(*client, split);
+            if !call.argument.is_empty() {
+                send_cmd(
+                    *client,
+                    Cmd::LoadFile(std::path::PathBuf::from(&call.argument)),
+                );
+            }
+        }
+        "tabnext" => send_cmd(*client, Cmd::NextTab),
+        "tabprevious" => send_cmd(*client, Cmd::PreviousTab),
+        "tabclose" => send_cmd(*client, Cmd::CloseTab),
+        "tabrename" => send_cmd(*client, Cmd::RenameTab(call.argument.clone())),
+        "close" => send_cmd(*client, Cmd::CloseWindow),
+        "resize" => {
+            if let Some(delta) = parse_resize_delta(&call.argument) {
+                send_cmd(
+                    *client,
+                    Cmd::ResizeWindow(SplitDirection::Horizontal, delta),
+                );
+            }
+        }
+        "vertical" => {
+            let mut words = call.argument.split_whitespace();
+            if let (Some("resize"), Some(delta)) =
+                (words.next(), words.next().and_then(parse_resize_delta))
+            {
+                send_cmd(*client, Cmd::ResizeWindow(SplitDirection::Vertical, delta));
+            }
+        }
+        "wq" => {
+            send_cmd(
+                *client,
+                Cmd::WriteBuffer(
+                    global_data.buffers[global_data.clients[*client].buffer()]
+                        .source
+                        .clone(),
+                    WriteOptions::default(),
+                ),
+            );
+            // Runs after the write so a failed write keeps the client open
+            close_or_quit(global_data, *client, false, send_cmd);
+        }
+        "kill" => match first_modified_buffer(global_data) {
+            Some(name) if !call.bang => send_cmd(*client, unsaved_changes_error(&name)),
+            _ => send_cmd(*client, Cmd::Kill),
+        },
+        "messages" => send_cmd(*client, Cmd::ShowMessages),
+        "set" | "setlocal" | "setglobal" => {
+            let scope = match call.name.as_str() {
+                "setlocal" => SetScope::Local,
+                "setglobal" => SetScope::Global,
+                _ => SetScope::Both,
+            };
+            set_options(global_data, *client, &call.argument, scope, send_cmd);
+        }
+        "substitute" => {
+            let (first, last) = call.range.unwrap_or_else(|| {
+                let line = global_data.clients[*client].window().cursor.position.y as usize;
+                (line, line)
+            });
+            match parse_substitute(&call.argument, LineRange { first, last }) {
+                Ok(substitution) => send_cmd(*client, Cmd::Substitute(substitution)),
+                Err(err) => send_cmd(*client, Cmd::Notify(NotifyLevel::Error, err)),
+            }
+        }
+        _ => {}
+    }
+}
+
 #[no_mangle]
 pub fn update(
     global_data: &mut GlobalData,
@@ -76,101 +657,110 @@ pub fn update(
     data_ptr: *mut c_void,
 ) {
     let mut data = unsafe { Box::from_raw(data_ptr as *mut Data) };
+    let text_before = data.command_buffer.text.clone();
     use Cmd::*;
     match msg {
         Msg::Cmd(client, cmd) => match cmd {
+            Cmd::RunCommand if data.search_backward.is_some() => {
+                send_cmd(*client, Cmd::RunSearch(data.command_buffer.text.clone()));
+                send_cmd(*client, Cmd::ChangeMode(Mode::Normal));
+            }
             Cmd::RunCommand => {
-                let mut command_words = data.command_buffer.text.split(" ");
-                match command_words.next() {
-                    Some("w") => {
-                        let path = command_words
-                            .next()
-                            .map(|file_path| std::path::PathBuf::from(file_path))
-                            .unwrap_or(
-                                global_data.buffers[global_data.clients[*client].buffer]
-                                    .source
-                                    .clone(),
-                            );
-                        send_cmd(*client, Cmd::WriteBuffer(path));
-                    }
-                    Some("e") => {
-                        let path = command_words
-                            .next()
-                            .map(|file_path| std::path::PathBuf::from(file_path))
-                            .unwrap_or(
-                                global_data.buffers[global_data.clients[*client].buffer]
-                                    .source
-                                    .clone(),
+                let text = data.command_buffer.text.trim().to_string();
+                if !text.is_empty() {
+                    add_to_history(&mut data.history, &text);
+                    if let Some(path) = &data.history_path {
+                        if let Err(err) = save_history(path, &data.history) {
+                            send_cmd(
+                                *client,
+                                Cmd::Notify(
+                                    NotifyLevel::Warning,
+                                    format!("Could not save command history: {}", err),
+                                ),
                             );
-                        send_cmd(*client, Cmd::LoadFile(path));
-                    }
-                    Some("q") => send_cmd(*client, Cmd::Quit),
-                    Some("wq") => {
-                        send_cmd(
-                            *client,
-                            Cmd::WriteBuffer(
-                                global_data.buffers[global_data.clients[*client].buffer]
-                                    .source
-                                    .clone(),
-                            ),
-                        );
-                        send_cmd(*client, Cmd::Quit);
-                    }
-                    Some("kill") => send_cmd(*client, Cmd::Kill),
-                    _ => {
-                        // Unknown command
+                        }
                     }
                 }
+                let text = data.command_buffer.text.clone();
+                run_command_line(global_data, *client, &text, send_cmd);
                 send_cmd(*client, Cmd::ChangeMode(Mode::Normal));
             }
+            Cmd::RunCommandLine(text) => run_command_line(global_data, *client, text, send_cmd),
+            Cmd::CompleteCommand if data.search_backward.is_none() => {
+                let text = data.command_buffer.text.clone();
+                // Tabbing again right after a completion moves on to the next candidate
+                let cycled = match data.completion.take() {
+                    Some(mut completion) if completion.text() == text => {
+                        completion.index = (completion.index + 1) % completion.candidates.len();
+                        Some(completion)
+                    }
+                    _ => complete_command(global_data, *client, &text),
+                };
+                if let Some(completion) = cycled {
+                    data.command_buffer.set(&completion.text());
+                    data.completion = Some(completion);
+                }
+            }
+            Cmd::RunExCommand(call) => run_command(global_data, client, call, send_cmd),
+            Cmd::QuitAll(force) => match first_modified_buffer(global_data) {
+                Some(name) if !force => send_cmd(*client, unsaved_changes_error(&name)),
+                _ => send_cmd(*client, Cmd::Quit),
+            },
+            // Cleared on the way out so that EditCommandLine can fill it in before entering
             Cmd::ChangeMode(mode) => {
-                if *mode == Mode::Command {
-                    data.command_buffer.text = "".into();
-                    data.command_buffer.index = 0;
+                if *mode != Mode::Command {
+                    data.command_buffer.clear();
+                    data.completion = None;
+                    data.history_browser.reset();
+                    data.search_backward = None;
                 }
             }
-            InsertChar(c) => match global_data.clients[*client].mode {
-                Mode::Command => {
-                    data.command_buffer
-                        .text
-                        .insert(data.command_buffer.index, *c);
-                    send_cmd(*client, MoveCursor(Direction::Right, false));
+            Cmd::StartSearch(backward) => {
+                data.search_backward = Some(*backward);
+                send_cmd(*client, Cmd::ChangeMode(Mode::Command));
+            }
+            Cmd::EditCommandLine(text) => {
+                data.command_buffer.set(text);
+                send_cmd(*client, Cmd::ChangeMode(Mode::Command));
+            }
+            Cmd::CommandLineEdit(edit) => data.command_buffer.edit(edit),
+            Cmd::CommandHistory(older) => {
+                let history = match data.search_backward {
+                    Some(_) => &global_data.search.history,
+                    None => &data.history,
+                };
+                let text = &data.command_buffer.text;
+                if let Some(entry) = data.history_browser.step(history, text, *older) {
+                    data.command_buffer.set(&entry);
                 }
-                _ => {}
-            },
-            DeleteChar(dir) => match global_data.clients[*client].mode {
-                Mode::Command => match dir {
-                    DeleteDirection::Before => {
-                        if data.command_buffer.index > 0 {
-                            data.command_buffer
-                                .text
-                                .remove(data.command_buffer.index - 1);
-                            send_cmd(*client, MoveCursor(Direction::Left, false));
-                        }
-                    }
-                    DeleteDirection::After => {}
-                },
-                _ => {}
+            }
+            Cmd::Pick(PickerSource::Commands) => {
+                let names = global_data.ex_commands.names_starting_with("");
+                let lines = names.iter().map(String::as_str);
+                send_cmd(
+                    *client,
+                    Cmd::OpenPicker(command_line_picker("Commands", lines)),
+                );
+            }
+            Cmd::Pick(PickerSource::History) => {
+                let lines = data.history.iter().rev().map(String::as_str);
+                send_cmd(
+                    *client,
+                    Cmd::OpenPicker(command_line_picker("History", lines)),
+                );
+            }
+            InsertChar(c) if global_data.clients[*client].mode == Mode::Command => {
+                data.command_buffer.insert(*c)
+            }
+            DeleteChar(dir) if global_data.clients[*client].mode == Mode::Command => match dir {
+                DeleteDirection::Before => data.command_buffer.delete_before(),
+                DeleteDirection::After => data.command_buffer.delete_after(),
             },
-            MoveCursor(dir, _selecting) => {
-                use Direction::*;
-                match global_data.clients[*client].mode {
-                    Mode::Command => {
-                        match dir {
-                            Left => {
-                                if data.command_buffer.index > 0 {
-                                    data.command_buffer.index -= 1;
-                                }
-                            }
-                            Right => {
-                                if data.command_buffer.index < data.command_buffer.text.len() {
-                                    data.command_buffer.index += 1;
-                                }
-                            }
-                            _ => {} // Only left and right matter
-                        }
-                    }
-                    _ => {}
+            MoveCursor(dir, _selecting) if global_data.clients[*client].mode == Mode::Command => {
+                match dir {
+                    Direction::Left => data.command_buffer.move_left(),
+                    Direction::Right => data.command_buffer.move_right(),
+                    _ => {} // Only left and right matter
                 }
             }
             _ => {}
@@ -178,12 +768,30 @@ pub fn update(
 
         _ => {}
     };
+    if let Msg::Cmd(client, cmd) = msg {
+        if data.command_buffer.text != text_before {
+            // Editing what a step through the history brought up starts a new search of it
+            if *cmd != Cmd::CommandHistory(true) && *cmd != Cmd::CommandHistory(false) {
+                data.history_browser.reset();
+            }
+            if data.search_backward.is_some() {
+                send_cmd(*client, Cmd::UpdateSearch(data.command_buffer.text.clone()));
+            }
+        }
+    }
     std::mem::forget(data);
 }
 
 #[no_mangle]
-pub fn init() -> *mut c_void {
-    unsafe { Box::into_raw(Box::new(Data::default())) as *mut c_void }
+pub fn init(global_data: &mut GlobalData) -> *mut c_void {
+    register_commands(&mut global_data.ex_commands);
+    let history_path = history_path();
+    let data = Data {
+        history: history_path.as_ref().map(load_history).unwrap_or_default(),
+        history_path,
+        ..Data::default()
+    };
+    Box::into_raw(Box::new(data)) as *mut c_void
 }
 
 #[no_mangle]
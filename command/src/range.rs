@@ -0,0 +1,149 @@
+use std::collections::HashMap;
+
+/// Lines an ex command works on, 0 based and inclusive
+#[derive(Debug, Clone, PartialEq)]
+pub struct LineRange {
+    pub first: usize,
+    pub last: usize,
+}
+
+/// What the addresses in a range can refer to
+#[derive(Debug, Default)]
+pub struct RangeContext {
+    pub current_line: usize,
+    pub last_line: usize,
+    /// First and last line of the selection, for `'<` and `'>`
+    pub selection: Option<(usize, usize)>,
+    /// Line of each mark set in the buffer, `'<` and `'>` are only used without a selection
+    pub marks: HashMap<char, usize>,
+}
+
+fn split_number(text: &str) -> (Option<i64>, &str) {
+    let digits = text
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or_else(|| text.len());
+    (text[..digits].parse().ok(), &text[digits..])
+}
+
+// One address such as `12`, `.`, `$`, `'<`, `'a` or `.+2`, as a 0 based line
+fn parse_address<'a>(
+    text: &'a str,
+    context: &RangeContext,
+) -> Result<Option<(i64, &'a str)>, String> {
+    let mut chars = text.chars();
+    let (mut line, mut rest) = match chars.next() {
+        Some('.') => (context.current_line as i64, &text[1..]),
+        Some('$') => (context.last_line as i64, &text[1..]),
+        Some('\'') => {
+            let line = match (chars.next(), context.selection) {
+                (Some('<'), Some((first, _))) => first,
+                (Some('>'), Some((_, last))) => last,
+                (Some(mark), _) if context.marks.contains_key(&mark) => context.marks[&mark],
+                (Some(mark), _) => return Err(format!("Mark not set: '{}", mark)),
+                (None, _) => return Err("Missing mark name".into()),
+            };
+            (line as i64, &text[2..])
+        }
+        Some(c) if c.is_ascii_digit() => {
+            let (number, rest) = split_number(text);
+            // Line 0 is allowed and means the first line, as in vim
+            (std::cmp::max(number.unwrap_or(1) - 1, 0), rest)
+        }
+        // A bare offset is relative to the cursor line
+        Some('+') | Some('-') => (context.current_line as i64, text),
+        _ => return Ok(None),
+    };
+    while rest.starts_with('+') || rest.starts_with('-') {
+        let sign = if rest.starts_with('-') { -1 } else { 1 };
+        let (number, after) = split_number(&rest[1..]);
+        line += sign * number.unwrap_or(1);
+        rest = after;
+    }
+    Ok(Some((line, rest)))
+}
+
+/// Splits a leading range such as `%`, `3,$` or `'<,'>` off `text`. Returns
+/// no range if `text` doesn't start with one.
+pub fn parse_range<'a>(
+    text: &'a str,
+    context: &RangeContext,
+) -> Result<(Option<LineRange>, &'a str), String> {
+    let text = text.trim_start();
+    if text.starts_with('%') {
+        let range = LineRange {
+            first: 0,
+            last: context.last_line,
+        };
+        return Ok((Some(range), &text[1..]));
+    }
+    let (first, rest) = match parse_address(text, context)? {
+        Some(address) => address,
+        None => return Ok((None, text)),
+    };
+    let (last, rest) = if rest.starts_with(',') {
+        parse_address(&rest[1..], context)?.ok_or("Missing end of range")?
+    } else {
+        (first, rest)
+    };
+    // Vim asks whether to swap a backwards range, we always do
+    let (first, last) = (std::cmp::min(first, last), std::cmp::max(first, last));
+    if first < 0 || last > context.last_line as i64 {
+        return Err("Invalid range".into());
+    }
+    let range = LineRange {
+        first: first as usize,
+        last: last as usize,
+    };
+    Ok((Some(range), rest))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn context() -> RangeContext {
+        RangeContext {
+            current_line: 4,
+            last_line: 9,
+            selection: Some((2, 3)),
+            marks: vec![('a', 1), ('B', 7), ('<', 0)].into_iter().collect(),
+        }
+    }
+
+    fn range(first: usize, last: usize) -> Option<LineRange> {
+        Some(LineRange { first, last })
+    }
+
+    #[test]
+    fn test_parse_range() {
+        let context = context();
+        assert_eq!(
+            parse_range("%s/a/b/", &context),
+            Ok((range(0, 9), "s/a/b/"))
+        );
+        assert_eq!(parse_range("3,$d", &context), Ok((range(2, 9), "d")));
+        assert_eq!(parse_range(".,.+2s", &context), Ok((range(4, 6), "s")));
+        assert_eq!(parse_range("'<,'>s", &context), Ok((range(2, 3), "s")));
+        assert_eq!(parse_range("'a,'B-1d", &context), Ok((range(1, 6), "d")));
+        assert_eq!(parse_range("-1s", &context), Ok((range(3, 3), "s")));
+        assert_eq!(parse_range("$,1", &context), Ok((range(0, 9), "")));
+        assert_eq!(parse_range("w file", &context), Ok((None, "w file")));
+    }
+
+    #[test]
+    fn test_parse_range_errors() {
+        let context = context();
+        assert_eq!(
+            parse_range("'a,'bs", &context),
+            Err("Mark not set: 'b".to_string())
+        );
+        assert_eq!(
+            parse_range("5,20s", &context),
+            Err("Invalid range".to_string())
+        );
+        assert_eq!(
+            parse_range("1-5", &context),
+            Err("Invalid range".to_string())
+        );
+    }
+}
@@ -0,0 +1,144 @@
+use types::{BufferIndex, OptionSpec, OptionValue, Options};
+
+/// Which values `:set`, `:setlocal` and `:setglobal` work on
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SetScope {
+    /// The buffer's own value and the global one, like vim's `:set`
+    Both,
+    Local,
+    Global,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum SetOutcome {
+    /// The option's full name, so plugins can be told
+    Changed(String),
+    /// A value to show the user
+    Shown(String),
+}
+
+/// How `:set` shows a value, `number` or `nonumber` for bools
+pub fn describe(spec: &OptionSpec, value: &OptionValue) -> String {
+    match value {
+        OptionValue::Bool(true) => spec.name.clone(),
+        OptionValue::Bool(false) => format!("no{}", spec.name),
+        other => format!("{}={}", spec.name, other),
+    }
+}
+
+fn assign(
+    options: &mut Options,
+    name: &str,
+    value: OptionValue,
+    buffer: BufferIndex,
+    scope: SetScope,
+) -> Result<SetOutcome, String> {
+    if scope != SetScope::Local {
+        options.set(name, value.clone(), None)?;
+    }
+    if scope != SetScope::Global {
+        options.set(name, value, Some(buffer))?;
+    }
+    Ok(SetOutcome::Changed(name.into()))
+}
+
+/// Carries out one word of a `:set` command: `opt`, `noopt`, `opt!`, `opt?` or `opt=value`
+pub fn run_set(
+    options: &mut Options,
+    word: &str,
+    buffer: BufferIndex,
+    scope: SetScope,
+) -> Result<SetOutcome, String> {
+    let (name, value) = match word.find('=') {
+        Some(equals) => (&word[..equals], Some(&word[equals + 1..])),
+        None => (word.trim_end_matches(|c| c == '?' || c == '!'), None),
+    };
+    // Disabling a bool uses its name with no in front, unless that's an option's name itself
+    let (spec, disable) = match (options.find(name), name.starts_with("no")) {
+        (Some(spec), _) => (spec.clone(), false),
+        (None, true) => match options.find(&name[2..]) {
+            Some(spec) => (spec.clone(), true),
+            None => return Err(format!("Unknown option: {}", name)),
+        },
+        (None, false) => return Err(format!("Unknown option: {}", name)),
+    };
+    let shown_buffer = if scope == SetScope::Global {
+        None
+    } else {
+        Some(buffer)
+    };
+    let current = options
+        .get(&spec.name, shown_buffer)
+        .cloned()
+        .unwrap_or_else(|| spec.default.clone());
+    let is_bool = match current {
+        OptionValue::Bool(_) => true,
+        _ => false,
+    };
+    if let Some(text) = value {
+        let value = spec.parse(text)?;
+        return assign(options, &spec.name, value, buffer, scope);
+    }
+    if word.ends_with('?') || (!is_bool && !disable) {
+        return Ok(SetOutcome::Shown(describe(&spec, &current)));
+    }
+    let value = match (current, word.ends_with('!'), disable) {
+        (OptionValue::Bool(current), true, false) => !current,
+        (OptionValue::Bool(_), false, disable) => !disable,
+        _ => return Err(format!("Invalid argument: {}", word)),
+    };
+    assign(options, &spec.name, OptionValue::Bool(value), buffer, scope)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use types::{OptionScope, SlotMap};
+
+    fn options() -> (Options, BufferIndex, BufferIndex) {
+        let mut options = Options::default();
+        options.declare(OptionSpec {
+            abbreviation: Some("nu".into()),
+            ..OptionSpec::new("number", OptionScope::Buffer, OptionValue::Bool(true))
+        });
+        options.declare(OptionSpec::new(
+            "sidescrolloff",
+            OptionScope::Global,
+            OptionValue::Int(5),
+        ));
+        let mut buffers: SlotMap<BufferIndex, ()> = SlotMap::new();
+        (options, buffers.insert(()), buffers.insert(()))
+    }
+
+    #[test]
+    fn test_set_bools() {
+        let (mut options, buffer, other) = options();
+        assert_eq!(
+            run_set(&mut options, "nonu", buffer, SetScope::Local),
+            Ok(SetOutcome::Changed("number".into()))
+        );
+        assert_eq!(options.get_bool("number", Some(buffer)), Some(false));
+        assert_eq!(options.get_bool("number", Some(other)), Some(true));
+        run_set(&mut options, "number!", other, SetScope::Both).unwrap();
+        assert_eq!(options.get_bool("number", Some(other)), Some(false));
+        assert_eq!(options.get_bool("number", None), Some(false));
+        assert_eq!(
+            run_set(&mut options, "nu?", buffer, SetScope::Both),
+            Ok(SetOutcome::Shown("nonumber".into()))
+        );
+    }
+
+    #[test]
+    fn test_set_values() {
+        let (mut options, buffer, _) = options();
+        run_set(&mut options, "sidescrolloff=8", buffer, SetScope::Both).unwrap();
+        assert_eq!(options.get_int("sidescrolloff", None), Some(8));
+        assert_eq!(
+            run_set(&mut options, "sidescrolloff", buffer, SetScope::Both),
+            Ok(SetOutcome::Shown("sidescrolloff=8".into()))
+        );
+        assert!(run_set(&mut options, "sidescrolloff=far", buffer, SetScope::Both).is_err());
+        assert!(run_set(&mut options, "nosidescrolloff", buffer, SetScope::Both).is_err());
+        assert!(run_set(&mut options, "wrap", buffer, SetScope::Both).is_err());
+    }
+}
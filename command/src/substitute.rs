@@ -0,0 +1,94 @@
+use crate::range::LineRange;
+use types::Substitution;
+
+// Splits on `delimiter` unless it's escaped, in which case the backslash goes
+fn split_unescaped(text: &str, delimiter: char) -> Vec<String> {
+    let mut parts = vec![String::new()];
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        if c == '\\' && chars.peek() == Some(&delimiter) {
+            parts.last_mut().unwrap().push(delimiter);
+            chars.next();
+        } else if c == '\\' {
+            // Other escapes are left for the regex and the replacement to handle
+            parts.last_mut().unwrap().push(c);
+            if let Some(escaped) = chars.next() {
+                parts.last_mut().unwrap().push(escaped);
+            }
+        } else if c == delimiter {
+            parts.push(String::new());
+        } else {
+            parts.last_mut().unwrap().push(c);
+        }
+    }
+    parts
+}
+
+/// Parses what follows `:s`, `/pattern/replacement/flags` where the delimiter
+/// can be any punctuation
+pub fn parse_substitute(argument: &str, range: LineRange) -> Result<Substitution, String> {
+    let delimiter = match argument.chars().next() {
+        Some(c) if c.is_ascii_punctuation() && c != '\\' && c != '"' && c != '|' => c,
+        // Plain :s would repeat the last substitution in vim
+        None => return Err("Argument required".into()),
+        Some(c) => return Err(format!("Invalid delimiter: {}", c)),
+    };
+    let mut parts = split_unescaped(&argument[delimiter.len_utf8()..], delimiter).into_iter();
+    let pattern = parts.next().unwrap_or_default();
+    let replacement = parts.next().unwrap_or_default();
+    let flags = parts.next().unwrap_or_default();
+    if let Some(extra) = parts.next() {
+        return Err(format!("Trailing characters: {}", extra));
+    }
+    let mut substitution = Substitution {
+        pattern,
+        replacement,
+        first_line: range.first,
+        last_line: range.last,
+        global: false,
+        confirm: false,
+        ignore_case: None,
+    };
+    for flag in flags.trim_end().chars() {
+        match flag {
+            'g' => substitution.global = true,
+            'c' => substitution.confirm = true,
+            'i' => substitution.ignore_case = Some(true),
+            'I' => substitution.ignore_case = Some(false),
+            other => return Err(format!("Unknown flag: {}", other)),
+        }
+    }
+    Ok(substitution)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn line(line: usize) -> LineRange {
+        LineRange {
+            first: line,
+            last: line,
+        }
+    }
+
+    #[test]
+    fn test_parse_substitute() {
+        let substitution = parse_substitute(r"/a\/b/c d/gc", line(3)).unwrap();
+        assert_eq!(substitution.pattern, "a/b");
+        assert_eq!(substitution.replacement, "c d");
+        assert_eq!((substitution.first_line, substitution.last_line), (3, 3));
+        assert!(substitution.global && substitution.confirm);
+        let substitution = parse_substitute(r"#(\w+)#\1#I", line(0)).unwrap();
+        assert_eq!(substitution.pattern, r"(\w+)");
+        assert_eq!(substitution.replacement, r"\1");
+        assert_eq!(substitution.ignore_case, Some(false));
+    }
+
+    #[test]
+    fn test_parse_substitute_errors() {
+        assert!(parse_substitute("/a/b/x", line(0)).is_err());
+        assert!(parse_substitute("", line(0)).is_err());
+        assert!(parse_substitute("xaxbx", line(0)).is_err());
+    }
+}
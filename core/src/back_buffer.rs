@@ -1,7 +1,7 @@
 use log::warn;
 use ropey::RopeSlice;
 use std::io::Write;
-use types::{BackBuffer, Cell, Color, Point, Rect, Style};
+use types::{BackBuffer, Cell, Color, GutterLayout, Point, Rect, Style, Theme};
 
 pub fn index_from_point(back_buffer: &BackBuffer, p: &Point) -> usize {
     (p.y * back_buffer.dim.w + p.x) as usize
@@ -47,10 +47,19 @@ pub fn style_range(
     }
 }
 
+pub fn clear_range(back_buffer: &mut BackBuffer, start_point: &Point, length: usize) {
+    let index = index_from_point(back_buffer, start_point);
+    let end = std::cmp::min(index + length, back_buffer.cells.len());
+    for cell in back_buffer.cells[index..end].iter_mut() {
+        *cell = back_buffer.blank.clone();
+    }
+}
+
 pub fn style_rope_slice_range(
     back_buffer: &mut BackBuffer,
     rope_slice: &RopeSlice,
     mut position: Point,
+    gutter: &GutterLayout,
     style: Option<Style>,
     fg: Option<Color>,
     bg: Option<Color>,
@@ -64,7 +73,7 @@ pub fn style_rope_slice_range(
             fg.clone(),
             bg.clone(),
         );
-        position.x = 4;
+        position.x = gutter.width;
         position.y += 1;
     }
 }
@@ -118,6 +127,13 @@ pub fn update_stdout(old_buffer: &BackBuffer, new_buffer: &BackBuffer, out: impl
             if let Some(ref bg) = new_cell.bg {
                 write!(writer, "\x1b[48;2;{};{};{}m", bg.r, bg.g, bg.b).unwrap();
             }
+            match new_cell.style {
+                Some(Style::Bold) => write!(writer, "{}", termion::style::Bold),
+                Some(Style::Underlined) => write!(writer, "{}", termion::style::Underline),
+                Some(Style::Highlighted) => write!(writer, "{}", termion::style::Invert),
+                None => Ok(()),
+            }
+            .unwrap();
             if let Some(c) = new_cell.value {
                 write!(writer, "{}", c).unwrap();
             } else {
@@ -134,13 +150,21 @@ pub fn update_stdout(old_buffer: &BackBuffer, new_buffer: &BackBuffer, out: impl
     writer.flush().unwrap();
 }
 
-pub fn create_back_buffer(size: Rect) -> BackBuffer {
+pub fn create_back_buffer(size: Rect, theme: &Theme) -> BackBuffer {
     // TODO: Figure out the client size
     let Rect { w, h } = size;
     let total_cell_count = w * h;
-    let mut cells = Vec::with_capacity(total_cell_count as usize);
-    for _ in 0..total_cell_count {
-        cells.push(Cell::default());
+    let text = theme.get("ui.text");
+    let blank = Cell {
+        value: None,
+        fg: text.fg,
+        bg: text.bg,
+        style: None,
+    };
+    let cells = vec![blank.clone(); total_cell_count as usize];
+    BackBuffer {
+        cells,
+        dim: size,
+        blank,
     }
-    BackBuffer { cells, dim: size }
 }
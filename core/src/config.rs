@@ -0,0 +1,423 @@
+use log::LevelFilter;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use types::{Keymaps, Options, StatusLineConfig, StatusSegment};
+
+/// What `~/.config/myedit/config.toml` and a project's `.myedit.toml` can hold.
+/// Anything left out keeps its default. Only the user's file can choose the
+/// plugins, the log and backups, see `for_project`.
+#[derive(Debug, Default, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    /// Where the plugin libraries are loaded from
+    pub plugin_dir: Option<PathBuf>,
+    /// Plugins to load by name, `buffer_manager` for `libbuffer_manager.dylib`.
+    /// Every library in the plugin directory is loaded when unset.
+    pub plugins: Option<Vec<String>>,
+    pub log_file: Option<PathBuf>,
+    /// `off`, `error`, `warn`, `info`, `debug` or `trace`
+    pub log_level: Option<String>,
+    /// A built in theme or one in the `themes` directory next to the user's config
+    pub theme: Option<String>,
+    /// Starting values for options by name, same as `:set` takes them
+    pub options: HashMap<String, toml::Value>,
+    pub keymaps: Keymaps,
+    pub status_line: StatusLineSection,
+}
+
+/// `[status_line]`, segment names for each side such as `mode` or `position`,
+/// or `text:` followed by text to show as it is. A side left out keeps its default.
+#[derive(Debug, Default, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct StatusLineSection {
+    pub left: Option<Vec<String>>,
+    pub right: Option<Vec<String>>,
+}
+
+// Options that pick where files get written, by full name and abbreviation
+const PATH_OPTIONS: [&str; 4] = ["backup", "bk", "backupdir", "bdir"];
+
+fn parse_segments(names: &[String]) -> Result<Vec<StatusSegment>, String> {
+    names
+        .iter()
+        .map(|name| StatusSegment::parse(name))
+        .collect()
+}
+
+impl Config {
+    pub fn plugin_dir(&self) -> PathBuf {
+        self.plugin_dir
+            .clone()
+            .unwrap_or_else(|| PathBuf::from("./target/release"))
+    }
+
+    pub fn log_file(&self) -> PathBuf {
+        self.log_file
+            .clone()
+            .unwrap_or_else(|| PathBuf::from("output.log"))
+    }
+
+    pub fn log_level(&self) -> Result<LevelFilter, String> {
+        match &self.log_level {
+            Some(level) => level
+                .parse()
+                .map_err(|_| format!("Invalid log level: {}", level)),
+            None => Ok(LevelFilter::Info),
+        }
+    }
+
+    /// Whether the library at `path` is one of the plugins to load
+    pub fn enables(&self, path: &Path) -> bool {
+        let plugins = match &self.plugins {
+            Some(plugins) => plugins,
+            None => return true,
+        };
+        let stem = path
+            .file_stem()
+            .and_then(|stem| stem.to_str())
+            .unwrap_or("");
+        let name = if stem.starts_with("lib") {
+            &stem[3..]
+        } else {
+            stem
+        };
+        // Crate names use dashes where the library names have underscores
+        plugins
+            .iter()
+            .any(|plugin| plugin.replace('-', "_") == name)
+    }
+
+    /// The status line layout, the default one where the config is invalid
+    pub fn status_line(&self) -> Result<StatusLineConfig, String> {
+        let mut layout = StatusLineConfig::default();
+        if let Some(left) = &self.status_line.left {
+            layout.left = parse_segments(left)?;
+        }
+        if let Some(right) = &self.status_line.right {
+            layout.right = parse_segments(right)?;
+        }
+        Ok(layout)
+    }
+
+    /// What a project's config may set, leaving out and reporting the rest. A
+    /// checkout could otherwise load libraries or write files of its choosing.
+    pub fn for_project(mut self) -> (Config, Vec<String>) {
+        let path_options: Vec<String> = self
+            .options
+            .keys()
+            .filter(|name| PATH_OPTIONS.contains(&name.as_str()))
+            .cloned()
+            .collect();
+        for name in &path_options {
+            self.options.remove(name);
+        }
+        let restricted = [
+            ("plugin_dir", self.plugin_dir.is_some()),
+            ("plugins", self.plugins.is_some()),
+            ("log_file", self.log_file.is_some()),
+            ("log_level", self.log_level.is_some()),
+        ];
+        let errors = restricted
+            .iter()
+            .filter(|(_, set)| *set)
+            .map(|(name, _)| *name)
+            .chain(path_options.iter().map(String::as_str))
+            .map(|name| format!("{} can only be set in the user's config", name))
+            .collect();
+        let config = Config {
+            theme: self.theme,
+            options: self.options,
+            keymaps: self.keymaps,
+            status_line: self.status_line,
+            ..Config::default()
+        };
+        (config, errors)
+    }
+
+    /// This config with a project's laid over it, which only brings options,
+    /// keymaps, the theme and the status line layout
+    pub fn merge(mut self, project: Config) -> Config {
+        self.options.extend(project.options);
+        self.keymaps.extend(project.keymaps);
+        Config {
+            theme: project.theme.or(self.theme),
+            status_line: StatusLineSection {
+                left: project.status_line.left.or(self.status_line.left),
+                right: project.status_line.right.or(self.status_line.right),
+            },
+            ..self
+        }
+    }
+
+    /// Sets the option defaults, the theme being one of them. Gives back the full
+    /// names of the declared options that were set, and what couldn't be.
+    pub fn apply_options(&self, options: &mut Options) -> (Vec<String>, Vec<String>) {
+        let mut values: Vec<(&str, String)> = Vec::new();
+        let mut errors = Vec::new();
+        for (name, value) in &self.options {
+            match value {
+                toml::Value::String(text) => values.push((name, text.clone())),
+                toml::Value::Boolean(_) | toml::Value::Integer(_) => {
+                    values.push((name, value.to_string()))
+                }
+                _ => errors.push(format!("Invalid value for {}: {}", name, value)),
+            }
+        }
+        if let Some(theme) = &self.theme {
+            values.push(("theme", theme.clone()));
+        }
+        let mut set = Vec::new();
+        for (name, text) in values {
+            match options.set_default(name, &text) {
+                Ok(()) => set.extend(options.find(name).map(|spec| spec.name.clone())),
+                Err(err) => errors.push(err),
+            }
+        }
+        (set, errors)
+    }
+
+    /// Puts the options this config set back to their defaults, so that ones a
+    /// reloaded config no longer sets don't keep its values. Gives back their names.
+    pub fn reset_options(&self, options: &mut Options) -> Vec<String> {
+        let names = self
+            .options
+            .keys()
+            .map(String::as_str)
+            .chain(self.theme.as_ref().map(|_| "theme"));
+        let mut reset: Vec<String> = names
+            .filter_map(|name| options.reset_default(name))
+            .collect();
+        reset.sort();
+        reset
+    }
+}
+
+/// Where the user's config file and themes are, under the XDG config directory
+pub fn config_dir() -> Option<PathBuf> {
+    let config_directory = std::env::var_os("XDG_CONFIG_HOME")
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))?;
+    Some(config_directory.join("myedit"))
+}
+
+pub fn user_config_path() -> Option<PathBuf> {
+    config_dir().map(|directory| directory.join("config.toml"))
+}
+
+/// The user's config file and the project's, which can set less
+#[derive(Debug, Clone, Default)]
+pub struct ConfigPaths {
+    pub user: Option<PathBuf>,
+    pub project: Option<PathBuf>,
+}
+
+impl ConfigPaths {
+    pub fn find() -> ConfigPaths {
+        ConfigPaths {
+            user: user_config_path(),
+            project: project_config_path(),
+        }
+    }
+
+    /// Both files, in the order they apply
+    pub fn all(&self) -> Vec<PathBuf> {
+        self.user.iter().chain(&self.project).cloned().collect()
+    }
+}
+
+/// The closest `.myedit.toml` from the current directory up, or where one would
+/// go in the current directory so that creating it gets noticed
+pub fn project_config_path() -> Option<PathBuf> {
+    let current = std::env::current_dir().ok()?;
+    current
+        .ancestors()
+        .map(|directory| directory.join(".myedit.toml"))
+        .find(|path| path.is_file())
+        .or_else(|| Some(current.join(".myedit.toml")))
+}
+
+// A missing file reads as no config at all
+fn read(path: &Path) -> Result<Option<Config>, String> {
+    match std::fs::read_to_string(path) {
+        Ok(contents) => toml::from_str(&contents)
+            .map(Some)
+            .map_err(|err| format!("{}: {}", path.display(), err)),
+        Err(ref err) if err.kind() == std::io::ErrorKind::NotFound => Ok(None),
+        Err(err) => Err(format!("{}: {}", path.display(), err)),
+    }
+}
+
+/// Reads and merges the config files. Missing files are skipped, as are ones
+/// that can't be read, which are reported.
+pub fn load(paths: &ConfigPaths) -> (Config, Vec<String>) {
+    let mut errors = Vec::new();
+    let mut config = match paths.user.as_ref().map(|path| read(path)) {
+        Some(Ok(Some(config))) => config,
+        Some(Err(err)) => {
+            errors.push(err);
+            Config::default()
+        }
+        _ => Config::default(),
+    };
+    if let Some(path) = &paths.project {
+        match read(path) {
+            Ok(Some(project)) => {
+                let (project, project_errors) = project.for_project();
+                errors.extend(
+                    project_errors
+                        .into_iter()
+                        .map(|err| format!("{}: {}", path.display(), err)),
+                );
+                config = config.merge(project);
+            }
+            Ok(None) => {}
+            Err(err) => errors.push(err),
+        }
+    }
+    (config, errors)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use types::{OptionScope, OptionSpec, OptionValue};
+
+    #[test]
+    fn test_project_config_wins() {
+        let user: Config = toml::from_str(
+            r#"
+            plugin_dir = "/opt/myedit/plugins"
+            log_level = "warn"
+            [options]
+            number = false
+            sidescrolloff = 8
+            [keymaps.normal]
+            "<C-s>" = "w"
+            "#,
+        )
+        .unwrap();
+        let project: Config = toml::from_str(
+            r#"
+            theme = "light"
+            [options]
+            sidescrolloff = 2
+            [keymaps.normal]
+            Q = "qa"
+            "#,
+        )
+        .unwrap();
+        let config = user.merge(project);
+        assert_eq!(config.plugin_dir(), PathBuf::from("/opt/myedit/plugins"));
+        assert_eq!(config.log_level(), Ok(LevelFilter::Warn));
+        assert_eq!(config.theme, Some("light".into()));
+        assert_eq!(config.options["sidescrolloff"], toml::Value::Integer(2));
+        assert_eq!(config.options["number"], toml::Value::Boolean(false));
+        assert_eq!(config.keymaps.normal.len(), 2);
+    }
+
+    #[test]
+    fn test_project_cannot_choose_plugins_or_log() {
+        let project: Config = toml::from_str(
+            r#"
+            plugin_dir = "./evil"
+            plugins = ["input", "buffer-manager"]
+            log_file = "/home/user/.bashrc"
+            [options]
+            sidescrolloff = 2
+            bk = true
+            backupdir = "/home/user/.ssh"
+            "#,
+        )
+        .unwrap();
+        let (project, errors) = project.for_project();
+        assert_eq!(errors.len(), 5);
+        assert!(errors.contains(&"backupdir can only be set in the user's config".to_string()));
+        assert_eq!(project.options.len(), 1);
+        assert_eq!(project.options["sidescrolloff"], toml::Value::Integer(2));
+        let config = Config::default().merge(project);
+        assert_eq!(config.plugin_dir(), PathBuf::from("./target/release"));
+        assert_eq!(config.log_file(), PathBuf::from("output.log"));
+        assert!(config.enables(Path::new("./target/release/libsearch.dylib")));
+        let user: Config = toml::from_str("plugins = [\"buffer-manager\"]").unwrap();
+        assert!(user.enables(Path::new("./target/release/libbuffer_manager.dylib")));
+        assert!(!user.enables(Path::new("./target/release/libsearch.dylib")));
+    }
+
+    #[test]
+    fn test_apply_options() {
+        let config: Config = toml::from_str(
+            r#"
+            theme = "light"
+            [options]
+            nu = false
+            sidescrolloff = "far"
+            "#,
+        )
+        .unwrap();
+        let mut options = Options::default();
+        options.declare(OptionSpec {
+            abbreviation: Some("nu".into()),
+            ..OptionSpec::new("number", OptionScope::Buffer, OptionValue::Bool(true))
+        });
+        options.declare(OptionSpec::new(
+            "sidescrolloff",
+            OptionScope::Global,
+            OptionValue::Int(5),
+        ));
+        let (set, errors) = config.apply_options(&mut options);
+        assert_eq!(set, vec!["number".to_string()]);
+        assert_eq!(errors.len(), 1);
+        assert_eq!(options.get_bool("number", None), Some(false));
+        options.declare(OptionSpec::new(
+            "theme",
+            OptionScope::Global,
+            OptionValue::String("dark".into()),
+        ));
+        assert_eq!(options.get_str("theme", None), Some("light"));
+        // Reloading without them puts the defaults back
+        assert_eq!(
+            config.reset_options(&mut options),
+            vec!["number", "sidescrolloff", "theme"]
+        );
+        assert_eq!(options.get_bool("number", None), Some(true));
+        assert_eq!(options.get_str("theme", None), Some("dark"));
+    }
+
+    #[test]
+    fn test_status_line() {
+        let config: Config = toml::from_str(
+            r#"
+            [status_line]
+            left = ["mode", "text:|", "file_path"]
+            "#,
+        )
+        .unwrap();
+        let layout = config.status_line().unwrap();
+        assert_eq!(
+            layout.left,
+            vec![
+                StatusSegment::Mode,
+                StatusSegment::Text("|".into()),
+                StatusSegment::FilePath
+            ]
+        );
+        assert_eq!(layout.right, StatusLineConfig::default().right);
+        // A project can lay out one side and keep the user's other side
+        let (project, errors) =
+            toml::from_str::<Config>("status_line = { right = [\"position\"] }")
+                .unwrap()
+                .for_project();
+        assert!(errors.is_empty());
+        let layout = config.merge(project).status_line().unwrap();
+        assert_eq!(layout.left[0], StatusSegment::Mode);
+        assert_eq!(layout.right, vec![StatusSegment::Position]);
+        let config: Config = toml::from_str("status_line = { right = [\"cursor\"] }").unwrap();
+        assert!(config.status_line().is_err());
+    }
+
+    #[test]
+    fn test_unknown_keys_are_errors() {
+        assert!(toml::from_str::<Config>("plugin_directory = \"plugins\"").is_err());
+    }
+}
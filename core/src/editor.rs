@@ -2,11 +2,11 @@ use crossbeam_channel::{unbounded, Sender};
 use libloading::os::unix::Symbol;
 use notify::{watcher, DebouncedEvent, RecommendedWatcher, RecursiveMode, Watcher};
 
-use log::{info, LevelFilter};
+use log::{info, warn};
 use log4rs::append::file::FileAppender;
-use log4rs::config::{Appender, Config, Root};
+use log4rs::config::{Appender, Root};
 use log4rs::encode::pattern::PatternEncoder;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::default::Default;
 use std::ffi::c_void;
 use std::io::Write;
@@ -14,16 +14,20 @@ use std::os::unix::net::{UnixListener, UnixStream};
 use std::{fs, path, time};
 
 use types::{
-    BackBuffer, Client, ClientIndex, Cmd, GlobalData, InitializeClient, Mode, Msg, Rect,
-    RemoteCommand, Utils,
+    ArgKind, Args, BackBuffer, ClientIndex, Cmd, ExCommand, GlobalData, InitializeClient,
+    LineNumbers, Msg, NotifyLevel, OptionScope, OptionSpec, OptionValue, Rect, RemoteCommand,
+    Theme, Utils,
 };
 
 use crate::back_buffer;
+use crate::config::{self, Config, ConfigPaths};
+use crate::theme;
 use crate::utils;
 
 #[derive(Debug)]
 struct DynLib {
     lib: libloading::Library,
+    path: path::PathBuf,
     render_fn:
         Symbol<extern "C" fn(&GlobalData, &ClientIndex, &mut BackBuffer, &Utils, *mut c_void)>,
     update_fn: Symbol<
@@ -31,6 +35,8 @@ struct DynLib {
     >,
     cleanup_fn: Symbol<extern "C" fn(*mut c_void)>,
     data: *mut c_void,
+    /// Libs are rendered from the lowest layer up so overlays end up on top
+    layer: i32,
 }
 
 impl Drop for DynLib {
@@ -39,7 +45,7 @@ impl Drop for DynLib {
     }
 }
 
-fn load_lib(path: &path::PathBuf, global_data: &GlobalData) -> DynLib {
+fn load_lib(path: &path::PathBuf, global_data: &mut GlobalData) -> DynLib {
     let file_name = path.file_name().expect("getting lib name");
     let copy_path: path::PathBuf = [
         "./lib_copies",
@@ -56,57 +62,146 @@ fn load_lib(path: &path::PathBuf, global_data: &GlobalData) -> DynLib {
         let update_fn: libloading::Symbol<
             extern "C" fn(&mut GlobalData, &Msg, &Utils, &Box<Fn(ClientIndex, Cmd)>, *mut c_void),
         > = lib.get(b"update").expect("loading update function");
-        let init_fn: libloading::Symbol<extern "C" fn(&GlobalData) -> *mut c_void> =
+        // Gets to change the global data so that it can register ex commands and the like
+        let init_fn: libloading::Symbol<extern "C" fn(&mut GlobalData) -> *mut c_void> =
             lib.get(b"init").expect("loading init function");
         let cleanup_fn: libloading::Symbol<extern "C" fn(*mut c_void)> =
             lib.get(b"cleanup").expect("loading cleanup function");
+        // Optional, most libs draw straight onto the windows
+        let layer = lib
+            .get::<extern "C" fn() -> i32>(b"layer")
+            .map(|layer_fn| layer_fn())
+            .unwrap_or(0);
         let data = init_fn(global_data);
         DynLib {
             render_fn: render_fn.into_raw(),
             update_fn: update_fn.into_raw(),
             cleanup_fn: cleanup_fn.into_raw(),
             data,
+            layer,
             lib,
+            path: path.clone(),
         }
     }
 }
 
-// #[cfg(debug_assertion)]
-// const LIB_LOC: &'static str = "./target/debug";
-const LIB_LOC: &'static str = "./target/release";
-// #[cfg(not(debug_assertion))]
+// The enabled libraries in the plugin directory
+fn lib_paths(config: &Config) -> Result<Vec<path::PathBuf>, String> {
+    let plugin_dir = config.plugin_dir();
+    let entries = fs::read_dir(&plugin_dir)
+        .map_err(|err| format!("Could not read {}: {}", plugin_dir.display(), err))?;
+    Ok(entries
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| path.extension().map(|ext| ext == "dylib").unwrap_or(false))
+        .filter(|path| config.enables(path))
+        .collect())
+}
 
-fn load_libs(
+/// Loads the enabled libraries that aren't loaded yet and drops the ones that
+/// no longer are, from another directory or taken out of the plugin list
+fn sync_libs(
+    libraries: &mut HashMap<String, DynLib>,
     watcher: &mut RecommendedWatcher,
-    global_data: &GlobalData,
-) -> HashMap<String, DynLib> {
-    use std::fs::read_dir;
-    read_dir(LIB_LOC)
-        .expect("reading lib folder")
-        .map(|dir_buff| dir_buff.unwrap().path())
-        .filter(|path| path.extension().map(|ext| ext == "dylib").unwrap_or(false))
-        .inspect(|path| watcher.watch(path, RecursiveMode::NonRecursive).unwrap())
-        .map(|path| {
-            (
-                path.file_name().unwrap().to_str().unwrap().to_owned(),
-                load_lib(&path, global_data),
-            )
-        })
-        .collect()
+    global_data: &mut GlobalData,
+    config: &Config,
+) -> Result<(), String> {
+    let paths = lib_paths(config)?;
+    libraries.retain(|key, lib| {
+        let keep = paths.contains(&lib.path);
+        if !keep {
+            let _ = watcher.unwatch(&lib.path);
+            info!("Unloaded lib: {}", key);
+        }
+        keep
+    });
+    for path in paths {
+        let key = path.file_name().unwrap().to_str().unwrap().to_owned();
+        if !libraries.contains_key(&key) {
+            watcher.watch(&path, RecursiveMode::NonRecursive).unwrap();
+            let lib = load_lib(&path, global_data);
+            info!("Loaded lib: {}", &key);
+            libraries.insert(key, lib);
+        }
+    }
+    Ok(())
 }
 
 fn initial_state() -> GlobalData {
-    use types::{SecondaryMap, SlotMap};
-    let buffer = Default::default();
-    let mut buffer_keys = SlotMap::new();
-    let current_buffer = buffer_keys.insert(());
-    let mut buffers = SecondaryMap::new();
-    buffers.insert(current_buffer, buffer);
-    GlobalData {
-        buffer_keys,
-        buffers,
-        clients: SecondaryMap::new(),
-        client_keys: SlotMap::new(),
+    let mut global_data = GlobalData::default();
+    global_data.add_buffer(Default::default());
+    // The gutter is laid out here in core rather than in a plugin, so its options are too
+    let line_numbers = global_data.settings.gutter.line_numbers.clone();
+    global_data.options.declare(OptionSpec {
+        abbreviation: Some("nu".into()),
+        ..OptionSpec::new(
+            "number",
+            OptionScope::Buffer,
+            OptionValue::Bool(line_numbers != LineNumbers::Off),
+        )
+    });
+    global_data.options.declare(OptionSpec {
+        abbreviation: Some("rnu".into()),
+        ..OptionSpec::new(
+            "relativenumber",
+            OptionScope::Buffer,
+            OptionValue::Bool(line_numbers == LineNumbers::Relative),
+        )
+    });
+    // Diagnostic signs are drawn by the language plugins, in a column kept for them here
+    global_data.options.declare(OptionSpec {
+        abbreviation: Some("scl".into()),
+        ..OptionSpec::new(
+            "signcolumn",
+            OptionScope::Buffer,
+            OptionValue::Bool(global_data.settings.gutter.diagnostic_signs),
+        )
+    });
+    // Room for git change marks and folds, nothing draws in them yet
+    global_data.options.declare(OptionSpec::new(
+        "changecolumn",
+        OptionScope::Buffer,
+        OptionValue::Bool(global_data.settings.gutter.change_marks),
+    ));
+    global_data.options.declare(OptionSpec {
+        abbreviation: Some("fdc".into()),
+        ..OptionSpec::new(
+            "foldcolumn",
+            OptionScope::Buffer,
+            OptionValue::Bool(global_data.settings.gutter.fold_markers),
+        )
+    });
+    // Themes are loaded here so that plugins only ever have to look colours up
+    global_data.options.declare(OptionSpec::new(
+        "theme",
+        OptionScope::Global,
+        OptionValue::String("dark".into()),
+    ));
+    // Any name goes, themes can be added while running and load_theme reports unknown ones
+    global_data.ex_commands.register(ExCommand::new(
+        "colorscheme",
+        "colo",
+        Args::Optional(ArgKind::Text),
+    ));
+    global_data
+}
+
+// Switches to the theme the theme option names, or puts the option back if it can't
+fn load_theme_option(global_data: &mut GlobalData) -> Result<(), String> {
+    let name = global_data
+        .options
+        .get_str("theme", None)
+        .unwrap_or_default()
+        .to_string();
+    match theme::load_theme(&name) {
+        Ok(theme) => {
+            global_data.theme = theme;
+            Ok(())
+        }
+        Err(err) => {
+            let current = OptionValue::String(global_data.theme.name.clone());
+            global_data.options.set("theme", current, None)?;
+            Err(err)
+        }
     }
 }
 
@@ -120,6 +215,82 @@ fn setup_watcher(msg_sender: Sender<Msg>) -> RecommendedWatcher {
     watcher(tx, time::Duration::from_millis(100)).unwrap()
 }
 
+// Watches directories rather than files so that files replaced through a rename
+// (as most editors and formatters save) keep being picked up
+fn setup_file_watcher(msg_sender: Sender<Msg>) -> RecommendedWatcher {
+    let (tx, rx) = std::sync::mpsc::channel();
+    std::thread::spawn(move || {
+        for file_event in rx.iter() {
+            match file_event {
+                DebouncedEvent::Write(path)
+                | DebouncedEvent::Create(path)
+                | DebouncedEvent::Chmod(path)
+                | DebouncedEvent::Rename(_, path) => {
+                    msg_sender.send(Msg::FileChanged(path)).unwrap();
+                }
+                _ => {}
+            }
+        }
+    });
+    watcher(tx, time::Duration::from_millis(100)).unwrap()
+}
+
+fn watch_buffer_directories(
+    watcher: &mut RecommendedWatcher,
+    watched: &mut HashSet<path::PathBuf>,
+    global_data: &GlobalData,
+) {
+    for buffer in global_data.buffers.values() {
+        if buffer.source.as_os_str().is_empty() {
+            continue;
+        }
+        let directory = match buffer.source.parent() {
+            Some(parent) if !parent.as_os_str().is_empty() => parent.to_path_buf(),
+            _ => path::PathBuf::from("."),
+        };
+        if let Ok(directory) = fs::canonicalize(directory) {
+            if watched.insert(directory.clone()) {
+                if let Err(err) = watcher.watch(&directory, RecursiveMode::NonRecursive) {
+                    info!("Could not watch {:?}: {:?}", directory, err);
+                    watched.remove(&directory);
+                }
+            }
+        }
+    }
+}
+
+// Config files are watched through their directory like buffers are
+fn watch_config_directories(
+    watcher: &mut RecommendedWatcher,
+    watched: &mut HashSet<path::PathBuf>,
+    config_paths: &[path::PathBuf],
+) {
+    for config_path in config_paths {
+        let directory = match config_path.parent().map(fs::canonicalize) {
+            Some(Ok(directory)) => directory,
+            _ => continue,
+        };
+        if watched.insert(directory.clone()) {
+            if let Err(err) = watcher.watch(&directory, RecursiveMode::NonRecursive) {
+                info!("Could not watch {:?}: {:?}", directory, err);
+                watched.remove(&directory);
+            }
+        }
+    }
+}
+
+fn is_config_file(config_paths: &[path::PathBuf], changed: &path::Path) -> bool {
+    config_paths.iter().any(|config_path| {
+        config_path.file_name() == changed.file_name()
+            && match (config_path.parent(), changed.parent()) {
+                (Some(config_dir), Some(changed_dir)) => {
+                    fs::canonicalize(config_dir).ok() == fs::canonicalize(changed_dir).ok()
+                }
+                _ => false,
+            }
+    })
+}
+
 fn setup_external_socket(msg_sender: Sender<Msg>) {
     std::thread::spawn(move || {
         // Don't care if it did not exist
@@ -171,27 +342,120 @@ fn setup_client_listener(msg_sender: Sender<Msg>) {
     });
 }
 
-fn setup_logging() {
+// Starts the log over unless `append`, which a reload into the same file is
+fn logging_config(
+    config: &Config,
+    append: bool,
+    errors: &mut Vec<String>,
+) -> Result<log4rs::config::Config, String> {
+    let level = config.log_level().unwrap_or_else(|err| {
+        errors.push(err);
+        log::LevelFilter::Info
+    });
+    let log_file = config.log_file();
     let logfile = FileAppender::builder()
         .encoder(Box::new(PatternEncoder::new("{l} - {m}\n")))
-        .append(false)
-        .build("output.log")
-        .expect("logging setup");
-    let config = Config::builder()
+        .append(append)
+        .build(&log_file)
+        .map_err(|err| format!("Could not open {}: {}", log_file.display(), err))?;
+    log4rs::config::Config::builder()
         .appender(Appender::builder().build("logfile", Box::new(logfile)))
-        .build(Root::builder().appender("logfile").build(LevelFilter::Info))
-        .unwrap();
-    log4rs::init_config(config).unwrap();
+        .build(Root::builder().appender("logfile").build(level))
+        .map_err(|err| format!("Invalid logging setup: {}", err))
+}
+
+// Logs to the default file when the configured one can't be opened, which
+// then counts as unset so that a reload tries the configured one again
+fn setup_logging(config: &mut Config, errors: &mut Vec<String>) -> log4rs::Handle {
+    let log_config = logging_config(config, false, errors).unwrap_or_else(|err| {
+        errors.push(err);
+        config.log_file = None;
+        // Level problems were already reported by the first attempt
+        logging_config(config, false, &mut Vec::new()).expect("logging setup")
+    });
+    let handle = log4rs::init_config(log_config).unwrap();
     info!("Hello World");
+    handle
+}
+
+/// Reads the config files again and applies what changed. Gives back the names of
+/// the options it set and anything that went wrong.
+fn reload_config(
+    config_paths: &ConfigPaths,
+    config: &mut Config,
+    log_handle: &log4rs::Handle,
+    libraries: &mut HashMap<String, DynLib>,
+    watcher: &mut RecommendedWatcher,
+    global_data: &mut GlobalData,
+) -> (Vec<String>, Vec<String>) {
+    let (new_config, mut errors) = config::load(config_paths);
+    if new_config.log_file() != config.log_file() || new_config.log_level != config.log_level {
+        let append = new_config.log_file() == config.log_file();
+        match logging_config(&new_config, append, &mut errors) {
+            Ok(log_config) => log_handle.set_config(log_config),
+            Err(err) => errors.push(err),
+        }
+    }
+    let mut set = config.reset_options(&mut global_data.options);
+    let (applied, option_errors) = new_config.apply_options(&mut global_data.options);
+    set.extend(applied);
+    set.sort();
+    set.dedup();
+    errors.extend(option_errors);
+    global_data.keymaps = new_config.keymaps.clone();
+    match new_config.status_line() {
+        Ok(layout) => global_data.settings.status_line = layout,
+        Err(err) => errors.push(err),
+    }
+    if new_config.plugin_dir() != config.plugin_dir() || new_config.plugins != config.plugins {
+        if let Err(err) = sync_libs(libraries, watcher, global_data, &new_config) {
+            errors.push(err);
+        }
+        errors.extend(global_data.options.take_errors());
+    }
+    *config = new_config;
+    info!("Reloaded config");
+    (set, errors)
 }
 
 pub fn start(file: Option<std::path::PathBuf>) {
-    setup_logging();
+    let config_paths = ConfigPaths::find();
+    let (mut config, mut config_errors) = config::load(&config_paths);
+    let log_handle = setup_logging(&mut config, &mut config_errors);
     let mut global_data = initial_state();
+    // Before the libs so that plugins declaring these options start out with them
+    let (_, option_errors) = config.apply_options(&mut global_data.options);
+    config_errors.extend(option_errors);
+    if let Err(err) = load_theme_option(&mut global_data) {
+        config_errors.push(err);
+    }
+    global_data.keymaps = config.keymaps.clone();
+    match config.status_line() {
+        Ok(layout) => global_data.settings.status_line = layout,
+        Err(err) => config_errors.push(err),
+    }
     let utils = utils::build_utils();
     let (msg_sender, msg_receiver) = unbounded::<Msg>();
     let mut watcher = setup_watcher(msg_sender.clone());
-    let mut libraries: HashMap<String, DynLib> = load_libs(&mut watcher, &global_data);
+    let mut libraries: HashMap<String, DynLib> = HashMap::new();
+    if let Err(err) = sync_libs(&mut libraries, &mut watcher, &mut global_data, &config) {
+        config_errors.push(err);
+        // Same as for the log file, the default directory stands in until a reload
+        config.plugin_dir = None;
+        sync_libs(&mut libraries, &mut watcher, &mut global_data, &config).expect("loading libs");
+    }
+    // Options the plugins just declared may not take the values the config gave them
+    config_errors.extend(global_data.options.take_errors());
+    let mut file_watcher = setup_file_watcher(msg_sender.clone());
+    let mut watched_directories = HashSet::new();
+    watch_config_directories(
+        &mut file_watcher,
+        &mut watched_directories,
+        &config_paths.all(),
+    );
+    for err in &config_errors {
+        warn!("{}", err);
+    }
 
     setup_external_socket(msg_sender.clone());
     setup_client_listener(msg_sender.clone());
@@ -213,12 +477,66 @@ pub fn start(file: Option<std::path::PathBuf>) {
                 DebouncedEvent::Create(ref path) => {
                     let key = path.file_name().unwrap().to_str().unwrap();
                     libraries.remove(key);
-                    let lib = load_lib(path, &global_data);
+                    let lib = load_lib(path, &mut global_data);
                     libraries.insert(key.to_string(), lib);
                     info!("Reloaded lib: {}", &key);
                 }
                 _ => {}
             },
+            Msg::FileChanged(ref path) if is_config_file(&config_paths.all(), path) => {
+                let (set, errors) = reload_config(
+                    &config_paths,
+                    &mut config,
+                    &log_handle,
+                    &mut libraries,
+                    &mut watcher,
+                    &mut global_data,
+                );
+                for client in global_data.client_keys.keys() {
+                    for name in &set {
+                        msg_sender
+                            .send(Msg::Cmd(client, Cmd::OptionChanged(name.clone())))
+                            .expect("sending option change");
+                    }
+                    for err in &errors {
+                        msg_sender
+                            .send(Msg::Cmd(
+                                client,
+                                Cmd::Notify(NotifyLevel::Error, err.clone()),
+                            ))
+                            .expect("sending config error");
+                    }
+                }
+            }
+            Msg::Cmd(client, Cmd::RunExCommand(ref call)) if call.name == "colorscheme" => {
+                if call.argument.is_empty() {
+                    // Listed fresh each time so themes added since startup show up
+                    let name = format!(
+                        "{} (available: {})",
+                        global_data.theme.name,
+                        theme::theme_names().join(", ")
+                    );
+                    msg_sender
+                        .send(Msg::Cmd(client, Cmd::Notify(NotifyLevel::Info, name)))
+                        .expect("sending theme name");
+                } else {
+                    let name = OptionValue::String(call.argument.trim().to_string());
+                    let cmd = match global_data.options.set("theme", name, None) {
+                        Ok(()) => Cmd::OptionChanged("theme".into()),
+                        Err(err) => Cmd::Notify(NotifyLevel::Error, err),
+                    };
+                    msg_sender
+                        .send(Msg::Cmd(client, cmd))
+                        .expect("sending option change");
+                }
+            }
+            Msg::Cmd(client, Cmd::OptionChanged(ref name)) if name == "theme" => {
+                if let Err(err) = load_theme_option(&mut global_data) {
+                    msg_sender
+                        .send(Msg::Cmd(client, Cmd::Notify(NotifyLevel::Error, err)))
+                        .expect("sending theme error");
+                }
+            }
             Msg::StdinEvent(_client, ref evt) => {
                 use termion::event::{Event, Key};
                 match evt {
@@ -227,12 +545,10 @@ pub fn start(file: Option<std::path::PathBuf>) {
                 }
             }
             Msg::Cmd(client_index, Cmd::Quit) => {
-                // TODO: don't crash
-                global_data.clients[client_index]
-                    .stream
-                    .shutdown(std::net::Shutdown::Both);
-                global_data.clients.remove(client_index);
-                global_data.client_keys.remove(client_index);
+                // The tabs stay around for the next client to connect
+                if let Some(client) = global_data.detach_client(client_index) {
+                    client.stream.shutdown(std::net::Shutdown::Both);
+                }
                 // Don't want to have other libs try to run this event
                 continue;
             }
@@ -247,28 +563,20 @@ pub fn start(file: Option<std::path::PathBuf>) {
                     "{}",
                     termion::clear::All
                 );
+                // The cleared screen is in the terminal's colours rather than the theme's
                 global_data.clients[client].back_buffer = back_buffer::create_back_buffer(
                     global_data.clients[client]
                         .size
                         .clone()
                         .unwrap_or(Rect::default()),
+                    &Theme::default(),
                 );
             }
             Msg::NewClient(ref stream) => {
                 let stream_clone = stream.try_clone().unwrap();
-                let mut client = Client {
-                    stream: stream.try_clone().unwrap(),
-                    buffer: global_data
-                        .buffers
-                        .iter()
-                        .next()
-                        .expect("Getting first buffer")
-                        .0,
-                    mode: Mode::Normal,
-                    back_buffer: back_buffer::create_back_buffer(Rect::default()),
-                    size: None,
-                };
-                let index = global_data.client_keys.insert(());
+                let reattached = global_data.detached_tabs.is_some();
+                let index = global_data.attach_client(stream.try_clone().unwrap());
+                let client = &mut global_data.clients[index];
                 // Tell the client who they are
                 info!("Information client {:?}", index);
                 let mut buf = Vec::new();
@@ -278,10 +586,15 @@ pub fn start(file: Option<std::path::PathBuf>) {
                     .unwrap();
                 client.stream.write_all(&buf).expect("sending client index");
                 client.stream.flush().expect("flushing stream");
-                // Store the client
-                global_data.clients.insert(index, client);
                 handle_client_input(index, stream_clone, msg_sender.clone());
-                if let Some(ref file) = file {
+                // Problems with the config read at startup go to whoever shows up first
+                for err in config_errors.drain(..) {
+                    msg_sender
+                        .send(Msg::Cmd(index, Cmd::Notify(NotifyLevel::Error, err)))
+                        .expect("sending config error");
+                }
+                // A client picking up old tabs keeps showing what they showed
+                if let (Some(file), false) = (&file, reattached) {
                     msg_sender
                         .send(Msg::Cmd(index, Cmd::LoadFile(file.to_path_buf())))
                         .expect("loading initial file");
@@ -299,12 +612,21 @@ pub fn start(file: Option<std::path::PathBuf>) {
             info!("updating: {}", path);
             (*lib.update_fn)(&mut global_data, &msg, &utils, &cmd_handler, lib.data);
         }
+        match msg {
+            Msg::Cmd(_, Cmd::BufferLoaded) | Msg::Cmd(_, Cmd::WriteBuffer(..)) => {
+                watch_buffer_directories(&mut file_watcher, &mut watched_directories, &global_data)
+            }
+            _ => {}
+        }
         if msg_sender.is_empty() {
             // Don't bother rendering if there is more in the pipeline
             for client in global_data.client_keys.keys() {
                 if let Some(size) = global_data.clients[client].size.clone() {
-                    let mut new_back_buffer = back_buffer::create_back_buffer(size);
-                    for (path, lib) in libraries.iter() {
+                    let mut new_back_buffer =
+                        back_buffer::create_back_buffer(size, &global_data.theme);
+                    let mut layered: Vec<_> = libraries.iter().collect();
+                    layered.sort_by_key(|(path, lib)| (lib.layer, *path));
+                    for (path, lib) in layered {
                         info!("rendering: {}", path);
                         (*lib.render_fn)(
                             &global_data,
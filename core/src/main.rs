@@ -1,7 +1,9 @@
 mod back_buffer;
 mod client;
+mod config;
 mod editor;
 mod send_cmd;
+mod theme;
 mod utils;
 
 use structopt::StructOpt;
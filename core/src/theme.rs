@@ -0,0 +1,83 @@
+use std::path::PathBuf;
+use types::{Theme, ThemeFile};
+
+use crate::config;
+
+const BUILT_IN: [(&str, &str); 2] = [
+    ("dark", include_str!("../themes/dark.toml")),
+    ("light", include_str!("../themes/light.toml")),
+];
+
+// Users keep their own themes next to the config file
+fn user_theme_dir() -> Option<PathBuf> {
+    config::config_dir().map(|directory| directory.join("themes"))
+}
+
+/// The built in themes and the ones in the user's theme directory, sorted
+pub fn theme_names() -> Vec<String> {
+    let mut names: Vec<String> = BUILT_IN.iter().map(|(name, _)| name.to_string()).collect();
+    if let Some(Ok(entries)) = user_theme_dir().map(std::fs::read_dir) {
+        names.extend(
+            entries
+                .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+                .filter(|path| path.extension().map(|ext| ext == "toml").unwrap_or(false))
+                .filter_map(|path| Some(path.file_stem()?.to_str()?.to_owned())),
+        );
+    }
+    names.sort();
+    names.dedup();
+    names
+}
+
+// A user's theme of the same name takes over from a built in one
+fn theme_source(name: &str) -> Result<String, String> {
+    if let Some(path) = user_theme_dir().map(|directory| directory.join(format!("{}.toml", name))) {
+        match std::fs::read_to_string(&path) {
+            Ok(text) => return Ok(text),
+            Err(ref err) if err.kind() == std::io::ErrorKind::NotFound => {}
+            Err(err) => return Err(format!("{}: {}", path.display(), err)),
+        }
+    }
+    BUILT_IN
+        .iter()
+        .find(|(built_in, _)| *built_in == name)
+        .map(|(_, text)| text.to_string())
+        .ok_or_else(|| format!("Unknown theme: {}", name))
+}
+
+fn load_inheriting(name: &str, seen: &mut Vec<String>) -> Result<Theme, String> {
+    if seen.iter().any(|loaded| loaded == name) {
+        return Err(format!("Themes inherit in a loop: {}", seen.join(" -> ")));
+    }
+    seen.push(name.into());
+    let file: ThemeFile =
+        toml::from_str(&theme_source(name)?).map_err(|err| format!("Theme {}: {}", name, err))?;
+    let base = match &file.inherits {
+        Some(base) => Some(load_inheriting(base, seen)?),
+        None => None,
+    };
+    Theme::from_file(name, file, base).map_err(|err| format!("Theme {}: {}", name, err))
+}
+
+/// Loads the theme called `name`, along with the ones it builds on
+pub fn load_theme(name: &str) -> Result<Theme, String> {
+    load_inheriting(name, &mut Vec::new())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_built_in_themes() {
+        for (name, text) in BUILT_IN.iter() {
+            let file: ThemeFile = toml::from_str(text).unwrap();
+            let theme = Theme::from_file(name, file, None).unwrap();
+            assert!(theme.fg("ui.text").is_some());
+            assert!(theme.bg("ui.text").is_some());
+            assert_eq!(theme.get("ui.tabline.active"), theme.get("ui.statusline"));
+            assert_eq!(theme.fg("ui.message.error"), theme.fg("diagnostic.error"));
+            assert_eq!(theme.fg("keyword.control"), theme.fg("keyword"));
+        }
+    }
+}
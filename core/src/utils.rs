@@ -1,6 +1,6 @@
-use crate::back_buffer::{style_range, style_rope_slice_range, write_to_buffer};
+use crate::back_buffer::{clear_range, style_range, style_rope_slice_range, write_to_buffer};
 use log::{debug, info, warn};
-use types::Utils;
+use types::{BufferIndex, GlobalData, GutterConfig, GutterLayout, LineNumbers, Utils};
 
 fn info(msg: &str) {
     info!("{}", msg);
@@ -14,6 +14,33 @@ fn debug(msg: &str) {
     debug!("{}", msg);
 }
 
+fn gutter_layout(global_data: &GlobalData, buffer: BufferIndex) -> GutterLayout {
+    let options = &global_data.options;
+    // The number and relativenumber options, which can differ per buffer
+    let line_numbers = match (
+        options.get_bool("number", Some(buffer)),
+        options.get_bool("relativenumber", Some(buffer)),
+    ) {
+        (_, Some(true)) => LineNumbers::Relative,
+        (Some(false), _) => LineNumbers::Off,
+        (Some(true), _) => LineNumbers::Absolute,
+        (None, _) => global_data.settings.gutter.line_numbers.clone(),
+    };
+    let config = GutterConfig {
+        line_numbers,
+        diagnostic_signs: options
+            .get_bool("signcolumn", Some(buffer))
+            .unwrap_or(global_data.settings.gutter.diagnostic_signs),
+        change_marks: options
+            .get_bool("changecolumn", Some(buffer))
+            .unwrap_or(global_data.settings.gutter.change_marks),
+        fold_markers: options
+            .get_bool("foldcolumn", Some(buffer))
+            .unwrap_or(global_data.settings.gutter.fold_markers),
+    };
+    GutterLayout::new(&config, global_data.buffers[buffer].rope.len_lines())
+}
+
 pub fn build_utils() -> Utils {
     Utils {
         write_to_buffer,
@@ -21,6 +48,8 @@ pub fn build_utils() -> Utils {
         warn,
         debug,
         style_range,
+        clear_range,
         style_rope_slice_range,
+        gutter_layout,
     }
 }
@@ -1,107 +1,82 @@
+mod marks;
+mod multi;
+mod visual;
+
 use ropey::Rope;
 
+use marks::update_marks;
+use multi::{cursors_on_lines, next_match};
 use termion::cursor::{Goto, Show};
 use types::{
-    BackBuffer, Buffer, BufferIndex, Client, ClientIndex, Cmd, Color, DeleteDirection, Direction,
-    GlobalData, JumpType, Mode, Msg, Point, Rect, SecondaryMap, Utils,
+    char_index, insert_at_points, point_from_char, remove_ranges, BackBuffer, ClientIndex, Cmd,
+    Cursor, DeleteDirection, Direction, GlobalData, GutterLayout, JumpType, Mode, Msg, NotifyLevel,
+    Operator, OptionScope, OptionSpec, OptionValue, Point, Theme, Utils, Window, WindowRegion,
 };
-
-#[derive(Debug)]
-struct Cursor {
-    position: Point,
-    stored_x: u16,
-    selection_anchor: Option<Point>,
-}
-
-impl Default for Cursor {
-    fn default() -> Cursor {
-        let position = Point { x: 1, y: 0 };
-        Cursor {
-            selection_anchor: None,
-            position,
-            stored_x: 0,
-        }
-    }
-}
-
-fn get_ropey_index_from_cursor(position: &Point, rope: &Rope) -> usize {
-    rope.line_to_char(position.y as usize) + position.x as usize - 1
-}
-
-fn get_char_range(
-    position: &Point,
-    selection_anchor: &Point,
-    rope: &Rope,
-) -> std::ops::RangeInclusive<usize> {
-    let start_index = get_ropey_index_from_cursor(position, rope);
-    let end_index = get_ropey_index_from_cursor(selection_anchor, rope);
-    if start_index < end_index {
-        start_index..=end_index
-    } else {
-        end_index..=start_index
-    }
-}
+use visual::{apply_operator, Selection};
 
 #[derive(Debug, Default)]
-struct State {
-    cursors: SecondaryMap<BufferIndex, Cursor>,
-}
-
-fn write_mode_status(back_buffer: &mut BackBuffer, client: &Client, utils: &Utils) {
-    if let Some(Rect { w, h }) = client.size {
-        let display = match client.mode {
-            Mode::Normal => "NORMAL",
-            Mode::Insert => "INSERT",
-            Mode::Command => "COMMAND",
-        };
-        (utils.write_to_buffer)(
-            back_buffer,
-            &Point {
-                x: w - display.len() as u16 - 1,
-                y: h - 1,
-            },
-            display,
-            None,
-            None,
-            None,
-        );
-    }
-}
+struct State {}
 
 fn apply_selection_style(
     back_buffer: &mut BackBuffer,
     utils: &Utils,
+    theme: &Theme,
+    mode: &Mode,
+    window: &Window,
     cursor: &Cursor,
     rope: &Rope,
-    start_line: usize,
+    gutter: &GutterLayout,
+    region: &WindowRegion,
 ) {
     if let Some(ref selection_anchor) = cursor.selection_anchor {
-        let char_range = get_char_range(&cursor.position, selection_anchor, rope);
-        let slice = rope.slice(char_range);
-        let start_point = if *selection_anchor > cursor.position {
-            Point {
-                x: cursor.position.x + 3,                 // Make room for line numbers
-                y: cursor.position.y - start_line as u16, // Prevent scrolling breaking things
+        // Another window may have shortened the buffer underneath this one
+        if std::cmp::max(cursor.position.y, selection_anchor.y) as usize >= rope.len_lines() {
+            return;
+        }
+        let text_width = gutter.text_width(region.size.w);
+        let style = theme.get("ui.selection");
+        let selection = Selection::new(mode, selection_anchor, &cursor.position);
+        let (first_line, last_line) = selection.lines();
+        // Style line by line so that lines scrolled off above or below and columns
+        // scrolled off to either side are skipped
+        let last_visible = window.start_line + region.size.h as usize;
+        for line in std::cmp::max(first_line, window.start_line)..=last_line {
+            if line >= last_visible {
+                break;
             }
-        } else {
-            Point {
-                x: selection_anchor.x + 3,
-                y: selection_anchor.y - start_line as u16,
+            let (from, to) = selection.span(rope, line);
+            let from = std::cmp::max(from, window.start_column);
+            let to = std::cmp::min(to, window.start_column + text_width);
+            if from >= to {
+                continue;
             }
-        };
+            if let Some(start_point) = gutter
+                .screen_point(from, line, window.start_column, window.start_line)
+                .and_then(|point| region.to_screen(&point))
+            {
+                (utils.style_range)(
+                    back_buffer,
+                    &start_point,
+                    to - from,
+                    style.style.clone(),
+                    style.fg.clone(),
+                    style.bg.clone(),
+                );
+            }
+        }
+    }
+}
 
-        (utils.style_rope_slice_range)(
-            back_buffer,
-            &slice,
-            start_point,
-            None,
-            None,
-            Some(Color {
-                r: 0,
-                g: 50,
-                b: 200,
-            }),
-        );
+fn scroll_horizontally(window: &mut Window, text_width: usize, margin: usize) {
+    if text_width == 0 {
+        return;
+    }
+    let margin = std::cmp::min(margin, (text_width - 1) / 2);
+    let column = window.cursor.position.x.saturating_sub(1) as usize;
+    if column < window.start_column + margin {
+        window.start_column = column.saturating_sub(margin);
+    } else if column + margin >= window.start_column + text_width {
+        window.start_column = column + margin + 1 - text_width;
     }
 }
 
@@ -113,32 +88,73 @@ pub fn render(
     utils: &Utils,
     data_ptr: *mut c_void,
 ) {
-    let mut data: Box<State> = unsafe { Box::from_raw(data_ptr as *mut State) };
-    write_mode_status(back_buffer, &global_data.clients[*client], utils);
+    let data: Box<State> = unsafe { Box::from_raw(data_ptr as *mut State) };
+    let client = &global_data.clients[*client];
     use std::io::Write;
-    let mut stream = global_data.clients[*client].stream.try_clone().unwrap();
-    let cursor = get_or_insert_cursor(&mut data, &global_data, client);
-    let buffer_index = global_data.clients[*client].buffer;
-    let rope = &global_data.buffers[buffer_index].rope;
-    let current_buffer = global_data.clients[*client].buffer;
-    apply_selection_style(
-        back_buffer,
-        utils,
-        &cursor,
-        rope,
-        global_data.buffers[current_buffer].start_line,
-    );
-    if global_data.clients[*client].mode != Mode::Command {
-        write!(
-            stream,
-            "{}{}",
-            Show,
-            Goto(
-                cursor.position.x + 4, // +4 for line numbers
-                cursor.position.y + 1 - global_data.buffers[current_buffer].start_line as u16
+    let mut stream = client.stream.try_clone().unwrap();
+    for region in client.window_regions() {
+        let window = &client.tab().windows[region.window];
+        let rope = &global_data.buffers[window.buffer].rope;
+        let gutter = (utils.gutter_layout)(global_data, window.buffer);
+        for cursor in std::iter::once(&window.cursor).chain(window.extra_cursors.iter()) {
+            apply_selection_style(
+                back_buffer,
+                utils,
+                &global_data.theme,
+                &client.mode,
+                window,
+                cursor,
+                rope,
+                &gutter,
+                &region,
+            );
+        }
+        // The terminal only has the one cursor, the others are drawn as cells
+        let style = global_data.theme.get("ui.cursor");
+        for cursor in &window.extra_cursors {
+            if let Some(screen_point) = gutter
+                .screen_point(
+                    cursor.position.x as usize - 1,
+                    cursor.position.y as usize,
+                    window.start_column,
+                    window.start_line,
+                )
+                .and_then(|point| region.to_screen(&point))
+            {
+                (utils.style_range)(
+                    back_buffer,
+                    &screen_point,
+                    1,
+                    style.style.clone(),
+                    style.fg.clone(),
+                    style.bg.clone(),
+                );
+            }
+        }
+        if region.window != client.tab().active_window
+            || client.mode == Mode::Command
+            || client.mode == Mode::Picker
+        {
+            continue;
+        }
+        let cursor = &window.cursor;
+        if let Some(screen_point) = gutter
+            .screen_point(
+                cursor.position.x as usize - 1,
+                cursor.position.y as usize,
+                window.start_column,
+                window.start_line,
+            )
+            .and_then(|point| region.to_screen(&point))
+        {
+            write!(
+                stream,
+                "{}{}",
+                Show,
+                Goto(screen_point.x + 1, screen_point.y + 1)
             )
-        )
-        .expect("Writing cursor position to client");
+            .expect("Writing cursor position to client");
+        }
     }
     std::mem::forget(data);
 }
@@ -153,21 +169,10 @@ fn get_new_x_position(cursor: &Cursor, rope: &Rope) -> u16 {
     )
 }
 
-fn get_or_insert_cursor<'a>(
-    data: &'a mut Box<State>,
-    global_data: &GlobalData,
-    client: &ClientIndex,
-) -> &'a mut Cursor {
-    let buffer_index = global_data.clients[*client].buffer;
-    if !data.cursors.contains_key(buffer_index) {
-        data.cursors
-            .insert(buffer_index, std::default::Default::default());
-    }
-    &mut data.cursors[buffer_index]
-}
-
 fn get_point_to_left(position: &Point, rope: &Rope) -> Point {
-    if position.x > 1 {
+    if position.x <= 1 && position.y == 0 {
+        position.clone()
+    } else if position.x > 1 {
         Point {
             x: position.x - 1,
             y: position.y,
@@ -197,13 +202,7 @@ fn get_point_to_right(position: &Point, rope: &Rope) -> Point {
     }
 }
 
-fn move_cursor_position(
-    cursor: &mut Cursor,
-    dir: &Direction,
-    current_buffer: &mut Buffer,
-    client: &Client,
-) {
-    let rope = &current_buffer.rope;
+fn move_cursor(dir: &Direction, cursor: &mut Cursor, rope: &Rope) {
     use Direction::*;
     match dir {
         Left => {
@@ -218,64 +217,290 @@ fn move_cursor_position(
             if cursor.position.y > 0 {
                 cursor.position.y -= 1;
             }
-            if (cursor.position.y as usize) < current_buffer.start_line {
-                current_buffer.start_line -= 1;
-            }
         }
         Down => {
             if (cursor.position.y as usize) + 2 < rope.len_lines() {
                 cursor.position.y += 1;
             }
-            if (cursor.position.y as usize)
-                >= current_buffer.start_line
-                    + (client.size.as_ref().map(|s| s.h).unwrap_or(0) as usize - 1)
-            {
-                current_buffer.start_line += 1;
-            }
         }
     }
     // Make sure we don't venture to nowhere
-    cursor.position.x = get_new_x_position(&cursor, &rope);
+    cursor.position.x = get_new_x_position(&cursor, rope);
+}
+
+fn move_cursor_position(dir: &Direction, window: &mut Window, rope: &Rope, visible_lines: usize) {
+    move_cursor(dir, &mut window.cursor, rope);
+    scroll_vertically(window, visible_lines);
+}
+
+// Scrolls just far enough to keep the cursor's line in view
+fn scroll_vertically(window: &mut Window, visible_lines: usize) {
+    let line = window.cursor.position.y as usize;
+    if line < window.start_line {
+        window.start_line = line;
+    } else if visible_lines > 0 && line >= window.start_line + visible_lines {
+        window.start_line = line + 1 - visible_lines;
+    }
+}
+
+// Centers the cursor's line when it is off screen, for jumps
+fn center_if_hidden(window: &mut Window, visible_lines: usize) {
+    let line = window.cursor.position.y as usize;
+    if line < window.start_line || line >= window.start_line + visible_lines {
+        window.start_line = line.saturating_sub(visible_lines / 2);
+    }
+}
+
+// Makes `cursor` the newest one, unless there already is one in its place
+fn add_cursor(window: &mut Window, cursor: Cursor) -> bool {
+    let taken = std::iter::once(&window.cursor)
+        .chain(window.extra_cursors.iter())
+        .any(|other| other.position == cursor.position);
+    if !taken {
+        let previous = std::mem::replace(&mut window.cursor, cursor);
+        window.extra_cursors.push(previous);
+    }
+    !taken
+}
+
+// Puts each cursor where an edit moved it to
+fn place_cursors(window: &mut Window, positions: Vec<Point>) {
+    for (cursor, position) in window.cursors_mut().zip(positions) {
+        cursor.stored_x = position.x;
+        cursor.position = position;
+        cursor.selection_anchor = None;
+    }
+    window.dedup_cursors();
 }
 
 #[no_mangle]
 pub fn update(
     global_data: &mut GlobalData,
     cmd: &Msg,
-    _utils: &Utils,
+    utils: &Utils,
     send_cmd: &Box<Fn(ClientIndex, Cmd)>,
     data_ptr: *mut c_void,
 ) {
-    let mut data: Box<State> = unsafe { Box::from_raw(data_ptr as *mut State) };
+    let data: Box<State> = unsafe { Box::from_raw(data_ptr as *mut State) };
     use Cmd::*;
     match cmd {
         Msg::Cmd(client_index, cmd) => {
-            let cursor = get_or_insert_cursor(&mut data, &global_data, client_index);
-            let current_buffer =
-                &mut global_data.buffers[global_data.clients[*client_index].buffer];
-            let rope = &mut current_buffer.rope;
-            let client = &global_data.clients[*client_index];
+            let buffer_index = global_data.clients[*client_index].buffer();
+            let gutter = (utils.gutter_layout)(global_data, buffer_index);
+            let region_size = global_data.clients[*client_index]
+                .active_region()
+                .map(|region| region.size)
+                .unwrap_or_default();
+            let text_width = gutter.text_width(region_size.w);
+            let mode = global_data.clients[*client_index].mode.clone();
+            if let ChangeMode(ref new_mode) = cmd {
+                global_data.clients[*client_index].mode = new_mode.clone();
+            }
+            update_marks(
+                global_data,
+                *client_index,
+                cmd,
+                &mode,
+                region_size.h as usize,
+                send_cmd,
+            );
+            let shiftwidth = global_data
+                .options
+                .get_int("shiftwidth", Some(buffer_index))
+                .unwrap_or(4)
+                .max(0) as usize;
+            let side_scroll_margin = global_data
+                .options
+                .get_int("sidescrolloff", None)
+                .unwrap_or(0);
+            let rope = &global_data.buffers[buffer_index].rope;
+            let window = global_data.clients[*client_index].window_mut();
             match cmd {
                 MoveCursor(dir, selecting) => {
-                    if *selecting {
-                        if cursor.selection_anchor.is_none() {
+                    let selecting = *selecting || mode.is_visual();
+                    for cursor in window.cursors_mut() {
+                        if selecting {
+                            if cursor.selection_anchor.is_none() {
+                                cursor.selection_anchor = Some(cursor.position.clone());
+                            }
+                        } else {
+                            cursor.selection_anchor = None;
+                        }
+                    }
+                    match mode {
+                        Mode::Command => {}
+                        _ => {
+                            for cursor in window.extra_cursors.iter_mut() {
+                                move_cursor(dir, cursor, rope);
+                            }
+                            move_cursor_position(dir, window, rope, region_size.h as usize);
+                            window.dedup_cursors();
+                        }
+                    }
+                }
+                // Switching between visual modes keeps the selection, only its shape changes
+                ChangeMode(new_mode) if new_mode.is_visual() => {
+                    for cursor in window.cursors_mut() {
+                        if !mode.is_visual() || cursor.selection_anchor.is_none() {
                             cursor.selection_anchor = Some(cursor.position.clone());
                         }
-                    } else {
+                    }
+                }
+                ChangeMode(_) => {
+                    for cursor in window.cursors_mut() {
                         cursor.selection_anchor = None;
                     }
-                    match global_data.clients[*client_index].mode {
-                        Mode::Command => {}
-                        _ => move_cursor_position(cursor, dir, current_buffer, client),
+                }
+                SwapSelectionEnds => {
+                    for cursor in window.cursors_mut() {
+                        if let Some(anchor) = cursor.selection_anchor.take() {
+                            cursor.selection_anchor = Some(cursor.position.clone());
+                            cursor.position = anchor;
+                            cursor.stored_x = cursor.position.x;
+                        }
                     }
                 }
-                ChangeMode(ref mode) => {
-                    global_data.clients[*client_index].mode = mode.clone();
-                    cursor.selection_anchor = None;
+                // Syntax objects are left to the language plugins
+                SelectObject(object, operator) if !object.kind.is_syntax() => {
+                    let index = char_index(&window.cursor.position, rope);
+                    if let Some((start, end)) = object.find(rope, index) {
+                        let (start, end) =
+                            (point_from_char(start, rope), point_from_char(end, rope));
+                        send_cmd(*client_index, Select(start, end));
+                        if let Some(operator) = operator {
+                            send_cmd(*client_index, ApplyOperator(operator.clone()));
+                        }
+                    }
+                }
+                Select(anchor, position) => {
+                    let cursor = &mut window.cursor;
+                    cursor.selection_anchor = Some(anchor.clone());
+                    cursor.position = position.clone();
+                    cursor.stored_x = cursor.position.x;
+                    // Objects are charwise whatever the visual mode was
+                    if mode == Mode::VisualLine || mode == Mode::VisualBlock {
+                        send_cmd(*client_index, ChangeMode(Mode::Visual));
+                    }
+                    scroll_vertically(window, region_size.h as usize);
+                }
+                AddCursorAtNextMatch | SkipCursor => {
+                    match next_match(rope, &mode, &window.cursor) {
+                        Some(cursor) => {
+                            // Skipping puts the newest cursor on the next match instead of adding one
+                            let added = if *cmd == SkipCursor {
+                                let taken = window
+                                    .extra_cursors
+                                    .iter()
+                                    .any(|other| other.position == cursor.position);
+                                if !taken {
+                                    window.cursor = cursor;
+                                }
+                                !taken
+                            } else {
+                                add_cursor(window, cursor)
+                            };
+                            if added {
+                                center_if_hidden(window, region_size.h as usize);
+                            } else {
+                                send_cmd(
+                                    *client_index,
+                                    Notify(NotifyLevel::Error, "No other match".into()),
+                                );
+                            }
+                        }
+                        None => send_cmd(
+                            *client_index,
+                            Notify(NotifyLevel::Error, "Nothing to match under cursor".into()),
+                        ),
+                    }
+                }
+                AddCursorsOnLines => {
+                    let mut cursors = cursors_on_lines(rope, &mode, &window.cursor);
+                    let line = window.cursor.position.y;
+                    let newest = cursors
+                        .iter()
+                        .position(|cursor| cursor.position.y == line)
+                        .unwrap_or(0);
+                    window.cursor = cursors.remove(newest);
+                    window.extra_cursors = cursors;
                 }
-                InsertChar(c) => match global_data.clients[*client_index].mode {
+                RemoveCursor => {
+                    if let Some(previous) = window.extra_cursors.pop() {
+                        window.cursor = previous;
+                        center_if_hidden(window, region_size.h as usize);
+                    }
+                }
+                RemoveExtraCursors => window.extra_cursors.clear(),
+                // With several cursors every charwise selection is deleted in one edit
+                ApplyOperator(operator)
+                    if !window.extra_cursors.is_empty()
+                        && mode == Mode::Visual
+                        && (*operator == Operator::Delete || *operator == Operator::Change) =>
+                {
+                    if let Some(anchor) = &window.cursor.selection_anchor {
+                        let selection = Selection::new(&mode, anchor, &window.cursor.position);
+                        send_cmd(*client_index, YankValue(selection.text(rope)));
+                    }
+                    let ranges: Vec<(Point, Point)> = window
+                        .cursors_mut()
+                        .map(|cursor| {
+                            let anchor = cursor.selection_anchor.take();
+                            let position = cursor.position.clone();
+                            (anchor.unwrap_or_else(|| position.clone()), position)
+                        })
+                        .collect();
+                    let mut edited = rope.clone();
+                    place_cursors(window, remove_ranges(&mut edited, &ranges));
+                    send_cmd(*client_index, DeleteCharRanges(ranges));
+                    let next_mode = if *operator == Operator::Change {
+                        Mode::Insert
+                    } else {
+                        Mode::Normal
+                    };
+                    send_cmd(*client_index, ChangeMode(next_mode));
+                }
+                ApplyOperator(operator) => {
+                    let cursor = &mut window.cursor;
+                    if let Some(anchor) = cursor.selection_anchor.take() {
+                        let selection = Selection::new(&mode, &anchor, &cursor.position);
+                        match operator {
+                            Operator::Delete | Operator::Yank | Operator::Change => {
+                                send_cmd(*client_index, YankValue(selection.text(rope)));
+                            }
+                            _ => {}
+                        }
+                        if let Some(text) = apply_operator(rope, &selection, operator, shiftwidth) {
+                            let (first, last) = selection.lines();
+                            send_cmd(*client_index, ReplaceLines(first, last, text));
+                        }
+                        cursor.position = selection.start();
+                        cursor.stored_x = cursor.position.x;
+                    }
+                    let next_mode = if *operator == Operator::Change {
+                        Mode::Insert
+                    } else {
+                        Mode::Normal
+                    };
+                    send_cmd(*client_index, ChangeMode(next_mode));
+                }
+                // Deleting whole lines can leave the cursor past the end
+                BufferModified => window.clamp_to(rope),
+                InsertChar(c) => match mode {
                     Mode::Command => {}
+                    _ if !window.extra_cursors.is_empty() => {
+                        let points: Vec<Point> = window
+                            .cursors_mut()
+                            .map(|cursor| cursor.position.clone())
+                            .collect();
+                        // Worked out on a copy, the real edit happens in buffer-editor
+                        let mut edited = rope.clone();
+                        let moved = insert_at_points(&mut edited, &c.to_string(), &points);
+                        place_cursors(window, moved);
+                        scroll_vertically(window, region_size.h as usize);
+                        send_cmd(*client_index, InsertStringAtPoints(c.to_string(), points));
+                    }
                     _ => {
+                        let cursor = &mut window.cursor;
                         send_cmd(
                             *client_index,
                             InsertCharAtPoint(*c, cursor.position.clone()),
@@ -284,9 +509,50 @@ pub fn update(
                         cursor.selection_anchor = None;
                     }
                 },
-                DeleteChar(dir) => match global_data.clients[*client_index].mode {
+                DeleteChar(dir) => match mode {
                     Mode::Command => {}
+                    _ if !window.extra_cursors.is_empty() => {
+                        // Backspace at the very start has nothing to delete
+                        let ranges: Vec<Option<(Point, Point)>> = window
+                            .cursors_mut()
+                            .map(|cursor| match dir {
+                                DeleteDirection::After => {
+                                    let anchor = cursor.selection_anchor.take();
+                                    Some((
+                                        cursor.position.clone(),
+                                        anchor.unwrap_or_else(|| cursor.position.clone()),
+                                    ))
+                                }
+                                DeleteDirection::Before => {
+                                    let point = get_point_to_left(&cursor.position, rope);
+                                    if point == cursor.position {
+                                        None
+                                    } else {
+                                        Some((point.clone(), point))
+                                    }
+                                }
+                            })
+                            .collect();
+                        let removing: Vec<(Point, Point)> =
+                            ranges.iter().filter_map(Clone::clone).collect();
+                        let mut edited = rope.clone();
+                        let mut moved = remove_ranges(&mut edited, &removing).into_iter();
+                        let positions: Vec<Point> = window
+                            .cursors_mut()
+                            .zip(&ranges)
+                            .map(|(cursor, range)| match range {
+                                Some(_) => moved.next().unwrap_or_else(|| cursor.position.clone()),
+                                None => cursor.position.clone(),
+                            })
+                            .collect();
+                        place_cursors(window, positions);
+                        scroll_vertically(window, region_size.h as usize);
+                        if !removing.is_empty() {
+                            send_cmd(*client_index, DeleteCharRanges(removing));
+                        }
+                    }
                     _ => {
+                        let cursor = &mut window.cursor;
                         match dir {
                             DeleteDirection::After => {
                                 send_cmd(
@@ -303,11 +569,13 @@ pub fn update(
                             DeleteDirection::Before => {
                                 let Cursor { position, .. } = cursor;
                                 let delete_point = get_point_to_left(&position, rope);
-                                send_cmd(*client_index, MoveCursor(Direction::Left, false));
-                                send_cmd(
-                                    *client_index,
-                                    DeleteCharRange(delete_point.clone(), delete_point),
-                                );
+                                if delete_point != *position {
+                                    send_cmd(*client_index, MoveCursor(Direction::Left, false));
+                                    send_cmd(
+                                        *client_index,
+                                        DeleteCharRange(delete_point.clone(), delete_point),
+                                    );
+                                }
                             }
                         }
                         send_cmd(*client_index, BufferModified);
@@ -319,36 +587,59 @@ pub fn update(
                         cursor.stored_x = cursor.position.x;
                     }
                 },
+                Jump(JumpType::Position(point)) => {
+                    window.cursor.position = point.clone();
+                    if !mode.is_visual() {
+                        window.cursor.selection_anchor = None;
+                    }
+                    window.clamp_to(rope);
+                    window.cursor.position.x = std::cmp::max(window.cursor.position.x, 1);
+                    window.cursor.stored_x = window.cursor.position.x;
+                    center_if_hidden(window, region_size.h as usize);
+                }
+                Jump(JumpType::BeginningOfBuffer) | Jump(JumpType::EndOfBuffer) => {
+                    window
+                        .jumps
+                        .push(window.buffer, window.cursor.position.clone());
+                    let line = if *cmd == Jump(JumpType::BeginningOfBuffer) {
+                        0
+                    } else {
+                        rope.len_lines().saturating_sub(2)
+                    };
+                    window.extra_cursors.clear();
+                    window.cursor.position = Point {
+                        x: 1,
+                        y: line as u16,
+                    };
+                    window.cursor.stored_x = 1;
+                    if !mode.is_visual() {
+                        window.cursor.selection_anchor = None;
+                    }
+                    window.clamp_to(rope);
+                    center_if_hidden(window, region_size.h as usize);
+                }
                 Jump(jump_type) => {
                     use JumpType::*;
-                    match jump_type {
-                        EndOfLine => {
-                            let mut position = &mut cursor.position;
-                            position.x = global_data.buffers
-                                [global_data.clients[*client_index].buffer]
-                                .rope
-                                .line(position.y as usize)
-                                .len_chars() as u16
-                        }
-                        StartOfLine => {
-                            cursor.position.x = 1;
+                    for cursor in window.cursors_mut() {
+                        match jump_type {
+                            EndOfLine => {
+                                let position = &mut cursor.position;
+                                position.x = rope.line(position.y as usize).len_chars() as u16
+                            }
+                            StartOfLine => {
+                                cursor.position.x = 1;
+                            }
+                            _ => {}
                         }
-                        _ => {}
-                    }
-                }
-                Yank => {
-                    if let Some(ref selection_anchor) = cursor.selection_anchor {
-                        let slice =
-                            rope.slice(get_char_range(&cursor.position, selection_anchor, rope));
-                        let string = String::from(slice);
-                        send_cmd(*client_index, YankValue(string));
                     }
+                    window.dedup_cursors();
                 }
                 Paste => {
-                    send_cmd(*client_index, PasteAtPoint(cursor.position.clone()));
+                    send_cmd(*client_index, PasteAtPoint(window.cursor.position.clone()));
                 }
                 _ => {}
             }
+            scroll_horizontally(window, text_width, side_scroll_margin.max(0) as usize);
         }
         _ => {}
     };
@@ -358,7 +649,17 @@ pub fn update(
 use std::ffi::c_void;
 
 #[no_mangle]
-pub fn init() -> *mut c_void {
+pub fn init(global_data: &mut GlobalData) -> *mut c_void {
+    // Columns kept visible between the cursor and the left/right edge before scrolling sideways
+    global_data.options.declare(OptionSpec {
+        abbreviation: Some("siso".into()),
+        ..OptionSpec::new("sidescrolloff", OptionScope::Global, OptionValue::Int(5))
+    });
+    // Columns `>` and `<` shift lines by
+    global_data.options.declare(OptionSpec {
+        abbreviation: Some("sw".into()),
+        ..OptionSpec::new("shiftwidth", OptionScope::Buffer, OptionValue::Int(4))
+    });
     Box::into_raw(Box::new(State::default())) as *mut c_void
 }
 
@@ -0,0 +1,137 @@
+use ropey::Rope;
+use types::{
+    is_mark_name, BufferIndex, ClientIndex, Cmd, Cursor, GlobalData, Mode, NotifyLevel, Point,
+};
+
+use crate::center_if_hidden;
+
+// Column of the first char on `line` that isn't a space or a tab
+fn first_non_blank(rope: &Rope, line: usize) -> u16 {
+    let blanks = rope
+        .line(line)
+        .chars()
+        .take_while(|c| *c == ' ' || *c == '\t')
+        .count();
+    blanks as u16 + 1
+}
+
+// Puts the cursor on `point` in `buffer`, showing it in the window when it isn't yet
+fn go_to(
+    global_data: &mut GlobalData,
+    client_index: ClientIndex,
+    buffer: BufferIndex,
+    point: Point,
+    visible_lines: usize,
+    send_cmd: &Box<Fn(ClientIndex, Cmd)>,
+) {
+    let window = global_data.clients[client_index].window_mut();
+    if window.buffer != buffer {
+        window.switch_buffer(buffer, &global_data.buffers[buffer].rope);
+        send_cmd(client_index, Cmd::BufferLoaded);
+    }
+    window.extra_cursors.clear();
+    window.cursor = Cursor {
+        stored_x: point.x,
+        position: point,
+        selection_anchor: None,
+    };
+    window.clamp_to(&global_data.buffers[buffer].rope);
+    center_if_hidden(window, visible_lines);
+}
+
+/// Handles the commands for marks and the jump list, which can reach into other
+/// buffers than the one shown. Runs before the cursor moves for `cmd`.
+pub fn update_marks(
+    global_data: &mut GlobalData,
+    client_index: ClientIndex,
+    cmd: &Cmd,
+    mode: &Mode,
+    visible_lines: usize,
+    send_cmd: &Box<Fn(ClientIndex, Cmd)>,
+) {
+    use Cmd::*;
+    let buffer = global_data.clients[client_index].buffer();
+    let position = global_data.clients[client_index]
+        .window()
+        .cursor
+        .position
+        .clone();
+    match cmd {
+        // `'<` and `'>` go to the ends of the selection after leaving visual mode
+        ApplyOperator(_) | ChangeMode(_) if mode.is_visual() => {
+            let leaving = match cmd {
+                ChangeMode(new_mode) => !new_mode.is_visual(),
+                _ => true,
+            };
+            let anchor = global_data.clients[client_index]
+                .window()
+                .cursor
+                .selection_anchor
+                .clone();
+            if let (true, Some(anchor)) = (leaving, anchor) {
+                let (start, end) = if (anchor.y, anchor.x) < (position.y, position.x) {
+                    (anchor, position)
+                } else {
+                    (position, anchor)
+                };
+                global_data.set_mark(buffer, '<', start);
+                global_data.set_mark(buffer, '>', end);
+            }
+        }
+        SetMark(name) if is_mark_name(*name) => global_data.set_mark(buffer, *name, position),
+        SetMark(_) => send_cmd(
+            client_index,
+            Notify(NotifyLevel::Error, "Invalid mark name".into()),
+        ),
+        JumpToMark(name, exact) => match global_data.find_mark(buffer, *name) {
+            Some((target, mut point)) => {
+                if !exact {
+                    let rope = &global_data.buffers[target].rope;
+                    let line = std::cmp::min(point.y as usize, rope.len_lines() - 1);
+                    point.x = first_non_blank(rope, line);
+                }
+                global_data.clients[client_index]
+                    .window_mut()
+                    .jumps
+                    .push(buffer, position);
+                go_to(
+                    global_data,
+                    client_index,
+                    target,
+                    point,
+                    visible_lines,
+                    send_cmd,
+                );
+            }
+            None => send_cmd(
+                client_index,
+                Notify(NotifyLevel::Error, "Mark not set".into()),
+            ),
+        },
+        RememberJump => global_data.clients[client_index]
+            .window_mut()
+            .jumps
+            .push(buffer, position),
+        JumpBack | JumpForward => {
+            let jumps = &mut global_data.clients[client_index].window_mut().jumps;
+            let target = if *cmd == JumpBack {
+                jumps.back(buffer, position)
+            } else {
+                jumps.forward()
+            };
+            if let Some((target, point)) = target {
+                if global_data.buffer_keys.contains_key(target) {
+                    go_to(
+                        global_data,
+                        client_index,
+                        target,
+                        point,
+                        visible_lines,
+                        send_cmd,
+                    );
+                }
+            }
+        }
+        _ => {}
+    }
+}
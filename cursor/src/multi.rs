@@ -0,0 +1,154 @@
+use ropey::Rope;
+use types::{char_index, is_word, point_from_char, Cursor, Mode, Point};
+
+use crate::visual::{Selection, SelectionKind};
+
+// Start and end, left out, of the word around `index`
+fn word_bounds(text: &[char], index: usize) -> Option<(usize, usize)> {
+    if !text.get(index).map_or(false, |c| is_word(*c)) {
+        return None;
+    }
+    let start = (0..index)
+        .rev()
+        .take_while(|at| is_word(text[*at]))
+        .last()
+        .unwrap_or(index);
+    let end = (index..text.len())
+        .find(|at| !is_word(text[*at]))
+        .unwrap_or_else(|| text.len());
+    Some((start, end))
+}
+
+// First match of `needle` after the one at `start`, wrapping around to the top
+// and ending up back at `start` when there is no other
+fn find_after(text: &[char], needle: &[char], start: usize, whole_word: bool) -> Option<usize> {
+    if needle.is_empty() || needle.len() > text.len() {
+        return None;
+    }
+    let matches_at = |at: usize| {
+        let end = at + needle.len();
+        text[at..end] == *needle
+            && (!whole_word
+                || ((at == 0 || !is_word(text[at - 1]))
+                    && text.get(end).map_or(true, |c| !is_word(*c))))
+    };
+    let last = text.len() - needle.len();
+    (start + 1..=last)
+        .chain(0..=std::cmp::min(start, last))
+        .find(|at| matches_at(*at))
+}
+
+/// A cursor on the next match after `cursor` of what it selects, or of the word
+/// under it when nothing is selected. The new cursor selects the match the same
+/// way, or sits as far into the word. Only charwise selections are matched.
+pub fn next_match(rope: &Rope, mode: &Mode, cursor: &Cursor) -> Option<Cursor> {
+    let text: Vec<char> = rope.chars().collect();
+    match &cursor.selection_anchor {
+        Some(anchor) => {
+            let selection = Selection::new(mode, anchor, &cursor.position);
+            if selection.kind != SelectionKind::Char {
+                return None;
+            }
+            let needle: Vec<char> = selection.text(rope).chars().collect();
+            let start = char_index(&selection.start(), rope);
+            let found = find_after(&text, &needle, start, false)?;
+            let first = point_from_char(found, rope);
+            let last = point_from_char(found + needle.len() - 1, rope);
+            let (anchor, position) = if selection.start() == cursor.position {
+                (last, first)
+            } else {
+                (first, last)
+            };
+            Some(Cursor {
+                stored_x: position.x,
+                position,
+                selection_anchor: Some(anchor),
+            })
+        }
+        None => {
+            let index = char_index(&cursor.position, rope);
+            let (start, end) = word_bounds(&text, index)?;
+            let found = find_after(&text, &text[start..end], start, true)?;
+            let position = point_from_char(found + index - start, rope);
+            Some(Cursor {
+                stored_x: position.x,
+                position,
+                selection_anchor: None,
+            })
+        }
+    }
+}
+
+/// A cursor on each line of the selection, top to bottom, in the column the
+/// cursor is in or on the left edge of a block
+pub fn cursors_on_lines(rope: &Rope, mode: &Mode, cursor: &Cursor) -> Vec<Cursor> {
+    let anchor = cursor.selection_anchor.as_ref().unwrap_or(&cursor.position);
+    let selection = Selection::new(mode, anchor, &cursor.position);
+    let column = match selection.kind {
+        SelectionKind::Block => selection.start().x,
+        _ => cursor.position.x,
+    };
+    let (first, last) = selection.lines();
+    (first..=last)
+        .map(|line| {
+            let length = std::cmp::max(1, rope.line(line).len_chars() as u16);
+            let position = Point {
+                x: std::cmp::min(column, length),
+                y: line as u16,
+            };
+            Cursor {
+                stored_x: position.x,
+                position,
+                selection_anchor: None,
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cursor(x: u16, y: u16, anchor: Option<(u16, u16)>) -> Cursor {
+        Cursor {
+            position: Point { x, y },
+            stored_x: x,
+            selection_anchor: anchor.map(|(x, y)| Point { x, y }),
+        }
+    }
+
+    #[test]
+    fn test_next_match() {
+        let rope = Rope::from_str("let name = names[0];\nname.len()\n");
+        // Whole words only, keeping the column within the word
+        let found = next_match(&rope, &Mode::Normal, &cursor(6, 0, None)).unwrap();
+        assert_eq!(found.position, Point { x: 2, y: 1 });
+        // Wraps around back to the first one
+        let found = next_match(&rope, &Mode::Normal, &found).unwrap();
+        assert_eq!(found.position, Point { x: 6, y: 0 });
+        // Selections match anywhere
+        let found = next_match(&rope, &Mode::Visual, &cursor(8, 0, Some((5, 0)))).unwrap();
+        assert_eq!(found.selection_anchor, Some(Point { x: 12, y: 0 }));
+        assert_eq!(found.position, Point { x: 15, y: 0 });
+        assert!(next_match(&rope, &Mode::VisualLine, &cursor(8, 0, Some((5, 0)))).is_none());
+        assert!(next_match(&rope, &Mode::Normal, &cursor(4, 0, None)).is_none());
+    }
+
+    #[test]
+    fn test_cursors_on_lines() {
+        let rope = Rope::from_str("a: u8,\nb,\nc: u8,\n");
+        let positions: Vec<Point> =
+            cursors_on_lines(&rope, &Mode::Visual, &cursor(4, 2, Some((1, 0))))
+                .into_iter()
+                .map(|cursor| cursor.position)
+                .collect();
+        assert_eq!(
+            positions,
+            vec![
+                Point { x: 4, y: 0 },
+                Point { x: 3, y: 1 },
+                Point { x: 4, y: 2 }
+            ]
+        );
+    }
+}
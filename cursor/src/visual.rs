@@ -0,0 +1,243 @@
+use ropey::Rope;
+use types::{char_index, Mode, Operator, Point};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SelectionKind {
+    Char,
+    Line,
+    Block,
+}
+
+/// The text between the anchor and the cursor, shaped by the visual mode
+#[derive(Debug, Clone)]
+pub struct Selection {
+    pub kind: SelectionKind,
+    pub anchor: Point,
+    pub position: Point,
+}
+
+// Chars on the line without its line break
+fn text_length(rope: &Rope, line: usize) -> usize {
+    rope.line(line)
+        .chars()
+        .filter(|c| *c != '\n' && *c != '\r')
+        .count()
+}
+
+impl Selection {
+    /// The selection visual `mode` makes, charwise for the Shift-HJKL one outside of it
+    pub fn new(mode: &Mode, anchor: &Point, position: &Point) -> Selection {
+        let kind = match mode {
+            Mode::VisualLine => SelectionKind::Line,
+            Mode::VisualBlock => SelectionKind::Block,
+            _ => SelectionKind::Char,
+        };
+        Selection {
+            kind,
+            anchor: anchor.clone(),
+            position: position.clone(),
+        }
+    }
+
+    pub fn lines(&self) -> (usize, usize) {
+        let (first, last) = (self.anchor.y as usize, self.position.y as usize);
+        (std::cmp::min(first, last), std::cmp::max(first, last))
+    }
+
+    /// Where the cursor goes after an operator, the top left of the selection
+    pub fn start(&self) -> Point {
+        let (first, _) = self.lines();
+        match self.kind {
+            SelectionKind::Char => {
+                if (self.anchor.y, self.anchor.x) < (self.position.y, self.position.x) {
+                    self.anchor.clone()
+                } else {
+                    self.position.clone()
+                }
+            }
+            SelectionKind::Line => Point {
+                x: 1,
+                y: first as u16,
+            },
+            SelectionKind::Block => Point {
+                x: std::cmp::min(self.anchor.x, self.position.x),
+                y: first as u16,
+            },
+        }
+    }
+
+    /// Chars selected on `line`, as offsets from its start with the end left out.
+    /// Charwise and linewise selections take in the line break, blockwise ones stop
+    /// at the end of the text.
+    pub fn span(&self, rope: &Rope, line: usize) -> (usize, usize) {
+        let line_start = rope.line_to_char(line);
+        let line_length = rope.line(line).len_chars();
+        match self.kind {
+            SelectionKind::Char => {
+                let (start, end) = {
+                    let (a, b) = (
+                        char_index(&self.anchor, rope),
+                        char_index(&self.position, rope),
+                    );
+                    (std::cmp::min(a, b), std::cmp::max(a, b))
+                };
+                let from = std::cmp::max(start, line_start) - line_start;
+                let to = std::cmp::min(end + 1, line_start + line_length);
+                (from, to.saturating_sub(line_start).max(from))
+            }
+            SelectionKind::Line => (0, line_length),
+            SelectionKind::Block => {
+                let left = std::cmp::min(self.anchor.x, self.position.x) as usize;
+                let right = std::cmp::max(self.anchor.x, self.position.x) as usize;
+                let length = text_length(rope, line);
+                let from = std::cmp::min(left.saturating_sub(1), length);
+                (from, std::cmp::min(right, length))
+            }
+        }
+    }
+
+    /// What a yank of the selection holds. Each line of a block goes on a line of its own.
+    pub fn text(&self, rope: &Rope) -> String {
+        let (first, last) = self.lines();
+        let parts: Vec<String> = (first..=last)
+            .map(|line| {
+                let (from, to) = self.span(rope, line);
+                rope.line(line).slice(from..to).to_string()
+            })
+            .collect();
+        match self.kind {
+            SelectionKind::Block => parts.join("\n"),
+            _ => parts.concat(),
+        }
+    }
+}
+
+fn toggle_case(text: &str) -> String {
+    text.chars()
+        .flat_map(|c| {
+            let toggled: Vec<char> = if c.is_lowercase() {
+                c.to_uppercase().collect()
+            } else {
+                c.to_lowercase().collect()
+            };
+            toggled
+        })
+        .collect()
+}
+
+fn outdent(line: &str, shiftwidth: usize) -> &str {
+    if line.starts_with('\t') {
+        return &line[1..];
+    }
+    let spaces = line
+        .chars()
+        .take(shiftwidth)
+        .take_while(|c| *c == ' ')
+        .count();
+    &line[spaces..]
+}
+
+/// The text the selected lines have after `operator`, `None` when it leaves them be
+pub fn apply_operator(
+    rope: &Rope,
+    selection: &Selection,
+    operator: &Operator,
+    shiftwidth: usize,
+) -> Option<String> {
+    let (first, last) = selection.lines();
+    let mut text = String::new();
+    for line in first..=last {
+        let line_text = rope.line(line).to_string();
+        match operator {
+            Operator::Yank => return None,
+            Operator::Indent => {
+                // Blank lines are left without trailing spaces
+                if !line_text.trim().is_empty() {
+                    text += &" ".repeat(shiftwidth);
+                }
+                text += &line_text;
+            }
+            Operator::Outdent => text += outdent(&line_text, shiftwidth),
+            Operator::Delete | Operator::Change | Operator::ToggleCase => {
+                let (from, to) = selection.span(rope, line);
+                let chars: Vec<char> = line_text.chars().collect();
+                text.extend(&chars[..from]);
+                if *operator == Operator::ToggleCase {
+                    text += &toggle_case(&chars[from..to].iter().collect::<String>());
+                }
+                text.extend(&chars[to..]);
+            }
+        }
+    }
+    Some(text)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn selection(mode: Mode, anchor: (u16, u16), position: (u16, u16)) -> Selection {
+        Selection::new(
+            &mode,
+            &Point {
+                x: anchor.0,
+                y: anchor.1,
+            },
+            &Point {
+                x: position.0,
+                y: position.1,
+            },
+        )
+    }
+
+    #[test]
+    fn test_charwise() {
+        let rope = Rope::from_str("first line\nsecond line\nthird\n");
+        // Backwards from the 'l' of "line" on the second line to the 'l' on the first
+        let selected = selection(Mode::Visual, (8, 1), (7, 0));
+        assert_eq!(selected.text(&rope), "line\nsecond l");
+        assert_eq!(selected.start(), Point { x: 7, y: 0 });
+        assert_eq!(
+            apply_operator(&rope, &selected, &Operator::Delete, 4),
+            Some("first ine\n".into())
+        );
+        assert_eq!(
+            apply_operator(&rope, &selected, &Operator::ToggleCase, 4),
+            Some("first LINE\nSECOND Line\n".into())
+        );
+        assert_eq!(apply_operator(&rope, &selected, &Operator::Yank, 4), None);
+    }
+
+    #[test]
+    fn test_linewise() {
+        let rope = Rope::from_str("fn main() {\n\n    go();\n}\n");
+        let selected = selection(Mode::VisualLine, (3, 2), (5, 0));
+        assert_eq!(selected.text(&rope), "fn main() {\n\n    go();\n");
+        assert_eq!(selected.start(), Point { x: 1, y: 0 });
+        assert_eq!(
+            apply_operator(&rope, &selected, &Operator::Delete, 4),
+            Some("".into())
+        );
+        assert_eq!(
+            apply_operator(&rope, &selected, &Operator::Indent, 4),
+            Some("    fn main() {\n\n        go();\n".into())
+        );
+        assert_eq!(
+            apply_operator(&rope, &selected, &Operator::Outdent, 4),
+            Some("fn main() {\n\ngo();\n".into())
+        );
+    }
+
+    #[test]
+    fn test_blockwise() {
+        let rope = Rope::from_str("abcdef\nab\nabcdef\n");
+        let selected = selection(Mode::VisualBlock, (5, 2), (2, 0));
+        assert_eq!(selected.span(&rope, 1), (1, 2));
+        assert_eq!(selected.text(&rope), "bcde\nb\nbcde");
+        assert_eq!(selected.start(), Point { x: 2, y: 0 });
+        assert_eq!(
+            apply_operator(&rope, &selected, &Operator::Delete, 4),
+            Some("af\na\naf\n".into())
+        );
+    }
+}
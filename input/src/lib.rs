@@ -1,13 +1,132 @@
 use std::ffi::c_void;
 use termion::event::{Event, Key};
 use types::{
-    BackBuffer, ClientIndex, Cmd, DeleteDirection, Direction, GlobalData, JumpType, Mode, Msg,
-    Utils,
+    BackBuffer, ClientIndex, Cmd, DeleteDirection, Direction, GlobalData, JumpType, LineEdit, Mode,
+    Msg, Operator, PickerSource, SplitDirection, TextObject, TextObjectKind, Utils,
 };
 
 #[derive(Debug, Default)]
 struct Data {
-    value: u8,
+    // First key of a two key binding such as Ctrl-w h or gt
+    pending_key: Option<Key>,
+    // Operator typed in normal mode, waiting for the text object to run on
+    pending_operator: Option<Operator>,
+}
+
+fn handle_picker_key(client: ClientIndex, key: &Key, send_cmd: &Box<Fn(ClientIndex, Cmd)>) {
+    match key {
+        Key::Esc => send_cmd(client, Cmd::ChangeMode(Mode::Normal)),
+        Key::Char('\n') => send_cmd(client, Cmd::PickerAccept),
+        Key::Char(c) => send_cmd(client, Cmd::PickerInsertChar(*c)),
+        Key::Backspace => send_cmd(client, Cmd::PickerDeleteChar),
+        Key::Up | Key::Ctrl('p') | Key::Ctrl('k') => send_cmd(client, Cmd::PickerMove(-1)),
+        Key::Down | Key::Ctrl('n') | Key::Ctrl('j') => send_cmd(client, Cmd::PickerMove(1)),
+        Key::PageUp => send_cmd(client, Cmd::PickerMove(-10)),
+        Key::PageDown => send_cmd(client, Cmd::PickerMove(10)),
+        _ => {}
+    }
+}
+
+fn handle_command_line_key(client: ClientIndex, key: &Key, send_cmd: &Box<Fn(ClientIndex, Cmd)>) {
+    match key {
+        Key::Char('\n') => send_cmd(client, Cmd::RunCommand),
+        Key::Char('\t') => send_cmd(client, Cmd::CompleteCommand),
+        Key::Char(c) => send_cmd(client, Cmd::InsertChar(*c)),
+        Key::Backspace => send_cmd(client, Cmd::DeleteChar(DeleteDirection::Before)),
+        Key::Delete | Key::Ctrl('d') => send_cmd(client, Cmd::DeleteChar(DeleteDirection::After)),
+        Key::Left | Key::Ctrl('b') => send_cmd(client, Cmd::MoveCursor(Direction::Left, false)),
+        Key::Right | Key::Ctrl('f') => send_cmd(client, Cmd::MoveCursor(Direction::Right, false)),
+        Key::Home | Key::Ctrl('a') => send_cmd(client, Cmd::CommandLineEdit(LineEdit::StartOfLine)),
+        Key::End | Key::Ctrl('e') => send_cmd(client, Cmd::CommandLineEdit(LineEdit::EndOfLine)),
+        Key::Ctrl('w') => send_cmd(client, Cmd::CommandLineEdit(LineEdit::DeleteWordBefore)),
+        Key::Ctrl('u') => send_cmd(client, Cmd::CommandLineEdit(LineEdit::DeleteToStart)),
+        Key::Up | Key::Ctrl('p') => send_cmd(client, Cmd::CommandHistory(true)),
+        Key::Down | Key::Ctrl('n') => send_cmd(client, Cmd::CommandHistory(false)),
+        Key::Esc => send_cmd(client, Cmd::ChangeMode(Mode::Normal)),
+        _ => {}
+    }
+}
+
+// Motions extend the selection, operators act on it and go back to normal mode
+fn handle_visual_key(
+    client: ClientIndex,
+    mode: &Mode,
+    key: &Key,
+    send_cmd: &Box<Fn(ClientIndex, Cmd)>,
+) {
+    // The key for the current visual mode leaves it, the others switch to theirs
+    let switch = |target: Mode| {
+        let next = if *mode == target {
+            Mode::Normal
+        } else {
+            target
+        };
+        send_cmd(client, Cmd::ChangeMode(next))
+    };
+    let operate = |operator: Operator| send_cmd(client, Cmd::ApplyOperator(operator));
+    match key {
+        Key::Esc => send_cmd(client, Cmd::ChangeMode(Mode::Normal)),
+        Key::Char('v') => switch(Mode::Visual),
+        Key::Char('V') => switch(Mode::VisualLine),
+        Key::Ctrl('v') => switch(Mode::VisualBlock),
+        Key::Char('h') | Key::Char('H') => send_cmd(client, Cmd::MoveCursor(Direction::Left, true)),
+        Key::Char('j') | Key::Char('J') => send_cmd(client, Cmd::MoveCursor(Direction::Down, true)),
+        Key::Char('k') | Key::Char('K') => send_cmd(client, Cmd::MoveCursor(Direction::Up, true)),
+        Key::Char('l') | Key::Char('L') => {
+            send_cmd(client, Cmd::MoveCursor(Direction::Right, true))
+        }
+        Key::Char('0') => send_cmd(client, Cmd::Jump(JumpType::StartOfLine)),
+        Key::Char('$') => send_cmd(client, Cmd::Jump(JumpType::EndOfLine)),
+        Key::Char('G') => send_cmd(client, Cmd::Jump(JumpType::EndOfBuffer)),
+        Key::Char('n') => send_cmd(client, Cmd::SearchNext(false)),
+        Key::Char('N') => send_cmd(client, Cmd::SearchNext(true)),
+        Key::Char('o') => send_cmd(client, Cmd::SwapSelectionEnds),
+        // A cursor on every line, typing then goes to all of them
+        Key::Char('I') => {
+            send_cmd(client, Cmd::AddCursorsOnLines);
+            send_cmd(client, Cmd::ChangeMode(Mode::Insert));
+        }
+        Key::Ctrl('n') => send_cmd(client, Cmd::AddCursorAtNextMatch),
+        Key::Ctrl('x') => send_cmd(client, Cmd::SkipCursor),
+        Key::Alt('n') => send_cmd(client, Cmd::RemoveCursor),
+        Key::Char('d') | Key::Char('x') | Key::Delete => operate(Operator::Delete),
+        Key::Char('y') => operate(Operator::Yank),
+        Key::Char('c') | Key::Char('s') => operate(Operator::Change),
+        Key::Char('>') => operate(Operator::Indent),
+        Key::Char('<') => operate(Operator::Outdent),
+        Key::Char('~') => operate(Operator::ToggleCase),
+        Key::Char(':') => send_cmd(client, Cmd::ChangeMode(Mode::Command)),
+        _ => {}
+    }
+}
+
+fn handle_window_key(client: ClientIndex, key: &Key, send_cmd: &Box<Fn(ClientIndex, Cmd)>) {
+    match key {
+        Key::Char('h') | Key::Ctrl('h') | Key::Left => {
+            send_cmd(client, Cmd::FocusWindow(Direction::Left))
+        }
+        Key::Char('j') | Key::Ctrl('j') | Key::Down => {
+            send_cmd(client, Cmd::FocusWindow(Direction::Down))
+        }
+        Key::Char('k') | Key::Ctrl('k') | Key::Up => {
+            send_cmd(client, Cmd::FocusWindow(Direction::Up))
+        }
+        Key::Char('l') | Key::Ctrl('l') | Key::Right => {
+            send_cmd(client, Cmd::FocusWindow(Direction::Right))
+        }
+        Key::Char('s') | Key::Ctrl('s') => {
+            send_cmd(client, Cmd::SplitWindow(SplitDirection::Horizontal))
+        }
+        Key::Char('v') | Key::Ctrl('v') => {
+            send_cmd(client, Cmd::SplitWindow(SplitDirection::Vertical))
+        }
+        Key::Char('c') | Key::Char('q') => send_cmd(client, Cmd::CloseWindow),
+        Key::Char('+') => send_cmd(client, Cmd::ResizeWindow(SplitDirection::Horizontal, 1)),
+        Key::Char('-') => send_cmd(client, Cmd::ResizeWindow(SplitDirection::Horizontal, -1)),
+        Key::Char('>') => send_cmd(client, Cmd::ResizeWindow(SplitDirection::Vertical, 1)),
+        Key::Char('<') => send_cmd(client, Cmd::ResizeWindow(SplitDirection::Vertical, -1)),
+        _ => {}
+    }
 }
 
 #[no_mangle]
@@ -19,9 +138,93 @@ pub fn update(
     msg: &Msg,
     _utils: &Utils,
     send_cmd: &Box<Fn(ClientIndex, Cmd)>,
+    data_ptr: *mut c_void,
 ) {
+    let mut data: Box<Data> = unsafe { Box::from_raw(data_ptr as *mut Data) };
     match msg {
         Msg::StdinEvent(client, evt) => {
+            if let Some(pending_key) = data.pending_key.take() {
+                match (pending_key, evt) {
+                    (Key::Ctrl('w'), Event::Key(key)) => handle_window_key(*client, key, send_cmd),
+                    (Key::Char('g'), Event::Key(Key::Char('t'))) => send_cmd(*client, Cmd::NextTab),
+                    (Key::Char('g'), Event::Key(Key::Char('T'))) => {
+                        send_cmd(*client, Cmd::PreviousTab)
+                    }
+                    (Key::Char('g'), Event::Key(Key::Char('g'))) => {
+                        send_cmd(*client, Cmd::Jump(JumpType::BeginningOfBuffer))
+                    }
+                    (Key::Char('g'), Event::Key(Key::Char('d'))) => {
+                        send_cmd(*client, Cmd::GotoDefinition)
+                    }
+                    (Key::Char('m'), Event::Key(Key::Char(c))) => {
+                        send_cmd(*client, Cmd::SetMark(*c))
+                    }
+                    // `'` goes to the first non-blank of the mark's line, `` ` `` to its column
+                    (Key::Char(prefix), Event::Key(Key::Char(c)))
+                        if prefix == '\'' || prefix == '`' =>
+                    {
+                        send_cmd(*client, Cmd::JumpToMark(*c, prefix == '`'))
+                    }
+                    (Key::Char(prefix), Event::Key(Key::Char(c)))
+                        if prefix == 'i' || prefix == 'a' =>
+                    {
+                        if let Some(kind) = TextObjectKind::from_key(*c) {
+                            let object = TextObject {
+                                kind,
+                                around: prefix == 'a',
+                            };
+                            let operator = data.pending_operator.take();
+                            send_cmd(*client, Cmd::SelectObject(object, operator));
+                        }
+                    }
+                    _ => {}
+                }
+                data.pending_operator = None;
+                std::mem::forget(data);
+                return;
+            }
+            if let Some(operator) = data.pending_operator.take() {
+                if let Event::Key(Key::Char(c)) = evt {
+                    if *c == 'i' || *c == 'a' {
+                        data.pending_key = Some(Key::Char(*c));
+                        data.pending_operator = Some(operator);
+                    }
+                }
+                std::mem::forget(data);
+                return;
+            }
+            if global_data.clients[*client].mode == Mode::Picker {
+                if let Event::Key(key) = evt {
+                    handle_picker_key(*client, key, send_cmd);
+                }
+                std::mem::forget(data);
+                return;
+            }
+            if global_data.clients[*client].mode == Mode::Command {
+                if let Event::Key(key) = evt {
+                    handle_command_line_key(*client, key, send_cmd);
+                }
+                std::mem::forget(data);
+                return;
+            }
+            if global_data.clients[*client].mode == Mode::Confirm {
+                match evt {
+                    Event::Key(Key::Esc) => send_cmd(*client, Cmd::Confirm('q')),
+                    Event::Key(Key::Char(c)) => send_cmd(*client, Cmd::Confirm(*c)),
+                    _ => {}
+                }
+                std::mem::forget(data);
+                return;
+            }
+            // Keymaps from the config come before the built in bindings
+            if let Event::Key(key) = evt {
+                let mode = &global_data.clients[*client].mode;
+                if let Some(line) = global_data.keymaps.get(mode, key) {
+                    send_cmd(*client, Cmd::RunCommandLine(line.into()));
+                    std::mem::forget(data);
+                    return;
+                }
+            }
             // Same for all modes
             match evt {
                 Event::Key(k) => match k {
@@ -33,7 +236,8 @@ pub fn update(
                 },
                 _ => {}
             }
-            match global_data.clients[*client].mode {
+            let mode = global_data.clients[*client].mode.clone();
+            match mode {
                 Mode::Normal => match evt {
                     Event::Key(Key::Char(c)) => match c {
                         'i' => send_cmd(*client, Cmd::ChangeMode(Mode::Insert)),
@@ -41,11 +245,24 @@ pub fn update(
                         'h' => send_cmd(*client, Cmd::MoveCursor(Direction::Left, false)),
                         'j' => send_cmd(*client, Cmd::MoveCursor(Direction::Down, false)),
                         'k' => send_cmd(*client, Cmd::MoveCursor(Direction::Up, false)),
-                        'L' => send_cmd(*client, Cmd::MoveCursor(Direction::Right, true)),
-                        'H' => send_cmd(*client, Cmd::MoveCursor(Direction::Left, true)),
-                        'J' => send_cmd(*client, Cmd::MoveCursor(Direction::Down, true)),
-                        'K' => send_cmd(*client, Cmd::MoveCursor(Direction::Up, true)),
-                        'd' => send_cmd(*client, Cmd::DeleteChar(DeleteDirection::After)),
+                        // Shift with a direction starts a selection, same as v and then moving
+                        'L' | 'H' | 'J' | 'K' => {
+                            let direction = match c {
+                                'L' => Direction::Right,
+                                'H' => Direction::Left,
+                                'J' => Direction::Down,
+                                _ => Direction::Up,
+                            };
+                            send_cmd(*client, Cmd::ChangeMode(Mode::Visual));
+                            send_cmd(*client, Cmd::MoveCursor(direction, true));
+                        }
+                        'v' => send_cmd(*client, Cmd::ChangeMode(Mode::Visual)),
+                        'V' => send_cmd(*client, Cmd::ChangeMode(Mode::VisualLine)),
+                        'x' => send_cmd(*client, Cmd::DeleteChar(DeleteDirection::After)),
+                        // Operators run on the text object typed next, as in `diw` or `ca(`
+                        'd' => data.pending_operator = Some(Operator::Delete),
+                        'c' => data.pending_operator = Some(Operator::Change),
+                        'y' => data.pending_operator = Some(Operator::Yank),
                         'a' => {
                             send_cmd(*client, Cmd::MoveCursor(Direction::Right, false));
                             send_cmd(*client, Cmd::ChangeMode(Mode::Insert));
@@ -61,18 +278,35 @@ pub fn update(
                         ':' => {
                             send_cmd(*client, Cmd::ChangeMode(Mode::Command));
                         }
-                        'y' => {
-                            send_cmd(*client, Cmd::Yank);
-                        }
+                        '/' => send_cmd(*client, Cmd::StartSearch(false)),
+                        '?' => send_cmd(*client, Cmd::StartSearch(true)),
+                        'n' => send_cmd(*client, Cmd::SearchNext(false)),
+                        'N' => send_cmd(*client, Cmd::SearchNext(true)),
+                        '*' => send_cmd(*client, Cmd::SearchWord(false)),
+                        '#' => send_cmd(*client, Cmd::SearchWord(true)),
                         'p' => {
                             send_cmd(*client, Cmd::Paste);
                         }
+                        'g' => data.pending_key = Some(Key::Char('g')),
+                        'G' => send_cmd(*client, Cmd::Jump(JumpType::EndOfBuffer)),
+                        'm' | '\'' | '`' => data.pending_key = Some(Key::Char(*c)),
+                        // Terminals send Ctrl-i as a tab
+                        '\t' => send_cmd(*client, Cmd::JumpForward),
                         _ => {}
                     },
                     Event::Key(Key::Ctrl(c)) => match c {
-                        'p' => send_cmd(*client, Cmd::SearchFiles),
+                        'p' => send_cmd(*client, Cmd::Pick(PickerSource::Files)),
+                        'w' => data.pending_key = Some(Key::Ctrl('w')),
+                        'v' => send_cmd(*client, Cmd::ChangeMode(Mode::VisualBlock)),
+                        'n' => send_cmd(*client, Cmd::AddCursorAtNextMatch),
+                        'x' => send_cmd(*client, Cmd::SkipCursor),
+                        'o' => send_cmd(*client, Cmd::JumpBack),
+                        // Terminals send Ctrl-^ as Ctrl-6
+                        '^' | '6' => send_cmd(*client, Cmd::AlternateBuffer),
                         _ => {}
                     },
+                    Event::Key(Key::Alt('n')) => send_cmd(*client, Cmd::RemoveCursor),
+                    Event::Key(Key::Esc) => send_cmd(*client, Cmd::RemoveExtraCursors),
                     _ => {}
                 },
                 Mode::Insert => match evt {
@@ -86,22 +320,21 @@ pub fn update(
                     },
                     _ => {}
                 },
-                Mode::Command => match evt {
-                    Event::Key(key) => match key {
-                        Key::Char('\n') => send_cmd(*client, Cmd::RunCommand),
-                        Key::Char(c) => send_cmd(*client, Cmd::InsertChar(*c)),
-                        Key::Backspace => {
-                            send_cmd(*client, Cmd::DeleteChar(DeleteDirection::Before))
-                        }
-                        Key::Esc => send_cmd(*client, Cmd::ChangeMode(Mode::Normal)),
-                        _ => {}
-                    },
+                Mode::Visual | Mode::VisualLine | Mode::VisualBlock => match evt {
+                    // Selects a text object instead, as in `viw`, or goes on with `gg` and the like
+                    Event::Key(Key::Char(c)) if *c == 'i' || *c == 'a' || *c == 'g' => {
+                        data.pending_key = Some(Key::Char(*c))
+                    }
+                    Event::Key(key) => handle_visual_key(*client, &mode, key, send_cmd),
                     _ => {}
                 },
+                // Handled before anything else above
+                Mode::Picker | Mode::Command | Mode::Confirm => {}
             }
         }
         _ => {}
     }
+    std::mem::forget(data);
 }
 
 #[no_mangle]
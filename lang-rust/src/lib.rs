@@ -1,14 +1,16 @@
-use ra_ide_api::{Analysis, AnalysisChange, AnalysisHost, FileId, HighlightedRange, SourceRootId};
-use ra_syntax::TextRange;
+use ra_ide_api::{
+    Analysis, AnalysisChange, AnalysisHost, FileId, FilePosition, HighlightedRange, Severity,
+    SourceRootId,
+};
+use ra_syntax::{ast, AstNode, SyntaxKind, SyntaxNode, TextRange, TextUnit};
 use relative_path::RelativePathBuf;
 use types::{
-    BackBuffer, Buffer, BufferIndex, Client, ClientIndex, Cmd, DeleteDirection, Direction,
-    GlobalData, JumpType, KeyData, Mode, Msg, Point, Rect, SecondaryMap, Utils,
+    inside_block, BackBuffer, Buffer, BufferIndex, Client, ClientIndex, Cmd, DeleteDirection,
+    DiagnosticCounts, Direction, GlobalData, GutterLayout, JumpType, KeyData, Mode, Msg,
+    NotifyLevel, Picker, PickerItem, PickerSource, Point, Rect, Rope, SecondaryMap, TextObject,
+    TextObjectKind, Theme, Utils, Window, WindowRegion,
 };
 
-mod colors;
-use colors::{get_color_from_severity, get_color_from_tag};
-
 #[derive(Debug, Default)]
 pub struct Cursor {
     pub position: Point,
@@ -18,6 +20,8 @@ pub struct Cursor {
 #[derive(Debug)]
 struct State {
     analysisHost: AnalysisHost,
+    /// Path each buffer was added to the analysis under
+    files: SecondaryMap<BufferIndex, RelativePathBuf>,
 }
 
 fn file_id_from_buffer_index(buffer_index: BufferIndex) -> FileId {
@@ -30,77 +34,312 @@ impl State {
         let mut add_root = AnalysisChange::new();
         add_root.add_root(SourceRootId(0), true);
         analysisHost.apply_change(add_root);
+        let mut state = State {
+            analysisHost,
+            files: SecondaryMap::new(),
+        };
         for buffer_index in global_data.buffer_keys.keys() {
-            let buffer = &global_data.buffers[buffer_index];
-            analysisHost.apply_change({
-                let mut change = AnalysisChange::new();
-                change.add_file(
-                    SourceRootId(0),
-                    file_id_from_buffer_index(buffer_index),
-                    RelativePathBuf::from_path(buffer.source.as_path())
-                        .expect("building relative path"),
-                    std::sync::Arc::new(String::from(buffer.rope.clone())),
-                );
-                change
-            });
+            state.add_file(buffer_index, &global_data.buffers[buffer_index]);
+        }
+        state
+    }
+
+    fn add_file(&mut self, buffer_index: BufferIndex, buffer: &Buffer) {
+        let path =
+            RelativePathBuf::from_path(buffer.source.as_path()).expect("building relative path");
+        self.analysisHost.apply_change({
+            let mut change = AnalysisChange::new();
+            change.add_file(
+                SourceRootId(0),
+                file_id_from_buffer_index(buffer_index),
+                path.clone(),
+                std::sync::Arc::new(String::from(buffer.rope.clone())),
+            );
+            change
+        });
+        self.files.insert(buffer_index, path);
+    }
+}
+
+// Keeps the counts shown in the status line in sync with the latest analysis
+fn update_diagnostic_counts(analysis: &Analysis, file_id: FileId, buffer: &mut Buffer) {
+    if let Ok(diagnostics) = analysis.diagnostics(file_id) {
+        let mut counts = DiagnosticCounts::default();
+        for diagnostic in diagnostics {
+            match diagnostic.severity {
+                Severity::Error => counts.errors += 1,
+                Severity::WeakWarning => counts.warnings += 1,
+            }
+        }
+        buffer.diagnostics = counts;
+    }
+}
+
+// Cursor position of a byte offset from the syntax tree, which may be stale
+// and point past the end of the text
+fn point_from_offset(rope: &Rope, offset: usize) -> Point {
+    let index = rope.byte_to_char(std::cmp::min(offset, rope.len_bytes()));
+    let line = rope.char_to_line(index);
+    Point {
+        y: line as u16,
+        x: (index - rope.line_to_char(line)) as u16 + 1,
+    }
+}
+
+// Where a picked diagnostic or symbol puts the cursor
+fn jump_to_offset(rope: &Rope, offset: usize) -> Cmd {
+    Cmd::Jump(JumpType::Position(point_from_offset(rope, offset)))
+}
+
+// Chars covered by a range of the syntax tree, which counts bytes
+fn char_range(rope: &Rope, range: TextRange) -> (usize, usize) {
+    (
+        rope.byte_to_char(range.start().to_usize()),
+        rope.byte_to_char(range.end().to_usize()),
+    )
+}
+
+// Smallest node around `offset` that `wanted` accepts
+fn enclosing_node(
+    root: &SyntaxNode,
+    offset: TextUnit,
+    wanted: impl Fn(&SyntaxNode) -> bool,
+) -> Option<SyntaxNode> {
+    root.descendants()
+        .filter(|node| node.text_range().contains_inclusive(offset) && wanted(node))
+        .last()
+}
+
+// An argument with the comma after it, or the one before it when it comes last
+fn with_separator(text: &[char], start: usize, end: usize) -> (usize, usize) {
+    let mut after = end + 1;
+    while after < text.len() && text[after].is_whitespace() {
+        after += 1;
+    }
+    if text.get(after) == Some(&',') {
+        let mut last = after;
+        while last + 1 < text.len() && (text[last + 1] == ' ' || text[last + 1] == '\t') {
+            last += 1;
+        }
+        return (start, last);
+    }
+    let mut before = start;
+    while before > 0 && text[before - 1].is_whitespace() {
+        before -= 1;
+    }
+    if before > 0 && text[before - 1] == ',' {
+        (before - 1, end)
+    } else {
+        (start, end)
+    }
+}
+
+/// First and last char of the function, impl block, argument or expression
+/// around `index`. The inside of a function or impl block is what is between
+/// its braces.
+fn syntax_object(
+    root: &SyntaxNode,
+    rope: &Rope,
+    index: usize,
+    object: &TextObject,
+) -> Option<(usize, usize)> {
+    let offset = TextUnit::from_usize(rope.char_to_byte(index));
+    let node = match object.kind {
+        TextObjectKind::Function => {
+            enclosing_node(root, offset, |node| node.kind() == SyntaxKind::FN_DEF)?
+        }
+        TextObjectKind::Impl => {
+            enclosing_node(root, offset, |node| node.kind() == SyntaxKind::IMPL_BLOCK)?
         }
-        State { analysisHost }
+        TextObjectKind::Argument => enclosing_node(root, offset, |node| {
+            node.kind() == SyntaxKind::PARAM
+                || node
+                    .parent()
+                    .map_or(false, |parent| parent.kind() == SyntaxKind::ARG_LIST)
+        })?,
+        TextObjectKind::Expression => {
+            enclosing_node(root, offset, |node| ast::Expr::cast(node.clone()).is_some())?
+        }
+        _ => return None,
+    };
+    let (start, end) = char_range(rope, node.text_range());
+    if start == end {
+        return None;
+    }
+    let text: Vec<char> = rope.chars().collect();
+    match object.kind {
+        TextObjectKind::Function | TextObjectKind::Impl if !object.around => {
+            let braces = node.descendants().find(|child| {
+                child.kind() == SyntaxKind::BLOCK || child.kind() == SyntaxKind::ITEM_LIST
+            })?;
+            let (open, close) = char_range(rope, braces.text_range());
+            inside_block(&text, open, close - 1)
+        }
+        TextObjectKind::Argument if object.around => Some(with_separator(&text, start, end - 1)),
+        _ => Some((start, end - 1)),
+    }
+}
+
+fn diagnostics_picker(analysis: &Analysis, file_id: FileId, buffer: &Buffer) -> Picker {
+    let diagnostics = analysis.diagnostics(file_id).unwrap_or_default();
+    Picker {
+        title: "Diagnostics".into(),
+        items: diagnostics
+            .into_iter()
+            .map(|diagnostic| {
+                let offset = diagnostic.range.start().to_usize();
+                let line = point_from_offset(&buffer.rope, offset).y as usize;
+                let severity = match diagnostic.severity {
+                    Severity::Error => "E",
+                    Severity::WeakWarning => "W",
+                };
+                PickerItem {
+                    label: format!("{} {}: {}", severity, line + 1, diagnostic.message),
+                    action: jump_to_offset(&buffer.rope, offset),
+                    preview: None,
+                }
+            })
+            .collect(),
+    }
+}
+
+fn symbols_picker(analysis: &Analysis, file_id: FileId, buffer: &Buffer) -> Picker {
+    Picker {
+        title: "Symbols".into(),
+        items: analysis
+            .file_structure(file_id)
+            .into_iter()
+            .map(|node| PickerItem {
+                label: match node.detail {
+                    Some(detail) => format!("{}: {}", node.label, detail),
+                    None => node.label,
+                },
+                action: jump_to_offset(&buffer.rope, node.navigation_range.start().to_usize()),
+                preview: None,
+            })
+            .collect(),
     }
 }
 
 pub fn get_pos_len_from_text_range(
     text_range: TextRange,
     buffer: &Buffer,
+    window: &Window,
+    gutter: &GutterLayout,
+    text_width: usize,
 ) -> (Option<Point>, usize) {
     let start_char_index = text_range.start().to_usize();
     let line_index = buffer.rope.char_to_line(start_char_index);
     let line_start_index = buffer.rope.line_to_char(line_index);
+    let column = start_char_index - line_start_index;
     let length = text_range.len().to_usize();
-    let start_point = if line_index < buffer.start_line {
-        None
-    } else {
-        Some(Point {
-            x: (start_char_index - line_start_index + 4) as u16,
-            y: (line_index - buffer.start_line) as u16,
-        })
-    };
-    (start_point, length)
+    let first_visible_column = window.start_column;
+    let last_visible_column = window.start_column + text_width;
+    if line_index < window.start_line
+        || column >= last_visible_column
+        || column + length <= first_visible_column
+    {
+        return (None, length);
+    }
+    // Clip the range to the columns that are currently scrolled into view
+    let visible_column = std::cmp::max(column, first_visible_column);
+    let visible_length = std::cmp::min(column + length, last_visible_column) - visible_column;
+    let start_point = gutter.screen_point(
+        visible_column,
+        line_index,
+        window.start_column,
+        window.start_line,
+    );
+    (start_point, visible_length)
+}
+
+fn severity_scope(severity: Severity) -> &'static str {
+    match severity {
+        Severity::Error => "diagnostic.error",
+        Severity::WeakWarning => "diagnostic.warning",
+    }
 }
 
 pub fn draw_diagnostics(
     analysis: &Analysis,
     file_id: FileId,
     buffer: &Buffer,
+    window: &Window,
+    region: &WindowRegion,
     back_buffer: &mut BackBuffer,
     utils: &Utils,
+    theme: &Theme,
+    gutter: &GutterLayout,
 ) {
     if let Ok(diagnostics) = analysis.diagnostics(file_id) {
+        let text_width = gutter.text_width(region.size.w);
         for diagnostic in diagnostics {
             (utils.info)(&format!("diagnostic: {:?}", diagnostic));
+            // The sign takes the scope's foreground, the range and message its background
+            let style = theme.get(severity_scope(diagnostic.severity));
+            let line_index = buffer
+                .rope
+                .char_to_line(diagnostic.range.start().to_usize());
+            if line_index < window.start_line {
+                continue;
+            }
+            let screen_y = (line_index - window.start_line) as u16;
+            if let Some(sign_point) = gutter.sign_x.and_then(|sign_x| {
+                region.to_screen(&Point {
+                    x: sign_x,
+                    y: screen_y,
+                })
+            }) {
+                let sign = match diagnostic.severity {
+                    Severity::Error => "E",
+                    Severity::WeakWarning => "W",
+                };
+                (utils.write_to_buffer)(
+                    back_buffer,
+                    &sign_point,
+                    sign,
+                    None,
+                    style.fg.clone(),
+                    None,
+                );
+            }
             if let (Some(start_point), length) =
-                get_pos_len_from_text_range(diagnostic.range, buffer)
+                get_pos_len_from_text_range(diagnostic.range, buffer, window, gutter, text_width)
             {
-                let color = get_color_from_severity(diagnostic.severity);
+                let start_point = match region.to_screen(&start_point) {
+                    Some(point) => point,
+                    None => continue,
+                };
                 (utils.style_range)(
                     back_buffer,
                     &start_point,
                     length,
                     None,
                     None,
-                    Some(color.clone()),
+                    style.bg.clone(),
                 );
-                let diagnostic_text_x =
-                    buffer.rope.line(start_point.y as usize).len_chars() as u16 + 5;
+                let diagnostic_column = buffer.rope.line(line_index).len_chars() + 1;
+                if diagnostic_column >= window.start_column + text_width {
+                    continue;
+                }
+                let diagnostic_text_x = gutter
+                    .screen_x(diagnostic_column, window.start_column)
+                    .unwrap_or(gutter.width);
+                let message: String = diagnostic
+                    .message
+                    .chars()
+                    .take(window.start_column + text_width - diagnostic_column)
+                    .collect();
                 (utils.write_to_buffer)(
                     back_buffer,
                     &Point {
-                        x: diagnostic_text_x,
+                        x: diagnostic_text_x + region.origin.x,
                         y: start_point.y,
                     },
-                    &diagnostic.message,
+                    &message,
                     None,
                     None,
-                    Some(color),
+                    style.bg.clone(),
                 );
             }
         }
@@ -111,20 +350,28 @@ fn draw_syntax_highlighting(
     analysis: &Analysis,
     file_id: FileId,
     buffer: &Buffer,
+    window: &Window,
+    region: &WindowRegion,
     back_buffer: &mut BackBuffer,
     utils: &Utils,
-    client: &Client,
+    theme: &Theme,
+    gutter: &GutterLayout,
 ) {
     if let Ok(highlighted_ranges) = analysis.highlight(file_id) {
+        let text_width = gutter.text_width(region.size.w);
         for HighlightedRange { range, tag, .. } in highlighted_ranges {
             (utils.info)(&format!("{:?} -> {}", range, tag));
-            if let (Some(start_point), length) = get_pos_len_from_text_range(range, buffer) {
+            if let (Some(start_point), length) =
+                get_pos_len_from_text_range(range, buffer, window, gutter, text_width)
+            {
                 (utils.info)(&format!("start point: {:?}", start_point));
-                if start_point.y > client.size.as_ref().map(|s| s.h).unwrap_or(0) {
+                if start_point.y >= region.size.h {
                     break;
                 }
-                let fg_color = get_color_from_tag(tag);
-                if fg_color.is_some() {
+                let fg_color = theme.fg(tag);
+                if let (true, Some(start_point)) =
+                    (fg_color.is_some(), region.to_screen(&start_point))
+                {
                     (utils.style_range)(back_buffer, &start_point, length, None, fg_color, None);
                 }
             }
@@ -143,19 +390,65 @@ pub fn render(
     let data: Box<State> = unsafe { Box::from_raw(data_ptr as *mut State) };
     let analysis = data.analysisHost.analysis();
     let client = &global_data.clients[*client_index];
-    let buffer = &global_data.buffers[client.buffer];
-    let file_id = file_id_from_buffer_index(client.buffer);
-    draw_syntax_highlighting(&analysis, file_id, &buffer, back_buffer, utils, client);
-    draw_diagnostics(&analysis, file_id, &buffer, back_buffer, utils);
+    for region in client.window_regions() {
+        let window = &client.tab().windows[region.window];
+        let buffer = &global_data.buffers[window.buffer];
+        let file_id = file_id_from_buffer_index(window.buffer);
+        let gutter = (utils.gutter_layout)(global_data, window.buffer);
+        draw_syntax_highlighting(
+            &analysis,
+            file_id,
+            &buffer,
+            window,
+            &region,
+            back_buffer,
+            utils,
+            &global_data.theme,
+            &gutter,
+        );
+        draw_diagnostics(
+            &analysis,
+            file_id,
+            &buffer,
+            window,
+            &region,
+            back_buffer,
+            utils,
+            &global_data.theme,
+            &gutter,
+        );
+    }
     std::mem::forget(data);
 }
 
+// Hands the analysis the current text of the buffer
+fn sync_buffer(
+    data: &mut State,
+    global_data: &mut GlobalData,
+    buffer_index: BufferIndex,
+    utils: &Utils,
+) {
+    let buffer = &mut global_data.buffers[buffer_index];
+    data.analysisHost.apply_change({
+        let mut change = AnalysisChange::new();
+        let new_source = std::sync::Arc::new(String::from(buffer.rope.clone()));
+        (utils.info)(&format!("{}", new_source));
+        change.change_file(file_id_from_buffer_index(buffer_index), new_source);
+        change
+    });
+    update_diagnostic_counts(
+        &data.analysisHost.analysis(),
+        file_id_from_buffer_index(buffer_index),
+        buffer,
+    );
+}
+
 #[no_mangle]
 pub fn update(
     global_data: &mut GlobalData,
     msg: &Msg,
     utils: &Utils,
-    _send_cmd: &Box<Fn(ClientIndex, Cmd)>,
+    send_cmd: &Box<Fn(ClientIndex, Cmd)>,
     data_ptr: *mut c_void,
 ) {
     let mut data: Box<State> = unsafe { Box::from_raw(data_ptr as *mut State) };
@@ -163,30 +456,126 @@ pub fn update(
     match msg {
         Msg::Cmd(client_index, cmd) => match cmd {
             BufferLoaded => {
-                let buffer_index = global_data.clients[*client_index].buffer;
+                let buffer_index = global_data.clients[*client_index].buffer();
                 let buffer = &mut global_data.buffers[buffer_index];
-                data.analysisHost.apply_change({
-                    let mut change = AnalysisChange::new();
-                    change.add_file(
-                        SourceRootId(0),
-                        file_id_from_buffer_index(buffer_index),
-                        RelativePathBuf::from_path(buffer.source.as_path())
-                            .expect("building relative path"),
-                        std::sync::Arc::new(String::from(buffer.rope.clone())),
-                    );
-                    change
-                });
+                data.add_file(buffer_index, buffer);
+                update_diagnostic_counts(
+                    &data.analysisHost.analysis(),
+                    file_id_from_buffer_index(buffer_index),
+                    buffer,
+                );
             }
             BufferModified => {
-                let buffer_index = global_data.clients[*client_index].buffer;
-                let buffer = &mut global_data.buffers[buffer_index];
-                data.analysisHost.apply_change({
-                    let mut change = AnalysisChange::new();
-                    let new_source = std::sync::Arc::new(String::from(buffer.rope.clone()));
-                    (utils.info)(&format!("{}", new_source));
-                    change.change_file(file_id_from_buffer_index(buffer_index), new_source);
-                    change
+                let buffer_index = global_data.clients[*client_index].buffer();
+                sync_buffer(&mut data, global_data, buffer_index, utils);
+            }
+            // Reloads also come for buffers that were never shown here
+            BufferReloaded(buffer_index) if data.files.contains_key(*buffer_index) => {
+                sync_buffer(&mut data, global_data, *buffer_index, utils);
+            }
+            BufferDeleted(buffer_index) => {
+                // The buffer is already gone from global data, hence the stored path
+                if let Some(path) = data.files.remove(buffer_index) {
+                    data.analysisHost.apply_change({
+                        let mut change = AnalysisChange::new();
+                        change.remove_file(
+                            SourceRootId(0),
+                            file_id_from_buffer_index(*buffer_index),
+                            path,
+                        );
+                        change
+                    });
+                }
+            }
+            // Only Rust buffers have a syntax tree worth looking at
+            SelectObject(object, operator) if object.kind.is_syntax() => {
+                let client = &global_data.clients[*client_index];
+                let buffer = &global_data.buffers[client.buffer()];
+                let position = &client.window().cursor.position;
+                let index = buffer.rope.line_to_char(position.y as usize)
+                    + position.x.saturating_sub(1) as usize;
+                let found = match buffer.source.extension() {
+                    Some(extension) if extension == "rs" => {
+                        let source_file = data
+                            .analysisHost
+                            .analysis()
+                            .parse(file_id_from_buffer_index(client.buffer()));
+                        syntax_object(source_file.syntax(), &buffer.rope, index, object)
+                    }
+                    _ => None,
+                };
+                if let Some((start, end)) = found {
+                    send_cmd(
+                        *client_index,
+                        Select(
+                            point_from_offset(&buffer.rope, start),
+                            point_from_offset(&buffer.rope, end),
+                        ),
+                    );
+                    if let Some(operator) = operator {
+                        send_cmd(*client_index, ApplyOperator(operator.clone()));
+                    }
+                }
+            }
+            GotoDefinition => {
+                let client = &global_data.clients[*client_index];
+                let buffer = &global_data.buffers[client.buffer()];
+                let position = &client.window().cursor.position;
+                let index = buffer.rope.line_to_char(position.y as usize)
+                    + position.x.saturating_sub(1) as usize;
+                let file_position = FilePosition {
+                    file_id: file_id_from_buffer_index(client.buffer()),
+                    offset: TextUnit::from_usize(buffer.rope.char_to_byte(index)),
+                };
+                let target = data
+                    .analysisHost
+                    .analysis()
+                    .goto_definition(file_position)
+                    .ok()
+                    .and_then(|found| found)
+                    .and_then(|found| found.info.into_iter().next());
+                // Definitions can be in any buffer added to the analysis
+                let found = target.and_then(|target| {
+                    global_data
+                        .buffer_keys
+                        .keys()
+                        .find(|index| file_id_from_buffer_index(*index) == target.file_id())
+                        .map(|index| (index, target))
                 });
+                match found {
+                    Some((target_buffer, target)) => {
+                        let rope = &global_data.buffers[target_buffer].rope;
+                        let range = target.focus_range().unwrap_or_else(|| target.full_range());
+                        let (start, _) = char_range(rope, range);
+                        send_cmd(*client_index, RememberJump);
+                        if target_buffer != client.buffer() {
+                            send_cmd(*client_index, ShowBuffer(target_buffer));
+                        }
+                        send_cmd(*client_index, jump_to_offset(rope, start));
+                    }
+                    None => send_cmd(
+                        *client_index,
+                        Notify(NotifyLevel::Error, "No definition found".into()),
+                    ),
+                }
+            }
+            Pick(PickerSource::Diagnostics) => {
+                let buffer_index = global_data.clients[*client_index].buffer();
+                let picker = diagnostics_picker(
+                    &data.analysisHost.analysis(),
+                    file_id_from_buffer_index(buffer_index),
+                    &global_data.buffers[buffer_index],
+                );
+                send_cmd(*client_index, OpenPicker(picker));
+            }
+            Pick(PickerSource::Symbols) => {
+                let buffer_index = global_data.clients[*client_index].buffer();
+                let picker = symbols_picker(
+                    &data.analysisHost.analysis(),
+                    file_id_from_buffer_index(buffer_index),
+                    &global_data.buffers[buffer_index],
+                );
+                send_cmd(*client_index, OpenPicker(picker));
             }
             _ => {}
         },
@@ -209,3 +598,23 @@ pub fn cleanup(data: *mut c_void) {
         drop(ptr);
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_offsets_are_bytes() {
+        let text = "// café über\nfn main() {}\n";
+        let rope = Rope::from_str(text);
+        let offset = text.find("main").unwrap();
+        assert_eq!(point_from_offset(&rope, offset), Point { x: 4, y: 1 });
+        let offset = text.find("über").unwrap();
+        assert_eq!(point_from_offset(&rope, offset), Point { x: 9, y: 0 });
+        // Offsets from an analysis older than the text stay inside it
+        assert_eq!(
+            point_from_offset(&rope, text.len() + 10),
+            Point { x: 1, y: 2 }
+        );
+    }
+}
@@ -0,0 +1,128 @@
+// Scores how well `query` matches `candidate` as a subsequence.
+// Matches at the start of words and runs of consecutive characters score
+// higher, gaps between matched characters and long candidates score lower.
+
+const MATCH: i64 = 16;
+const WORD_START_BONUS: i64 = 8;
+const CONSECUTIVE_BONUS: i64 = 6;
+const FILE_NAME_BONUS: i64 = 2;
+const GAP_PENALTY: i64 = 1;
+const MAX_GAP_PENALTY: i64 = 8;
+
+fn is_separator(c: char) -> bool {
+    match c {
+        '/' | '_' | '-' | '.' | ' ' => true,
+        _ => false,
+    }
+}
+
+fn chars_match(query: char, candidate: char, case_sensitive: bool) -> bool {
+    if case_sensitive {
+        query == candidate
+    } else {
+        query.to_lowercase().eq(candidate.to_lowercase())
+    }
+}
+
+/// Returns the score and the char indices of `candidate` that matched, or
+/// `None` if `query` isn't a subsequence of it. Matching ignores case unless
+/// the query has an uppercase letter in it.
+pub fn fuzzy_match(query: &str, candidate: &str) -> Option<(i64, Vec<usize>)> {
+    let query: Vec<char> = query.chars().collect();
+    let candidate: Vec<char> = candidate.chars().collect();
+    if query.is_empty() {
+        return Some((0, Vec::new()));
+    }
+    let case_sensitive = query.iter().any(|c| c.is_uppercase());
+    // Find where the earliest complete match ends
+    let mut query_index = 0;
+    let mut end = None;
+    for (index, c) in candidate.iter().enumerate() {
+        if chars_match(query[query_index], *c, case_sensitive) {
+            query_index += 1;
+            if query_index == query.len() {
+                end = Some(index);
+                break;
+            }
+        }
+    }
+    let end = end?;
+    // Walk back from there to find the tightest start for that end
+    let mut query_index = query.len();
+    let mut start = end;
+    for index in (0..=end).rev() {
+        if chars_match(query[query_index - 1], candidate[index], case_sensitive) {
+            query_index -= 1;
+            if query_index == 0 {
+                start = index;
+                break;
+            }
+        }
+    }
+    let file_name_start = candidate
+        .iter()
+        .rposition(|c| *c == '/')
+        .map(|index| index + 1)
+        .unwrap_or(0);
+    let mut positions = Vec::with_capacity(query.len());
+    let mut score = 0;
+    let mut query_index = 0;
+    let mut previous: Option<usize> = None;
+    for index in start..=end {
+        if query_index == query.len() {
+            break;
+        }
+        if !chars_match(query[query_index], candidate[index], case_sensitive) {
+            continue;
+        }
+        score += MATCH;
+        if index == 0 || is_separator(candidate[index - 1]) {
+            score += WORD_START_BONUS;
+        }
+        if index >= file_name_start {
+            score += FILE_NAME_BONUS;
+        }
+        match previous {
+            Some(previous) if previous + 1 == index => score += CONSECUTIVE_BONUS,
+            Some(previous) => {
+                score -= std::cmp::min((index - previous - 1) as i64 * GAP_PENALTY, MAX_GAP_PENALTY)
+            }
+            None => {}
+        }
+        positions.push(index);
+        previous = Some(index);
+        query_index += 1;
+    }
+    // Prefer shorter candidates when everything else is equal
+    score -= candidate.len() as i64 / 16;
+    Some((score, positions))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_subsequence_matches() {
+        let (_, positions) = fuzzy_match("bfr", "buffer-render").unwrap();
+        assert_eq!(positions, vec![0, 2, 5]);
+        assert!(fuzzy_match("xyz", "buffer-render").is_none());
+        assert_eq!(fuzzy_match("", "anything"), Some((0, Vec::new())));
+    }
+
+    #[test]
+    fn test_smart_case() {
+        assert!(fuzzy_match("lib", "src/Lib.rs").is_some());
+        assert!(fuzzy_match("Lib", "src/lib.rs").is_none());
+    }
+
+    #[test]
+    fn test_word_starts_and_runs_score_higher() {
+        let (contiguous, _) = fuzzy_match("lib", "src/lib.rs").unwrap();
+        let (scattered, _) = fuzzy_match("lib", "src/lint/bar.rs").unwrap();
+        assert!(contiguous > scattered);
+        let (file_name, _) = fuzzy_match("main", "core/src/main.rs").unwrap();
+        let (directory, _) = fuzzy_match("main", "main/src/other.rs").unwrap();
+        assert!(file_name > directory);
+    }
+}
@@ -0,0 +1,442 @@
+mod fuzzy;
+mod walk;
+
+use std::ffi::c_void;
+use std::io::Read;
+use std::path::Path;
+use termion::cursor::{Goto, Show};
+use types::{
+    ArgKind, Args, BackBuffer, ClientIndex, Cmd, ExCommand, GlobalData, Mode, Msg, NotifyLevel,
+    Picker, PickerItem, PickerSource, Point, SecondaryMap, Theme, Utils,
+};
+
+// Only the start of a file is shown, so there's no need to read all of it
+const PREVIEW_BYTES: u64 = 16 * 1024;
+// Below this the preview would squeeze the list too much to be useful
+const PREVIEW_MIN_WIDTH: u16 = 60;
+
+#[derive(Debug)]
+struct Match {
+    item: usize,
+    score: i64,
+    /// Char indices in the label that matched the query
+    positions: Vec<usize>,
+}
+
+#[derive(Debug)]
+struct OpenPicker {
+    title: String,
+    items: Vec<PickerItem>,
+    query: String,
+    matches: Vec<Match>,
+    selected: usize,
+    /// Lines of the selected item's preview file
+    preview: Vec<String>,
+}
+
+impl OpenPicker {
+    fn new(picker: Picker) -> OpenPicker {
+        let mut open = OpenPicker {
+            title: picker.title,
+            items: picker.items,
+            query: String::new(),
+            matches: Vec::new(),
+            selected: 0,
+            preview: Vec::new(),
+        };
+        open.filter();
+        open
+    }
+
+    fn filter(&mut self) {
+        let query = &self.query;
+        self.matches = self
+            .items
+            .iter()
+            .enumerate()
+            .filter_map(|(item, PickerItem { label, .. })| {
+                fuzzy::fuzzy_match(query, label).map(|(score, positions)| Match {
+                    item,
+                    score,
+                    positions,
+                })
+            })
+            .collect();
+        // Stable, so equal scores keep the order the source gave
+        self.matches.sort_by(|a, b| b.score.cmp(&a.score));
+        self.selected = 0;
+        self.load_preview();
+    }
+
+    fn move_selection(&mut self, offset: i32) {
+        if self.matches.is_empty() {
+            return;
+        }
+        let last = self.matches.len() as i64 - 1;
+        let selected = (self.selected as i64 + offset as i64).max(0).min(last);
+        self.selected = selected as usize;
+        self.load_preview();
+    }
+
+    fn has_preview(&self) -> bool {
+        self.items.iter().any(|item| item.preview.is_some())
+    }
+
+    fn load_preview(&mut self) {
+        self.preview = match self
+            .matches
+            .get(self.selected)
+            .and_then(|selected| self.items[selected.item].preview.as_ref())
+        {
+            Some(path) => read_preview(path),
+            None => Vec::new(),
+        };
+    }
+
+    /// Gives up the picker to hand out the action of the selected item
+    fn into_action(mut self) -> Option<Cmd> {
+        let selected = self.matches.get(self.selected)?.item;
+        Some(self.items.swap_remove(selected).action)
+    }
+}
+
+fn files_picker(root: &Path) -> Picker {
+    Picker {
+        title: "Files".into(),
+        items: walk::project_files(root)
+            .into_iter()
+            .map(|path| PickerItem {
+                label: path.to_string_lossy().into_owned(),
+                action: Cmd::LoadFile(path.clone()),
+                preview: Some(path),
+            })
+            .collect(),
+    }
+}
+
+fn read_preview(path: &Path) -> Vec<String> {
+    let mut bytes = Vec::new();
+    let read =
+        std::fs::File::open(path).and_then(|file| file.take(PREVIEW_BYTES).read_to_end(&mut bytes));
+    if let Err(err) = read {
+        return vec![format!("Can't read {}: {}", path.display(), err)];
+    }
+    if bytes.contains(&0) {
+        return vec!["Binary file".into()];
+    }
+    String::from_utf8_lossy(&bytes)
+        .lines()
+        // Tabs would throw off the cell count, so show them as spaces
+        .map(|line| line.replace('\t', "    "))
+        .collect()
+}
+
+#[derive(Debug, Default)]
+struct State {
+    pickers: SecondaryMap<ClientIndex, OpenPicker>,
+}
+
+// Draws one row of the box: left border, contents padded to `width` and right border
+fn render_row(
+    back_buffer: &mut BackBuffer,
+    utils: &Utils,
+    position: Point,
+    contents: &str,
+    width: usize,
+    ends: (char, char),
+) {
+    let text: String = contents.chars().take(width).collect();
+    (utils.write_to_buffer)(
+        back_buffer,
+        &position,
+        &format!(
+            "{}{}{:width$}{}",
+            ends.0,
+            text,
+            "",
+            ends.1,
+            width = width - text.chars().count()
+        ),
+        None,
+        None,
+        None,
+    );
+}
+
+fn render_picker(
+    back_buffer: &mut BackBuffer,
+    utils: &Utils,
+    theme: &Theme,
+    picker: &OpenPicker,
+    area: Point,
+) -> Point {
+    // area holds the screen width and the height available above the status line
+    let width = area.x * 9 / 10;
+    let height = area.y * 8 / 10;
+    let left = (area.x - width) / 2;
+    let top = (area.y - height) / 2;
+    let inner_width = (width - 2) as usize;
+    let (list_width, preview_width) = if width >= PREVIEW_MIN_WIDTH && picker.has_preview() {
+        let list_width = inner_width / 2;
+        (list_width, inner_width - list_width - 1)
+    } else {
+        (inner_width, 0)
+    };
+    for y in top..top + height {
+        (utils.clear_range)(back_buffer, &Point { x: left, y }, width as usize);
+    }
+
+    let title = format!(
+        " {} {}/{} ",
+        picker.title,
+        picker.matches.len(),
+        picker.items.len()
+    );
+    let mut top_border: String = format!("─{}", title).chars().take(inner_width).collect();
+    top_border += &"─".repeat(inner_width - top_border.chars().count());
+    render_row(
+        back_buffer,
+        utils,
+        Point { x: left, y: top },
+        &top_border,
+        inner_width,
+        ('┌', '┐'),
+    );
+    let prompt = format!("> {}", picker.query);
+    render_row(
+        back_buffer,
+        utils,
+        Point {
+            x: left,
+            y: top + 1,
+        },
+        &prompt,
+        inner_width,
+        ('│', '│'),
+    );
+    let mut separator = "─".repeat(list_width);
+    let mut bottom_border = separator.clone();
+    if preview_width > 0 {
+        separator += &format!("┬{}", "─".repeat(preview_width));
+        bottom_border += &format!("┴{}", "─".repeat(preview_width));
+    }
+    render_row(
+        back_buffer,
+        utils,
+        Point {
+            x: left,
+            y: top + 2,
+        },
+        &separator,
+        inner_width,
+        ('├', '┤'),
+    );
+    render_row(
+        back_buffer,
+        utils,
+        Point {
+            x: left,
+            y: top + height - 1,
+        },
+        &bottom_border,
+        inner_width,
+        ('└', '┘'),
+    );
+
+    let rows = (height - 4) as usize;
+    // Keep the selection on screen by scrolling just far enough
+    let first = (picker.selected + 1).saturating_sub(rows);
+    for row in 0..rows {
+        let y = top + 3 + row as u16;
+        let mut line = match picker.matches.get(first + row) {
+            Some(found) => format!(" {}", picker.items[found.item].label),
+            None => String::new(),
+        };
+        if preview_width > 0 {
+            line = format!("{:width$}", line, width = list_width)
+                .chars()
+                .take(list_width)
+                .collect();
+            let preview = picker.preview.get(row).map(String::as_str).unwrap_or("");
+            line += &format!("│ {}", preview);
+        }
+        render_row(
+            back_buffer,
+            utils,
+            Point { x: left, y },
+            &line,
+            inner_width,
+            ('│', '│'),
+        );
+        if let Some(found) = picker.matches.get(first + row) {
+            if first + row == picker.selected {
+                let selected = theme.get("ui.picker.selected");
+                (utils.style_range)(
+                    back_buffer,
+                    &Point { x: left + 1, y },
+                    list_width,
+                    selected.style,
+                    selected.fg,
+                    selected.bg,
+                );
+            }
+            let matched = theme.get("ui.picker.match");
+            for position in found.positions.iter().filter(|p| **p + 1 < list_width) {
+                (utils.style_range)(
+                    back_buffer,
+                    &Point {
+                        x: left + 2 + *position as u16,
+                        y,
+                    },
+                    1,
+                    matched.style.clone(),
+                    matched.fg.clone(),
+                    matched.bg.clone(),
+                );
+            }
+        }
+    }
+    Point {
+        x: left + 1 + std::cmp::min(prompt.chars().count(), inner_width) as u16,
+        y: top + 1,
+    }
+}
+
+#[no_mangle]
+pub fn render(
+    global_data: &GlobalData,
+    client_index: &ClientIndex,
+    back_buffer: &mut BackBuffer,
+    utils: &Utils,
+    data_ptr: *mut c_void,
+) {
+    let data: Box<State> = unsafe { Box::from_raw(data_ptr as *mut State) };
+    let client = &global_data.clients[*client_index];
+    if let (Some(picker), Some(size), Some(status_row)) = (
+        data.pickers.get(*client_index),
+        client.size.as_ref(),
+        client.status_row(),
+    ) {
+        // Anything smaller can't fit the borders, prompt and a row of results
+        if size.w >= 10 && status_row >= 8 {
+            let cursor = render_picker(
+                back_buffer,
+                utils,
+                &global_data.theme,
+                picker,
+                Point {
+                    x: size.w,
+                    y: status_row,
+                },
+            );
+            use std::io::Write;
+            let mut stream = client.stream.try_clone().unwrap();
+            let _ = write!(stream, "{}{}", Show, Goto(cursor.x + 1, cursor.y + 1));
+        }
+    }
+    std::mem::forget(data);
+}
+
+#[no_mangle]
+pub fn update(
+    _global_data: &mut GlobalData,
+    msg: &Msg,
+    _utils: &Utils,
+    send_cmd: &Box<Fn(ClientIndex, Cmd)>,
+    data_ptr: *mut c_void,
+) {
+    let mut data: Box<State> = unsafe { Box::from_raw(data_ptr as *mut State) };
+    if let Msg::Cmd(client, cmd) = msg {
+        match cmd {
+            // The argument was checked against the source names when the command was parsed
+            Cmd::RunExCommand(call) if call.name == "pick" => {
+                if let Some(source) = PickerSource::from_name(&call.argument) {
+                    send_cmd(*client, Cmd::Pick(source));
+                }
+            }
+            Cmd::Pick(PickerSource::Files) => match std::env::current_dir() {
+                // Goes through the queue like any other source so it lands after pending mode changes
+                Ok(root) => send_cmd(*client, Cmd::OpenPicker(files_picker(&root))),
+                Err(err) => send_cmd(
+                    *client,
+                    Cmd::Notify(NotifyLevel::Error, format!("Can't search files: {}", err)),
+                ),
+            },
+            Cmd::OpenPicker(picker) if picker.items.is_empty() => send_cmd(
+                *client,
+                Cmd::Notify(
+                    NotifyLevel::Info,
+                    format!("Nothing to pick from in {}", picker.title),
+                ),
+            ),
+            Cmd::OpenPicker(picker) => {
+                data.pickers
+                    .insert(*client, OpenPicker::new(picker.clone()));
+                send_cmd(*client, Cmd::ChangeMode(Mode::Picker));
+            }
+            Cmd::PickerInsertChar(c) => {
+                if let Some(picker) = data.pickers.get_mut(*client) {
+                    picker.query.push(*c);
+                    picker.filter();
+                }
+            }
+            Cmd::PickerDeleteChar => {
+                if let Some(picker) = data.pickers.get_mut(*client) {
+                    picker.query.pop();
+                    picker.filter();
+                }
+            }
+            Cmd::PickerMove(offset) => {
+                if let Some(picker) = data.pickers.get_mut(*client) {
+                    picker.move_selection(*offset);
+                }
+            }
+            Cmd::PickerAccept => {
+                let action = data
+                    .pickers
+                    .remove(*client)
+                    .and_then(|picker| picker.into_action());
+                // Leave the picker first so actions can switch to a mode of their own
+                send_cmd(*client, Cmd::ChangeMode(Mode::Normal));
+                if let Some(action) = action {
+                    if let Cmd::Jump(_) = action {
+                        send_cmd(*client, Cmd::RememberJump);
+                    }
+                    send_cmd(*client, action);
+                }
+            }
+            Cmd::ChangeMode(mode) if *mode != Mode::Picker => {
+                data.pickers.remove(*client);
+            }
+            _ => {}
+        }
+    }
+    std::mem::forget(data);
+}
+
+/// Drawn over the windows and the status line's message output
+#[no_mangle]
+pub fn layer() -> i32 {
+    2
+}
+
+#[no_mangle]
+pub fn init(global_data: &mut GlobalData) -> *mut c_void {
+    let sources = PickerSource::ALL
+        .iter()
+        .map(|source| source.name().to_string())
+        .collect();
+    global_data.ex_commands.register(ExCommand::new(
+        "pick",
+        "pick",
+        Args::Required(ArgKind::Choice(sources)),
+    ));
+    Box::into_raw(Box::new(State::default())) as *mut c_void
+}
+
+#[no_mangle]
+pub fn cleanup(data_ptr: *mut c_void) {
+    unsafe {
+        let _ = Box::from_raw(data_ptr as *mut State);
+    }
+}
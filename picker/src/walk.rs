@@ -0,0 +1,222 @@
+use std::path::{Path, PathBuf};
+
+// Enough for any project we would want to fuzzy find in
+const MAX_FILES: usize = 100_000;
+
+/// Matches `text` against a gitignore style glob. `*` and `?` stay within one
+/// path component, `**` crosses components and `[...]` matches a set of chars.
+pub fn glob_match(pattern: &str, text: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let text: Vec<char> = text.chars().collect();
+    glob_match_from(&pattern, &text)
+}
+
+fn glob_match_from(pattern: &[char], text: &[char]) -> bool {
+    match pattern.first() {
+        None => text.is_empty(),
+        Some('*') if pattern.get(1) == Some(&'*') => {
+            // "**/" also matches no directories at all
+            let rest = if pattern.get(2) == Some(&'/') {
+                &pattern[3..]
+            } else {
+                &pattern[2..]
+            };
+            (0..=text.len()).any(|skip| glob_match_from(rest, &text[skip..]))
+        }
+        Some('*') => {
+            let rest = &pattern[1..];
+            for skip in 0..=text.len() {
+                if glob_match_from(rest, &text[skip..]) {
+                    return true;
+                }
+                if skip < text.len() && text[skip] == '/' {
+                    break;
+                }
+            }
+            false
+        }
+        Some('?') => match text.first() {
+            Some(c) if *c != '/' => glob_match_from(&pattern[1..], &text[1..]),
+            _ => false,
+        },
+        Some('[') => {
+            let close = match pattern.iter().skip(1).position(|c| *c == ']') {
+                Some(offset) => offset + 1,
+                // Not a set after all, match the bracket literally
+                None => {
+                    return text.first() == Some(&'[') && glob_match_from(&pattern[1..], &text[1..])
+                }
+            };
+            let c = match text.first() {
+                Some(c) => *c,
+                None => return false,
+            };
+            let set = &pattern[1..close];
+            let (negated, set) = match set.first() {
+                Some('!') | Some('^') => (true, &set[1..]),
+                _ => (false, set),
+            };
+            let mut index = 0;
+            let mut found = false;
+            while index < set.len() {
+                if index + 2 < set.len() && set[index + 1] == '-' {
+                    found |= set[index] <= c && c <= set[index + 2];
+                    index += 3;
+                } else {
+                    found |= set[index] == c;
+                    index += 1;
+                }
+            }
+            found != negated && glob_match_from(&pattern[close + 1..], &text[1..])
+        }
+        Some('\\') if pattern.len() > 1 => {
+            text.first() == Some(&pattern[1]) && glob_match_from(&pattern[2..], &text[1..])
+        }
+        Some(p) => text.first() == Some(p) && glob_match_from(&pattern[1..], &text[1..]),
+    }
+}
+
+/// One line of a `.gitignore` file
+#[derive(Debug, Clone)]
+struct IgnoreRule {
+    pattern: String,
+    /// Directory holding the `.gitignore`, relative to the project root
+    base: PathBuf,
+    negated: bool,
+    directory_only: bool,
+    /// Patterns with a slash in them only match relative to `base`
+    anchored: bool,
+}
+
+impl IgnoreRule {
+    fn parse(line: &str, base: &Path) -> Option<IgnoreRule> {
+        let line = line.trim_end();
+        if line.is_empty() || line.starts_with('#') {
+            return None;
+        }
+        let (negated, line) = if line.starts_with('!') {
+            (true, &line[1..])
+        } else {
+            (false, line)
+        };
+        let (directory_only, line) = if line.ends_with('/') {
+            (true, &line[..line.len() - 1])
+        } else {
+            (false, line)
+        };
+        if line.is_empty() {
+            return None;
+        }
+        Some(IgnoreRule {
+            anchored: line.contains('/'),
+            pattern: line.trim_start_matches('/').to_string(),
+            base: base.to_path_buf(),
+            negated,
+            directory_only,
+        })
+    }
+
+    fn matches(&self, path: &Path, is_dir: bool) -> bool {
+        if self.directory_only && !is_dir {
+            return false;
+        }
+        let relative = match path.strip_prefix(&self.base) {
+            Ok(relative) => relative,
+            Err(_) => return false,
+        };
+        if self.anchored {
+            glob_match(&self.pattern, &relative.to_string_lossy())
+        } else {
+            relative
+                .file_name()
+                .map(|file_name| glob_match(&self.pattern, &file_name.to_string_lossy()))
+                .unwrap_or(false)
+        }
+    }
+}
+
+fn read_ignore_file(root: &Path, directory: &Path) -> Vec<IgnoreRule> {
+    match std::fs::read_to_string(root.join(directory).join(".gitignore")) {
+        Ok(contents) => contents
+            .lines()
+            .filter_map(|line| IgnoreRule::parse(line, directory))
+            .collect(),
+        Err(_) => Vec::new(),
+    }
+}
+
+// Later rules win, so a `!pattern` can bring back something ignored earlier
+fn is_ignored(rules: &[IgnoreRule], path: &Path, is_dir: bool) -> bool {
+    rules
+        .iter()
+        .rev()
+        .find(|rule| rule.matches(path, is_dir))
+        .map(|rule| !rule.negated)
+        .unwrap_or(false)
+}
+
+/// Every file under `root` that git would not ignore, relative to `root`
+pub fn project_files(root: &Path) -> Vec<PathBuf> {
+    let mut files = Vec::new();
+    let mut pending = vec![(PathBuf::new(), read_ignore_file(root, Path::new("")))];
+    while let Some((directory, rules)) = pending.pop() {
+        let entries = match std::fs::read_dir(root.join(&directory)) {
+            Ok(entries) => entries,
+            Err(_) => continue,
+        };
+        let mut entries: Vec<_> = entries.filter_map(|entry| entry.ok()).collect();
+        entries.sort_by_key(|entry| entry.file_name());
+        for entry in entries {
+            let path = directory.join(entry.file_name());
+            let is_dir = entry.file_type().map(|kind| kind.is_dir()).unwrap_or(false);
+            if entry.file_name() == ".git" || is_ignored(&rules, &path, is_dir) {
+                continue;
+            }
+            if is_dir {
+                let mut nested_rules = rules.clone();
+                nested_rules.extend(read_ignore_file(root, &path));
+                pending.push((path, nested_rules));
+            } else {
+                files.push(path);
+                if files.len() >= MAX_FILES {
+                    return files;
+                }
+            }
+        }
+    }
+    files.sort();
+    files
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_glob_match() {
+        assert!(glob_match("*.rs", "lib.rs"));
+        assert!(!glob_match("*.rs", "src/lib.rs"));
+        assert!(glob_match("src/*.rs", "src/lib.rs"));
+        assert!(glob_match("**/*.rs", "lib.rs"));
+        assert!(glob_match("**/*.rs", "a/b/lib.rs"));
+        assert!(glob_match("a/**/z", "a/b/c/z"));
+        assert!(glob_match("file?.[ch]", "file1.c"));
+        assert!(!glob_match("file?.[!ch]", "file1.c"));
+        assert!(glob_match("[a-c]x", "bx"));
+    }
+
+    #[test]
+    fn test_ignore_rules() {
+        let root = Path::new("");
+        let rules: Vec<IgnoreRule> = ["target/", "*.log", "!keep.log", "/docs/build"]
+            .iter()
+            .filter_map(|line| IgnoreRule::parse(line, root))
+            .collect();
+        assert!(is_ignored(&rules, Path::new("target"), true));
+        assert!(!is_ignored(&rules, Path::new("target"), false));
+        assert!(is_ignored(&rules, Path::new("a/b/debug.log"), false));
+        assert!(!is_ignored(&rules, Path::new("a/keep.log"), false));
+        assert!(is_ignored(&rules, Path::new("docs/build"), true));
+        assert!(!is_ignored(&rules, Path::new("src/docs/build"), true));
+    }
+}
@@ -1,11 +1,32 @@
 use types::{
     BackBuffer, Buffer, BufferIndex, Client, ClientIndex, Cmd, Color, DeleteDirection, Direction,
-    GlobalData, JumpType, Mode, Msg, Point, Rect, Rope, SecondaryMap, Utils,
+    GlobalData, JumpType, Mode, Msg, Picker, PickerItem, PickerSource, Point, Rect, Rope,
+    SecondaryMap, Utils,
 };
 
+// Older yanks are forgotten past this many
+const MAX_YANKS: usize = 20;
+
 #[derive(Debug, Default)]
 struct State {
     register: String,
+    /// Recent yanks, newest last, for picking something other than the latest
+    yanks: Vec<String>,
+}
+
+fn registers_picker(yanks: &[String], position: &Point) -> Picker {
+    Picker {
+        title: "Registers".into(),
+        items: yanks
+            .iter()
+            .rev()
+            .map(|yank| PickerItem {
+                label: yank.replace('\n', "\\n"),
+                action: Cmd::InsertStringAtPoint(yank.clone(), position.clone()),
+                preview: None,
+            })
+            .collect(),
+    }
 }
 
 #[no_mangle]
@@ -20,7 +41,7 @@ pub fn render(
 
 #[no_mangle]
 pub fn update(
-    _global_data: &mut GlobalData,
+    global_data: &mut GlobalData,
     cmd: &Msg,
     _utils: &Utils,
     send_cmd: &Box<Fn(ClientIndex, Cmd)>,
@@ -32,6 +53,11 @@ pub fn update(
         Msg::Cmd(client_index, cmd) => match cmd {
             YankValue(val) => {
                 data.register = val.clone();
+                data.yanks.retain(|yank| yank != val);
+                data.yanks.push(val.clone());
+                if data.yanks.len() > MAX_YANKS {
+                    data.yanks.remove(0);
+                }
             }
             PasteAtPoint(position) => {
                 send_cmd(
@@ -39,6 +65,13 @@ pub fn update(
                     InsertStringAtPoint(data.register.clone(), position.clone()),
                 );
             }
+            Pick(PickerSource::Registers) => {
+                let position = &global_data.clients[*client_index].window().cursor.position;
+                send_cmd(
+                    *client_index,
+                    OpenPicker(registers_picker(&data.yanks, position)),
+                );
+            }
             _ => {}
         },
         _ => {}
@@ -0,0 +1,648 @@
+mod substitute;
+
+use regex::{Regex, RegexBuilder};
+use std::ffi::c_void;
+use substitute::{apply_replacements, find_replacements, translate_replacement, Replacement};
+use types::{
+    char_index, is_word, point_from_char, Args, BackBuffer, ClientIndex, Cmd, ExCommand,
+    GlobalData, JumpType, Mode, Msg, NotifyLevel, OptionScope, OptionSpec, OptionValue, Options,
+    Point, Rope, SecondaryMap, Substitution, Utils,
+};
+
+// Where the cursor was when the search started, to go back to if it's cancelled
+#[derive(Debug)]
+struct Pending {
+    origin: Point,
+    start_line: usize,
+    backward: bool,
+}
+
+// A `:s///c` waiting for an answer about each match in turn
+#[derive(Debug)]
+struct Confirming {
+    first_line: usize,
+    last_line: usize,
+    /// Text of the lines as it was when the command ran
+    text: String,
+    replacements: Vec<Replacement>,
+    /// Answers so far, one for each replacement from the start
+    accepted: Vec<bool>,
+    /// As typed, for the prompt
+    replacement: String,
+}
+
+#[derive(Debug, Default)]
+struct State {
+    pending: SecondaryMap<ClientIndex, Pending>,
+    confirming: SecondaryMap<ClientIndex, Confirming>,
+}
+
+fn compile(pattern: &str, ignore_case: bool) -> Result<Regex, regex::Error> {
+    RegexBuilder::new(pattern)
+        .case_insensitive(ignore_case)
+        .multi_line(true)
+        .build()
+}
+
+/// Whether a search for `pattern` ignores case, with smartcase on an uppercase
+/// letter in the pattern makes it match case
+fn ignores_case(options: &Options, pattern: &str) -> bool {
+    let ignore_case = options.get_bool("ignorecase", None).unwrap_or(false);
+    let smart_case = options.get_bool("smartcase", None).unwrap_or(false);
+    ignore_case && !(smart_case && pattern.chars().any(char::is_uppercase))
+}
+
+/// Char index of the first match after `from`, or before it when going
+/// backward. The bool is set when the search had to wrap around.
+fn find_match(regex: &Regex, rope: &Rope, from: usize, backward: bool) -> Option<(usize, bool)> {
+    let text = rope.to_string();
+    let starts: Vec<usize> = regex
+        .find_iter(&text)
+        .map(|found| rope.byte_to_char(found.start()))
+        .collect();
+    if backward {
+        starts
+            .iter()
+            .rev()
+            .find(|start| **start < from)
+            .map(|start| (*start, false))
+            .or_else(|| starts.last().map(|start| (*start, true)))
+    } else {
+        starts
+            .iter()
+            .find(|start| **start > from)
+            .map(|start| (*start, false))
+            .or_else(|| starts.first().map(|start| (*start, true)))
+    }
+}
+
+// Start of the word under the cursor and the word itself
+fn word_under_cursor(position: &Point, rope: &Rope) -> Option<(usize, String)> {
+    let line = rope.line(position.y as usize);
+    let column = position.x.saturating_sub(1) as usize;
+    if column >= line.len_chars() || !is_word(line.char(column)) {
+        return None;
+    }
+    let start = (0..column)
+        .rev()
+        .take_while(|index| is_word(line.char(*index)))
+        .last()
+        .unwrap_or(column);
+    let word: String = line
+        .chars()
+        .skip(start)
+        .take_while(|c| is_word(*c))
+        .collect();
+    Some((rope.line_to_char(position.y as usize) + start, word))
+}
+
+// Moves the client's cursor to the next match, telling the user about wrapping or failing
+fn jump_to_match(
+    global_data: &GlobalData,
+    client: ClientIndex,
+    pattern: &str,
+    from: usize,
+    backward: bool,
+    send_cmd: &Box<Fn(ClientIndex, Cmd)>,
+) {
+    let regex = match compile(pattern, ignores_case(&global_data.options, pattern)) {
+        Ok(regex) => regex,
+        Err(err) => {
+            send_cmd(
+                client,
+                Cmd::Notify(NotifyLevel::Error, format!("Invalid pattern: {}", err)),
+            );
+            return;
+        }
+    };
+    let rope = &global_data.buffers[global_data.clients[client].buffer()].rope;
+    match find_match(&regex, rope, from, backward) {
+        Some((index, wrapped)) => {
+            if wrapped {
+                let message = if backward {
+                    "Search hit TOP, continuing at BOTTOM"
+                } else {
+                    "Search hit BOTTOM, continuing at TOP"
+                };
+                send_cmd(client, Cmd::Notify(NotifyLevel::Warning, message.into()));
+            }
+            send_cmd(client, Cmd::RememberJump);
+            send_cmd(
+                client,
+                Cmd::Jump(JumpType::Position(point_from_char(index, rope))),
+            );
+        }
+        None => send_cmd(
+            client,
+            Cmd::Notify(
+                NotifyLevel::Error,
+                format!("Pattern not found: {}", pattern),
+            ),
+        ),
+    }
+}
+
+fn restore_origin(global_data: &mut GlobalData, client: ClientIndex, pending: &Pending) {
+    let window = global_data.clients[client].window_mut();
+    window.cursor.position = pending.origin.clone();
+    window.start_line = pending.start_line;
+}
+
+fn line_chars(rope: &Rope, first_line: usize, last_line: usize) -> std::ops::Range<usize> {
+    let end_line = std::cmp::min(last_line + 1, rope.len_lines());
+    rope.line_to_char(first_line)..rope.line_to_char(end_line)
+}
+
+fn plural(count: usize, word: &str) -> String {
+    if count == 1 {
+        format!("{} {}", count, word)
+    } else {
+        format!("{} {}s", count, word)
+    }
+}
+
+// Runs a substitution, or starts asking about each match when it has the c flag
+fn substitute(
+    data: &mut State,
+    global_data: &mut GlobalData,
+    client: ClientIndex,
+    substitution: &Substitution,
+    send_cmd: &Box<Fn(ClientIndex, Cmd)>,
+) -> Result<(), String> {
+    let pattern = if substitution.pattern.is_empty() {
+        global_data
+            .search
+            .pattern
+            .clone()
+            .ok_or("No previous search pattern")?
+    } else {
+        substitution.pattern.clone()
+    };
+    let ignore_case = substitution
+        .ignore_case
+        .unwrap_or_else(|| ignores_case(&global_data.options, &pattern));
+    let regex =
+        compile(&pattern, ignore_case).map_err(|err| format!("Invalid pattern: {}", err))?;
+    let rope = &global_data.buffers[global_data.clients[client].buffer()].rope;
+    let text = rope
+        .slice(line_chars(
+            rope,
+            substitution.first_line,
+            substitution.last_line,
+        ))
+        .to_string();
+    let replacements = find_replacements(
+        &regex,
+        &translate_replacement(&substitution.replacement),
+        &text,
+        substitution.global,
+    );
+    if replacements.is_empty() {
+        return Err(format!("Pattern not found: {}", pattern));
+    }
+    // Like vim, n and N go on to repeat the pattern
+    global_data.search.set_pattern(&pattern, false);
+    let mut confirming = Confirming {
+        first_line: substitution.first_line,
+        last_line: substitution.last_line,
+        text,
+        accepted: Vec::new(),
+        replacements,
+        replacement: substitution.replacement.clone(),
+    };
+    if substitution.confirm {
+        ask_to_confirm(global_data, client, &confirming, send_cmd);
+        data.confirming.insert(client, confirming);
+        send_cmd(client, Cmd::ChangeMode(Mode::Confirm));
+    } else {
+        confirming.accepted = vec![true; confirming.replacements.len()];
+        finish_substitution(client, &confirming, send_cmd);
+    }
+    Ok(())
+}
+
+// Puts the cursor on the next match, highlighted as the current one, and asks about it
+fn ask_to_confirm(
+    global_data: &GlobalData,
+    client: ClientIndex,
+    confirming: &Confirming,
+    send_cmd: &Box<Fn(ClientIndex, Cmd)>,
+) {
+    let rope = &global_data.buffers[global_data.clients[client].buffer()].rope;
+    let replacement = &confirming.replacements[confirming.accepted.len()];
+    let index = rope.line_to_char(confirming.first_line)
+        + confirming.text[..replacement.start].chars().count();
+    send_cmd(
+        client,
+        Cmd::Jump(JumpType::Position(point_from_char(index, rope))),
+    );
+    send_cmd(
+        client,
+        Cmd::Notify(
+            NotifyLevel::Info,
+            format!("Replace with {} (y/n/a/q/l)?", confirming.replacement),
+        ),
+    );
+}
+
+fn finish_substitution(
+    client: ClientIndex,
+    confirming: &Confirming,
+    send_cmd: &Box<Fn(ClientIndex, Cmd)>,
+) {
+    let (text, last_start) = apply_replacements(
+        &confirming.text,
+        &confirming.replacements,
+        &confirming.accepted,
+    );
+    let last_start = match last_start {
+        Some(last_start) => last_start,
+        None => return,
+    };
+    let count = confirming
+        .accepted
+        .iter()
+        .filter(|accepted| **accepted)
+        .count();
+    let mut lines: Vec<usize> = confirming
+        .replacements
+        .iter()
+        .zip(&confirming.accepted)
+        .filter(|(_, accepted)| **accepted)
+        .map(|(replacement, _)| confirming.text[..replacement.start].matches('\n').count())
+        .collect();
+    lines.dedup();
+    let last_line = confirming.first_line + text[..last_start].matches('\n').count();
+    send_cmd(
+        client,
+        Cmd::ReplaceLines(confirming.first_line, confirming.last_line, text),
+    );
+    send_cmd(
+        client,
+        Cmd::Jump(JumpType::Position(Point {
+            y: last_line as u16,
+            x: 1,
+        })),
+    );
+    send_cmd(
+        client,
+        Cmd::Notify(
+            NotifyLevel::Info,
+            format!(
+                "{} on {}",
+                plural(count, "substitution"),
+                plural(lines.len(), "line")
+            ),
+        ),
+    );
+}
+
+#[no_mangle]
+pub fn render(
+    global_data: &GlobalData,
+    client_index: &ClientIndex,
+    back_buffer: &mut BackBuffer,
+    utils: &Utils,
+    _data_ptr: *mut c_void,
+) {
+    // With hlsearch off only the search being typed is highlighted
+    let pattern = if global_data.options.get_bool("hlsearch", None) == Some(false) {
+        global_data.search.preview.as_ref().map(String::as_str)
+    } else {
+        global_data.search.active_pattern()
+    };
+    let regex = match pattern
+        .map(|pattern| compile(pattern, ignores_case(&global_data.options, pattern)))
+    {
+        Some(Ok(regex)) => regex,
+        _ => return,
+    };
+    let client = &global_data.clients[*client_index];
+    let match_style = global_data.theme.get("ui.search.match");
+    let current_style = global_data.theme.get("ui.search.current");
+    for region in client.window_regions() {
+        let window = &client.tab().windows[region.window];
+        let rope = &global_data.buffers[window.buffer].rope;
+        let gutter = (utils.gutter_layout)(global_data, window.buffer);
+        let text_width = gutter.text_width(region.size.w);
+        let is_active = region.window == client.tab().active_window;
+        let last_line = std::cmp::min(window.start_line + region.size.h as usize, rope.len_lines());
+        for line_index in window.start_line..last_line {
+            let line = rope.line(line_index).to_string();
+            let line_length = line.trim_end_matches(|c| c == '\n' || c == '\r').len();
+            for found in regex.find_iter(&line) {
+                // Only the part on this line and scrolled into view is styled
+                let from = line[..found.start()].chars().count();
+                let to = line[..std::cmp::min(found.end(), line_length)]
+                    .chars()
+                    .count();
+                let from_visible = std::cmp::max(from, window.start_column);
+                let to_visible = std::cmp::min(to, window.start_column + text_width);
+                if from_visible >= to_visible {
+                    continue;
+                }
+                let is_current = is_active
+                    && window.cursor.position.y as usize == line_index
+                    && window.cursor.position.x as usize == from + 1;
+                if let Some(start_point) = gutter
+                    .screen_point(
+                        from_visible,
+                        line_index,
+                        window.start_column,
+                        window.start_line,
+                    )
+                    .and_then(|point| region.to_screen(&point))
+                {
+                    let style = if is_current {
+                        &current_style
+                    } else {
+                        &match_style
+                    };
+                    (utils.style_range)(
+                        back_buffer,
+                        &start_point,
+                        to_visible - from_visible,
+                        style.style.clone(),
+                        style.fg.clone(),
+                        style.bg.clone(),
+                    );
+                }
+            }
+        }
+    }
+}
+
+#[no_mangle]
+pub fn update(
+    global_data: &mut GlobalData,
+    msg: &Msg,
+    _utils: &Utils,
+    send_cmd: &Box<Fn(ClientIndex, Cmd)>,
+    data_ptr: *mut c_void,
+) {
+    let mut data: Box<State> = unsafe { Box::from_raw(data_ptr as *mut State) };
+    if let Msg::Cmd(client, cmd) = msg {
+        match cmd {
+            Cmd::StartSearch(backward) => {
+                let window = global_data.clients[*client].window();
+                data.pending.insert(
+                    *client,
+                    Pending {
+                        origin: window.cursor.position.clone(),
+                        start_line: window.start_line,
+                        backward: *backward,
+                    },
+                );
+            }
+            Cmd::UpdateSearch(text) => {
+                if let Some(pending) = data.pending.get(*client) {
+                    global_data.search.preview = if text.is_empty() {
+                        None
+                    } else {
+                        Some(text.clone())
+                    };
+                    restore_origin(global_data, *client, pending);
+                    let rope = &global_data.buffers[global_data.clients[*client].buffer()].rope;
+                    // Half typed patterns are often invalid, those just don't move the cursor
+                    let ignore_case = ignores_case(&global_data.options, text);
+                    if let Some((index, _wrapped)) =
+                        compile(text, ignore_case).ok().and_then(|regex| {
+                            find_match(
+                                &regex,
+                                rope,
+                                char_index(&pending.origin, rope),
+                                pending.backward,
+                            )
+                        })
+                    {
+                        send_cmd(
+                            *client,
+                            Cmd::Jump(JumpType::Position(point_from_char(index, rope))),
+                        );
+                    }
+                }
+            }
+            Cmd::RunSearch(text) => {
+                let backward = match data.pending.remove(*client) {
+                    Some(pending) => {
+                        restore_origin(global_data, *client, &pending);
+                        pending.backward
+                    }
+                    None => false,
+                };
+                global_data.search.preview = None;
+                let pattern = if text.is_empty() {
+                    global_data.search.pattern.clone()
+                } else {
+                    Some(text.clone())
+                };
+                match pattern {
+                    Some(pattern) => {
+                        match compile(&pattern, ignores_case(&global_data.options, &pattern)) {
+                            Ok(_) => {
+                                global_data.search.set_pattern(&pattern, backward);
+                                let rope = &global_data.buffers
+                                    [global_data.clients[*client].buffer()]
+                                .rope;
+                                let from = char_index(
+                                    &global_data.clients[*client].window().cursor.position,
+                                    rope,
+                                );
+                                jump_to_match(
+                                    global_data,
+                                    *client,
+                                    &pattern,
+                                    from,
+                                    backward,
+                                    send_cmd,
+                                );
+                            }
+                            // Keeps a typo out of the history and away from n and N
+                            Err(err) => send_cmd(
+                                *client,
+                                Cmd::Notify(
+                                    NotifyLevel::Error,
+                                    format!("Invalid pattern: {}", err),
+                                ),
+                            ),
+                        }
+                    }
+                    None => send_cmd(
+                        *client,
+                        Cmd::Notify(NotifyLevel::Error, "No previous search pattern".into()),
+                    ),
+                }
+            }
+            Cmd::SearchNext(reverse) => match global_data.search.pattern.clone() {
+                Some(pattern) => {
+                    global_data.search.highlight = true;
+                    let backward = global_data.search.backward != *reverse;
+                    let rope = &global_data.buffers[global_data.clients[*client].buffer()].rope;
+                    let from =
+                        char_index(&global_data.clients[*client].window().cursor.position, rope);
+                    jump_to_match(global_data, *client, &pattern, from, backward, send_cmd);
+                }
+                None => send_cmd(
+                    *client,
+                    Cmd::Notify(NotifyLevel::Error, "No previous search pattern".into()),
+                ),
+            },
+            Cmd::RunExCommand(call) if call.name == "nohlsearch" => {
+                global_data.search.highlight = false;
+            }
+            Cmd::SearchWord(backward) => {
+                let rope = &global_data.buffers[global_data.clients[*client].buffer()].rope;
+                let position = &global_data.clients[*client].window().cursor.position;
+                match word_under_cursor(position, rope) {
+                    Some((start, word)) => {
+                        // Going backward starts from the word so it doesn't find itself
+                        let from = if *backward {
+                            start
+                        } else {
+                            char_index(position, rope)
+                        };
+                        let pattern = format!(r"\b{}\b", regex::escape(&word));
+                        global_data.search.set_pattern(&pattern, *backward);
+                        jump_to_match(global_data, *client, &pattern, from, *backward, send_cmd);
+                    }
+                    None => send_cmd(
+                        *client,
+                        Cmd::Notify(NotifyLevel::Error, "No string under cursor".into()),
+                    ),
+                }
+            }
+            Cmd::Substitute(substitution) => {
+                if let Err(err) =
+                    substitute(&mut data, global_data, *client, substitution, send_cmd)
+                {
+                    send_cmd(*client, Cmd::Notify(NotifyLevel::Error, err));
+                }
+            }
+            Cmd::Confirm(answer) => {
+                if let Some(mut confirming) = data.confirming.remove(*client) {
+                    let total = confirming.replacements.len();
+                    let done = match answer {
+                        'y' => {
+                            confirming.accepted.push(true);
+                            false
+                        }
+                        'n' => {
+                            confirming.accepted.push(false);
+                            false
+                        }
+                        'a' => {
+                            confirming.accepted.resize(total, true);
+                            true
+                        }
+                        'l' => {
+                            confirming.accepted.push(true);
+                            true
+                        }
+                        'q' => true,
+                        // Anything else asks the same question again
+                        _ => false,
+                    };
+                    if done || confirming.accepted.len() == total {
+                        finish_substitution(*client, &confirming, send_cmd);
+                        send_cmd(*client, Cmd::ChangeMode(Mode::Normal));
+                    } else {
+                        ask_to_confirm(global_data, *client, &confirming, send_cmd);
+                        data.confirming.insert(*client, confirming);
+                    }
+                }
+            }
+            // Leaving the command line without running the search cancels it
+            Cmd::ChangeMode(mode) if *mode != Mode::Command => {
+                if let Some(pending) = data.pending.remove(*client) {
+                    restore_origin(global_data, *client, &pending);
+                    global_data.search.preview = None;
+                }
+            }
+            _ => {}
+        }
+    }
+    std::mem::forget(data);
+}
+
+#[no_mangle]
+pub fn init(global_data: &mut GlobalData) -> *mut c_void {
+    global_data
+        .ex_commands
+        .register(ExCommand::new("nohlsearch", "noh", Args::None));
+    let options = &mut global_data.options;
+    options.declare(OptionSpec {
+        abbreviation: Some("hls".into()),
+        ..OptionSpec::new("hlsearch", OptionScope::Global, OptionValue::Bool(true))
+    });
+    options.declare(OptionSpec {
+        abbreviation: Some("ic".into()),
+        ..OptionSpec::new("ignorecase", OptionScope::Global, OptionValue::Bool(true))
+    });
+    options.declare(OptionSpec {
+        abbreviation: Some("scs".into()),
+        ..OptionSpec::new("smartcase", OptionScope::Global, OptionValue::Bool(true))
+    });
+    Box::into_raw(Box::new(State::default())) as *mut c_void
+}
+
+#[no_mangle]
+pub fn cleanup(data: *mut c_void) {
+    unsafe {
+        let ptr = Box::from_raw(data as *mut State);
+        drop(ptr);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_find_match_wraps_around() {
+        let rope = Rope::from_str("one two\nthree two\n");
+        let regex = compile("two", true).unwrap();
+        assert_eq!(find_match(&regex, &rope, 0, false), Some((4, false)));
+        assert_eq!(find_match(&regex, &rope, 4, false), Some((14, false)));
+        assert_eq!(find_match(&regex, &rope, 14, false), Some((4, true)));
+        assert_eq!(find_match(&regex, &rope, 14, true), Some((4, false)));
+        assert_eq!(find_match(&regex, &rope, 4, true), Some((14, true)));
+    }
+
+    #[test]
+    fn test_smart_case() {
+        let mut options = Options::default();
+        for name in &["ignorecase", "smartcase"] {
+            options.declare(OptionSpec::new(
+                name,
+                OptionScope::Global,
+                OptionValue::Bool(true),
+            ));
+        }
+        let rope = Rope::from_str("Alpha alpha\n");
+        let search = |options: &Options, pattern: &str| {
+            let regex = compile(pattern, ignores_case(options, pattern)).unwrap();
+            find_match(&regex, &rope, 3, false)
+        };
+        assert_eq!(search(&options, "alpha"), Some((6, false)));
+        assert_eq!(search(&options, "Alpha"), Some((0, true)));
+        options
+            .set("smartcase", OptionValue::Bool(false), None)
+            .unwrap();
+        assert_eq!(search(&options, "ALPHA"), Some((6, false)));
+        options
+            .set("ignorecase", OptionValue::Bool(false), None)
+            .unwrap();
+        assert_eq!(search(&options, "alpha"), Some((6, false)));
+        assert_eq!(search(&options, "ALPHA"), None);
+    }
+
+    #[test]
+    fn test_word_under_cursor() {
+        let rope = Rope::from_str("let some_value = 1;\n");
+        assert_eq!(
+            word_under_cursor(&Point { y: 0, x: 8 }, &rope),
+            Some((4, "some_value".to_string()))
+        );
+        assert_eq!(word_under_cursor(&Point { y: 0, x: 4 }, &rope), None);
+    }
+}
@@ -0,0 +1,121 @@
+use regex::Regex;
+
+/// One match to replace, as byte offsets into the text of the lines
+#[derive(Debug, PartialEq)]
+pub struct Replacement {
+    pub start: usize,
+    pub end: usize,
+    pub text: String,
+}
+
+/// Turns vim's `&`, `\1` and `\n` into what `Captures::expand` understands
+pub fn translate_replacement(replacement: &str) -> String {
+    let mut translated = String::new();
+    let mut chars = replacement.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '&' => translated.push_str("${0}"),
+            '$' => translated.push_str("$$"),
+            '\\' => match chars.next() {
+                Some(digit) if digit.is_ascii_digit() => {
+                    translated.push_str(&format!("${{{}}}", digit))
+                }
+                Some('n') | Some('r') => translated.push('\n'),
+                Some('t') => translated.push('\t'),
+                Some('$') => translated.push_str("$$"),
+                // Covers \& and \\ along with anything else that needn't be escaped
+                Some(other) => translated.push(other),
+                None => translated.push('\\'),
+            },
+            other => translated.push(other),
+        }
+    }
+    translated
+}
+
+/// Every match in `text`, or only the first one on each line unless `global`
+pub fn find_replacements(
+    regex: &Regex,
+    template: &str,
+    text: &str,
+    global: bool,
+) -> Vec<Replacement> {
+    let mut replacements = Vec::new();
+    let mut previous_line_start = None;
+    for captures in regex.captures_iter(text) {
+        let found = captures.get(0).unwrap();
+        if !global {
+            let line_start = text[..found.start()]
+                .rfind('\n')
+                .map(|index| index + 1)
+                .unwrap_or(0);
+            if previous_line_start == Some(line_start) {
+                continue;
+            }
+            previous_line_start = Some(line_start);
+        }
+        let mut replaced = String::new();
+        captures.expand(template, &mut replaced);
+        replacements.push(Replacement {
+            start: found.start(),
+            end: found.end(),
+            text: replaced,
+        });
+    }
+    replacements
+}
+
+/// Replaces the accepted matches. Also returns where the last replacement
+/// starts in the new text, if any were made.
+pub fn apply_replacements(
+    text: &str,
+    replacements: &[Replacement],
+    accepted: &[bool],
+) -> (String, Option<usize>) {
+    let mut result = String::with_capacity(text.len());
+    let mut last_start = None;
+    let mut copied_up_to = 0;
+    for (replacement, _) in replacements
+        .iter()
+        .zip(accepted)
+        .filter(|(_, accepted)| **accepted)
+    {
+        result.push_str(&text[copied_up_to..replacement.start]);
+        last_start = Some(result.len());
+        result.push_str(&replacement.text);
+        copied_up_to = replacement.end;
+    }
+    result.push_str(&text[copied_up_to..]);
+    (result, last_start)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_translate_replacement() {
+        assert_eq!(translate_replacement(r"\2-\1"), "${2}-${1}");
+        assert_eq!(translate_replacement(r"[&] \& $5"), "[${0}] & $$5");
+        assert_eq!(translate_replacement(r"a\nb\\"), "a\nb\\");
+    }
+
+    #[test]
+    fn test_substitute_first_or_every_match() {
+        let regex = Regex::new(r"(\w+)=(\w+)").unwrap();
+        let template = translate_replacement(r"\2=\1");
+        let text = "a=b c=d\ne=f\n";
+        let first = find_replacements(&regex, &template, text, false);
+        assert_eq!(first.len(), 2);
+        let all_accepted = vec![true; first.len()];
+        assert_eq!(
+            apply_replacements(text, &first, &all_accepted),
+            ("b=a c=d\nf=e\n".to_string(), Some(8))
+        );
+        let every = find_replacements(&regex, &template, text, true);
+        assert_eq!(
+            apply_replacements(text, &every, &[false, true, true]).0,
+            "a=b d=c\nf=e\n"
+        );
+    }
+}
@@ -0,0 +1,295 @@
+use std::ffi::c_void;
+use types::{
+    BackBuffer, Buffer, Client, ClientIndex, Cmd, GlobalData, Mode, Msg, NotifyLevel, Point,
+    SecondaryMap, StatusSegment, Theme, Utils,
+};
+
+// Oldest messages are dropped once a client has this many
+const MAX_HISTORY: usize = 200;
+
+#[derive(Debug, Clone)]
+struct Notification {
+    level: NotifyLevel,
+    text: String,
+}
+
+#[derive(Debug, Default, Clone)]
+struct Messages {
+    history: Vec<Notification>,
+    /// Latest message is shown until the next key press
+    show_latest: bool,
+    /// Lines from `:messages` or another command, also closed by the next key press
+    output: Option<Vec<Notification>>,
+}
+
+#[derive(Debug, Default)]
+struct State {
+    messages: SecondaryMap<ClientIndex, Messages>,
+}
+
+fn level_scope(level: &NotifyLevel) -> &'static str {
+    match level {
+        NotifyLevel::Info => "ui.message.info",
+        NotifyLevel::Warning => "ui.message.warning",
+        NotifyLevel::Error => "ui.message.error",
+    }
+}
+
+fn render_notification(
+    back_buffer: &mut BackBuffer,
+    utils: &Utils,
+    theme: &Theme,
+    notification: &Notification,
+    y: u16,
+    width: usize,
+) {
+    let text: String = format!("{:width$}", notification.text, width = width)
+        .chars()
+        .take(width)
+        .collect();
+    (utils.write_to_buffer)(
+        back_buffer,
+        &Point { x: 0, y },
+        &text,
+        None,
+        theme.fg(level_scope(&notification.level)),
+        None,
+    );
+}
+
+// Last lines of the output, drawn over the windows and ending right above the status line
+fn render_output(
+    back_buffer: &mut BackBuffer,
+    utils: &Utils,
+    theme: &Theme,
+    history: &[Notification],
+    status_row: u16,
+    width: usize,
+) {
+    let shown = std::cmp::min(history.len(), status_row as usize);
+    let first_row = status_row - shown as u16;
+    for (offset, notification) in history[history.len() - shown..].iter().enumerate() {
+        render_notification(
+            back_buffer,
+            utils,
+            theme,
+            notification,
+            first_row + offset as u16,
+            width,
+        );
+    }
+}
+
+fn file_type(buffer: &Buffer) -> String {
+    match buffer.source.extension().and_then(|ext| ext.to_str()) {
+        Some("rs") => "rust".into(),
+        Some("toml") => "toml".into(),
+        Some("md") => "markdown".into(),
+        Some(ext) => ext.into(),
+        None => "text".into(),
+    }
+}
+
+fn line_ending(buffer: &Buffer) -> &'static str {
+    let rope = &buffer.rope;
+    if rope.len_lines() > 1 {
+        let first_line = rope.line(0);
+        let len = first_line.len_chars();
+        if len >= 2 && first_line.char(len - 2) == '\r' {
+            return "crlf";
+        }
+    }
+    "lf"
+}
+
+fn segment_text(segment: &StatusSegment, client: &Client, buffer: &Buffer) -> String {
+    let cursor = &client.window().cursor;
+    match segment {
+        StatusSegment::Mode => match client.mode {
+            Mode::Normal => "NORMAL",
+            Mode::Insert => "INSERT",
+            Mode::Command => "COMMAND",
+            Mode::Picker => "PICKER",
+            Mode::Confirm => "CONFIRM",
+            Mode::Visual => "VISUAL",
+            Mode::VisualLine => "V-LINE",
+            Mode::VisualBlock => "V-BLOCK",
+        }
+        .into(),
+        StatusSegment::FilePath => buffer.display_name(),
+        StatusSegment::Modified => {
+            if buffer.is_modified() {
+                "[+]".into()
+            } else {
+                String::new()
+            }
+        }
+        StatusSegment::Position => format!("{}:{}", cursor.position.y + 1, cursor.position.x),
+        StatusSegment::Percentage => {
+            // The rope always ends with an empty line after the final newline
+            let line_count = std::cmp::max(buffer.rope.len_lines().saturating_sub(1), 1);
+            let line = std::cmp::min(cursor.position.y as usize + 1, line_count);
+            format!("{}%", line * 100 / line_count)
+        }
+        StatusSegment::FileType => file_type(buffer),
+        StatusSegment::LineEnding => line_ending(buffer).into(),
+        StatusSegment::Diagnostics => {
+            let counts = &buffer.diagnostics;
+            if counts.errors == 0 && counts.warnings == 0 {
+                String::new()
+            } else {
+                format!("E:{} W:{}", counts.errors, counts.warnings)
+            }
+        }
+        StatusSegment::Text(text) => text.clone(),
+    }
+}
+
+// Empty segments are skipped so that they don't leave double spaces behind
+fn join_segments(segments: &[StatusSegment], client: &Client, buffer: &Buffer) -> String {
+    segments
+        .iter()
+        .map(|segment| segment_text(segment, client, buffer))
+        .filter(|text| !text.is_empty())
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+#[no_mangle]
+pub fn render(
+    global_data: &GlobalData,
+    client_index: &ClientIndex,
+    back_buffer: &mut BackBuffer,
+    utils: &Utils,
+    data_ptr: *mut c_void,
+) {
+    let data: Box<State> = unsafe { Box::from_raw(data_ptr as *mut State) };
+    let client = &global_data.clients[*client_index];
+    if let (Some(size), Some(status_row)) = (client.size.as_ref(), client.status_row()) {
+        let width = size.w as usize;
+        let buffer = &global_data.buffers[client.buffer()];
+        let config = &global_data.settings.status_line;
+        let left = format!(" {}", join_segments(&config.left, client, buffer));
+        let right = format!("{} ", join_segments(&config.right, client, buffer));
+        let left_width = left.chars().count();
+        let right_width = right.chars().count();
+        // The left side wins when there isn't room for both
+        let line: String = if left_width + right_width < width {
+            format!(
+                "{}{}{}",
+                left,
+                " ".repeat(width - left_width - right_width),
+                right
+            )
+        } else {
+            format!("{:width$}", left, width = width)
+                .chars()
+                .take(width)
+                .collect()
+        };
+        let theme = &global_data.theme;
+        let status_style = theme.get("ui.statusline");
+        (utils.write_to_buffer)(
+            back_buffer,
+            &Point {
+                x: 0,
+                y: status_row,
+            },
+            &line,
+            status_style.style,
+            status_style.fg,
+            status_style.bg,
+        );
+        if let Some(messages) = data.messages.get(*client_index) {
+            if let Some(output) = &messages.output {
+                render_output(back_buffer, utils, theme, output, status_row, width);
+            }
+            if let (true, Some(latest), Some(command_row), false) = (
+                messages.show_latest,
+                messages.history.last(),
+                client.command_row(),
+                client.mode == Mode::Command,
+            ) {
+                render_notification(back_buffer, utils, theme, latest, command_row, width);
+            }
+        }
+    }
+    std::mem::forget(data);
+}
+
+#[no_mangle]
+pub fn update(
+    _global_data: &mut GlobalData,
+    msg: &Msg,
+    utils: &Utils,
+    _send_cmd: &Box<Fn(ClientIndex, Cmd)>,
+    data_ptr: *mut c_void,
+) {
+    let mut data: Box<State> = unsafe { Box::from_raw(data_ptr as *mut State) };
+    match msg {
+        Msg::Cmd(client, Cmd::Notify(level, text)) => {
+            match level {
+                NotifyLevel::Error | NotifyLevel::Warning => (utils.warn)(text),
+                NotifyLevel::Info => (utils.info)(text),
+            }
+            if !data.messages.contains_key(*client) {
+                data.messages.insert(*client, Messages::default());
+            }
+            let messages = &mut data.messages[*client];
+            messages.history.push(Notification {
+                level: level.clone(),
+                text: text.clone(),
+            });
+            if messages.history.len() > MAX_HISTORY {
+                messages.history.remove(0);
+            }
+            messages.show_latest = true;
+        }
+        Msg::Cmd(client, Cmd::ShowMessages) => {
+            if let Some(messages) = data.messages.get_mut(*client) {
+                messages.output = Some(messages.history.clone());
+            }
+        }
+        Msg::Cmd(client, Cmd::ShowOutput(lines)) => {
+            if !data.messages.contains_key(*client) {
+                data.messages.insert(*client, Messages::default());
+            }
+            data.messages[*client].output = Some(
+                lines
+                    .iter()
+                    .map(|line| Notification {
+                        level: NotifyLevel::Info,
+                        text: line.clone(),
+                    })
+                    .collect(),
+            );
+        }
+        Msg::StdinEvent(client, _) => {
+            if let Some(messages) = data.messages.get_mut(*client) {
+                messages.show_latest = false;
+                messages.output = None;
+            }
+        }
+        _ => {}
+    }
+    std::mem::forget(data);
+}
+
+/// Drawn after the other libs so command output covers the windows
+#[no_mangle]
+pub fn layer() -> i32 {
+    1
+}
+
+#[no_mangle]
+pub fn init() -> *mut c_void {
+    Box::into_raw(Box::new(State::default())) as *mut c_void
+}
+
+#[no_mangle]
+pub fn cleanup(data: *mut c_void) {
+    unsafe {
+        let ptr = Box::from_raw(data as *mut State);
+        drop(ptr);
+    }
+}
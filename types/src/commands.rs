@@ -1,7 +1,10 @@
-use crate::{DeleteDirection, Direction, JumpType, Mode, Point, Rect};
+use crate::{
+    BufferIndex, DeleteDirection, Direction, ExCall, JumpType, Mode, Picker, PickerSource, Point,
+    Rect, SplitDirection, Substitution, TextObject,
+};
 use serde::{Deserialize, Serialize};
 
-#[derive(Debug, Deserialize, Serialize, PartialEq)]
+#[derive(Debug, Clone, Deserialize, Serialize, PartialEq)]
 pub enum Cmd {
     MoveCursor(Direction, bool),
     Quit,
@@ -11,18 +14,159 @@ pub enum Cmd {
     InsertCharAtPoint(char, Point),
     InsertStringAtPoint(String, Point),
     DeleteCharRange(Point, Point),
+    /// Inserts the string at every point, for editing with several cursors
+    InsertStringAtPoints(String, Vec<Point>),
+    /// Deletes every inclusive range, for editing with several cursors
+    DeleteCharRanges(Vec<(Point, Point)>),
     DeleteChar(DeleteDirection),
     Jump(JumpType),
     RunCommand,
-    WriteBuffer(std::path::PathBuf),
+    WriteBuffer(std::path::PathBuf, WriteOptions),
     LoadFile(std::path::PathBuf),
+    /// Writes every modified buffer back to its own file
+    WriteAllBuffers,
+    ShowBuffer(BufferIndex),
+    /// Switches the active window back to the buffer it showed before
+    AlternateBuffer,
+    /// Removes a buffer, refusing while it has unsaved changes unless forced
+    DeleteBuffer(BufferIndex, bool),
+    /// Sent once a buffer is gone so plugins can drop whatever they kept for it
+    BufferDeleted(BufferIndex),
+    /// Throws away unsaved changes and loads the file again
+    ReloadBuffer,
+    /// Keeps the buffer as it is after its file changed on disk
+    KeepBuffer,
+    /// Opens the differences between the buffer and its file on disk in a split
+    DiffWithDisk,
+    /// Quits the client, refusing while buffers have unsaved changes unless forced
+    QuitAll(bool),
     BufferLoaded,
     BufferModified,
-    SearchFiles,
+    /// The text of a buffer was loaded again from disk, sent once for it
+    /// whichever windows show it
+    BufferReloaded(BufferIndex),
+    /// Asks whichever plugin owns the source to open a picker over it
+    Pick(PickerSource),
+    OpenPicker(Picker),
+    PickerInsertChar(char),
+    PickerDeleteChar,
+    /// Moves the picker selection down by the given number of rows, up when negative
+    PickerMove(i32),
+    /// Closes the picker and sends the action of the selected item
+    PickerAccept,
     CleanRender,
     ResizeClient(Rect),
-    Yank,
+    /// Runs an operator over the selection, then leaves visual mode
+    ApplyOperator(Operator),
+    /// Moves the cursor to the other end of the selection, as `o` in visual mode does
+    SwapSelectionEnds,
+    /// Selects the text object around the cursor, then runs the operator on it if there is one
+    SelectObject(TextObject, Option<Operator>),
+    /// Selects from the first point to the second, both included
+    Select(Point, Point),
+    /// Adds a cursor on the next match of the selection, or of the word under the cursor
+    AddCursorAtNextMatch,
+    /// Puts a cursor on every line of the selection
+    AddCursorsOnLines,
+    /// Drops the newest cursor and adds one on the match after it instead
+    SkipCursor,
+    /// Drops the newest cursor, going back to the one added before it
+    RemoveCursor,
+    /// Drops every cursor but the newest
+    RemoveExtraCursors,
+    /// Marks the cursor position with the name
+    SetMark(char),
+    /// Goes to the mark with the name, to its column when true and else to its first non-blank
+    JumpToMark(char, bool),
+    /// Adds the cursor position to the jump list, sent before big motions
+    RememberJump,
+    /// Goes back to where the last big motion left from
+    JumpBack,
+    /// Goes forward again along the jump list after `JumpBack`
+    JumpForward,
+    /// Goes to where the name under the cursor is defined
+    GotoDefinition,
     YankValue(String),
     Paste,
     PasteAtPoint(Point),
+    SplitWindow(SplitDirection),
+    CloseWindow,
+    FocusWindow(Direction),
+    ResizeWindow(SplitDirection, i16),
+    NewTab,
+    CloseTab,
+    NextTab,
+    PreviousTab,
+    RenameTab(String),
+    /// Message for the user, shown under the status line until the next key press
+    Notify(NotifyLevel, String),
+    ShowMessages,
+    /// Lines of command output shown over the windows until the next key press
+    ShowOutput(Vec<String>),
+    CompleteCommand,
+    /// Runs a registered ex command, handled by whichever plugin registered it
+    RunExCommand(ExCall),
+    /// Runs text as if it was typed on the command line, for keymaps
+    RunCommandLine(String),
+    /// The option with this name was set, for plugins that keep something derived from it
+    OptionChanged(String),
+    /// Opens the command line with the given text already typed in
+    EditCommandLine(String),
+    CommandLineEdit(LineEdit),
+    /// Steps through earlier command lines that start with what was typed, older when true
+    CommandHistory(bool),
+    /// Opens the command line for a search, backward when true
+    StartSearch(bool),
+    /// Search text changed while typing it, for highlighting as you type
+    UpdateSearch(String),
+    /// Finishes the search being typed, an empty pattern repeats the last one
+    RunSearch(String),
+    /// Repeats the last search, in the opposite direction when true
+    SearchNext(bool),
+    /// Searches for the word under the cursor, backward when true
+    SearchWord(bool),
+    Substitute(Substitution),
+    /// Key pressed to answer a question while in confirm mode
+    Confirm(char),
+    /// Replaces whole lines, 0 based and inclusive, with new text in one edit
+    ReplaceLines(usize, usize, String),
+}
+
+#[derive(Debug, Clone, Default, Deserialize, Serialize, PartialEq)]
+pub struct WriteOptions {
+    /// Create missing parent directories instead of failing
+    pub create_directories: bool,
+    /// Bind the buffer to the written path from now on, as `:saveas` does
+    pub set_source: bool,
+    /// Write even though the file changed on disk since it was loaded
+    pub overwrite_changed: bool,
+}
+
+/// What a visual mode operator does to the selected text
+#[derive(Debug, Clone, Deserialize, Serialize, PartialEq)]
+pub enum Operator {
+    Delete,
+    Yank,
+    /// Deletes and goes to insert mode
+    Change,
+    /// Shifts the selected lines right by `shiftwidth`
+    Indent,
+    Outdent,
+    ToggleCase,
+}
+
+/// Readline style edits for the command line
+#[derive(Debug, Clone, Deserialize, Serialize, PartialEq)]
+pub enum LineEdit {
+    StartOfLine,
+    EndOfLine,
+    DeleteWordBefore,
+    DeleteToStart,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize, PartialEq)]
+pub enum NotifyLevel {
+    Info,
+    Warning,
+    Error,
 }
@@ -0,0 +1,128 @@
+use crate::Point;
+use ropey::Rope;
+
+/// Char index of the 1 based cursor column `point`, no further than the end of
+/// `rope` so that the cursor past the last char can still insert there
+pub fn char_index(point: &Point, rope: &Rope) -> usize {
+    let index = rope.line_to_char(point.y as usize) + point.x.saturating_sub(1) as usize;
+    std::cmp::min(index, rope.len_chars())
+}
+
+/// Cursor position of the char at `index`, the other way from `char_index`
+pub fn point_from_char(index: usize, rope: &Rope) -> Point {
+    let line = rope.char_to_line(index);
+    Point {
+        y: line as u16,
+        x: (index - rope.line_to_char(line)) as u16 + 1,
+    }
+}
+
+/// Inserts `text` at each of `points` in one go. Returns where each point ends
+/// up, just after the text that went in there, so that later points account for
+/// the text inserted before them. Points that are the same only get `text` once.
+pub fn insert_at_points(rope: &mut Rope, text: &str, points: &[Point]) -> Vec<Point> {
+    let indices: Vec<usize> = points.iter().map(|point| char_index(point, rope)).collect();
+    let mut targets = indices.clone();
+    targets.sort();
+    targets.dedup();
+    // Back to front so that the indices still to go stay where they were
+    for index in targets.iter().rev() {
+        rope.insert(*index, text);
+    }
+    let length = text.chars().count();
+    indices
+        .iter()
+        .map(|index| {
+            let inserted = targets.iter().filter(|target| *target <= index).count();
+            point_from_char(index + inserted * length, rope)
+        })
+        .collect()
+}
+
+/// Removes each inclusive range between two points in one go, ranges that
+/// overlap are merged. Returns where the start of each range ends up.
+pub fn remove_ranges(rope: &mut Rope, ranges: &[(Point, Point)]) -> Vec<Point> {
+    let bounds: Vec<(usize, usize)> = ranges
+        .iter()
+        .map(|(a, b)| {
+            let (a, b) = (char_index(a, rope), char_index(b, rope));
+            let (start, end) = (std::cmp::min(a, b), std::cmp::max(a, b));
+            (start, std::cmp::min(end + 1, rope.len_chars()))
+        })
+        .collect();
+    let mut merged: Vec<(usize, usize)> = bounds.clone();
+    merged.sort();
+    merged.dedup();
+    let mut removals: Vec<(usize, usize)> = Vec::new();
+    for (start, end) in merged {
+        match removals.last_mut() {
+            Some(last) if start <= last.1 => last.1 = std::cmp::max(last.1, end),
+            _ => removals.push((start, end)),
+        }
+    }
+    for (start, end) in removals.iter().rev() {
+        rope.remove(*start..*end);
+    }
+    // Everything removed before an index moves it back by as much
+    let shifted = |index: usize| {
+        let removed: usize = removals
+            .iter()
+            .map(|(start, end)| std::cmp::min(index.saturating_sub(*start), end - start))
+            .sum();
+        index - removed
+    };
+    bounds
+        .iter()
+        .map(|(start, _)| point_from_char(shifted(*start), rope))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn point(x: u16, y: u16) -> Point {
+        Point { x, y }
+    }
+
+    #[test]
+    fn test_char_index_round_trip() {
+        let rope = Rope::from_str("ab\ncd");
+        assert_eq!(char_index(&point(2, 1), &rope), 4);
+        assert_eq!(point_from_char(4, &rope), point(2, 1));
+        // Past the last char is the end of the text, where inserting still works
+        assert_eq!(char_index(&point(3, 1), &rope), 5);
+        assert_eq!(char_index(&point(9, 1), &rope), 5);
+        assert_eq!(point_from_char(5, &rope), point(3, 1));
+    }
+
+    #[test]
+    fn test_insert_at_points() {
+        let mut rope = Rope::from_str("a: u8,\nb: u8,\n");
+        let moved = insert_at_points(&mut rope, "x", &[point(2, 1), point(1, 0), point(2, 0)]);
+        assert_eq!(rope.to_string(), "xax: u8,\nbx: u8,\n");
+        assert_eq!(moved, vec![point(3, 1), point(2, 0), point(4, 0)]);
+        let moved = insert_at_points(&mut rope, "\n", &[point(2, 0), point(2, 0)]);
+        assert_eq!(rope.to_string(), "x\nax: u8,\nbx: u8,\n");
+        assert_eq!(moved, vec![point(1, 1), point(1, 1)]);
+    }
+
+    #[test]
+    fn test_remove_ranges() {
+        let mut rope = Rope::from_str("foo(a);\nfoo(b);\n");
+        let moved = remove_ranges(
+            &mut rope,
+            &[(point(3, 1), point(1, 1)), (point(1, 0), point(3, 0))],
+        );
+        assert_eq!(rope.to_string(), "(a);\n(b);\n");
+        assert_eq!(moved, vec![point(1, 1), point(1, 0)]);
+        // Ranges that overlap only remove their chars once
+        let mut rope = Rope::from_str("abcdef");
+        let moved = remove_ranges(
+            &mut rope,
+            &[(point(2, 0), point(4, 0)), (point(3, 0), point(5, 0))],
+        );
+        assert_eq!(rope.to_string(), "af");
+        assert_eq!(moved, vec![point(2, 0), point(2, 0)]);
+    }
+}
@@ -0,0 +1,129 @@
+use serde::{Deserialize, Serialize};
+
+/// What sort of argument an ex command takes, used to check and complete it
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum ArgKind {
+    Path,
+    /// A buffer number or part of a buffer name
+    Buffer,
+    /// The name of a setting
+    Option,
+    /// A whole number, optionally signed
+    Number,
+    /// Anything at all, left to the command to make sense of
+    Text,
+    /// One of a fixed set of words
+    Choice(Vec<String>),
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum Args {
+    None,
+    Optional(ArgKind),
+    Required(ArgKind),
+}
+
+/// An ex command that can be run from the command line
+#[derive(Debug, Clone, PartialEq)]
+pub struct ExCommand {
+    pub name: String,
+    /// Shortest prefix of the name that still runs it, like `b` for `buffer`
+    pub abbreviation: String,
+    pub args: Args,
+    /// Whether it can be given lines to work on, like `%` or `3,5`
+    pub range: bool,
+    /// Whether it can be forced with a `!`
+    pub bang: bool,
+}
+
+impl ExCommand {
+    pub fn new(name: &str, abbreviation: &str, args: Args) -> Self {
+        ExCommand {
+            name: name.into(),
+            abbreviation: abbreviation.into(),
+            args,
+            range: false,
+            bang: false,
+        }
+    }
+
+    /// Whether typing `word` runs this command
+    pub fn matches(&self, word: &str) -> bool {
+        word.len() >= self.abbreviation.len() && self.name.starts_with(word)
+    }
+}
+
+/// Every ex command, plugins add theirs when they're loaded
+#[derive(Debug, Default, Clone)]
+pub struct ExCommands {
+    commands: Vec<ExCommand>,
+}
+
+impl ExCommands {
+    /// Adds `command`, replacing any with the same name so a reloaded plugin
+    /// doesn't end up with its commands twice
+    pub fn register(&mut self, command: ExCommand) {
+        match self
+            .commands
+            .iter_mut()
+            .find(|existing| existing.name == command.name)
+        {
+            Some(existing) => *existing = command,
+            None => self.commands.push(command),
+        }
+    }
+
+    /// The command `word` runs. A full name wins over an abbreviation, then the
+    /// first one registered does.
+    pub fn find(&self, word: &str) -> Option<&ExCommand> {
+        self.commands
+            .iter()
+            .find(|command| command.name == word)
+            .or_else(|| self.commands.iter().find(|command| command.matches(word)))
+    }
+
+    /// Names starting with `prefix`, sorted
+    pub fn names_starting_with(&self, prefix: &str) -> Vec<String> {
+        let mut names: Vec<String> = self
+            .commands
+            .iter()
+            .filter(|command| command.name.starts_with(prefix))
+            .map(|command| command.name.clone())
+            .collect();
+        names.sort();
+        names
+    }
+}
+
+/// A command line that named a registered command, already checked against it
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ExCall {
+    /// Full name of the command, whichever abbreviation was typed
+    pub name: String,
+    pub bang: bool,
+    /// First and last line, 0 based and inclusive, when a range was given
+    pub range: Option<(usize, usize)>,
+    /// Everything after the name, trimmed
+    pub argument: String,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_find_by_abbreviation() {
+        let mut commands = ExCommands::default();
+        commands.register(ExCommand::new("write", "w", Args::None));
+        commands.register(ExCommand::new("wall", "wa", Args::None));
+        commands.register(ExCommand::new("tabnext", "tabn", Args::None));
+        commands.register(ExCommand::new("tabnew", "tabnew", Args::None));
+        assert_eq!(commands.find("w").unwrap().name, "write");
+        assert_eq!(commands.find("wri").unwrap().name, "write");
+        assert_eq!(commands.find("wa").unwrap().name, "wall");
+        assert_eq!(commands.find("tabne").unwrap().name, "tabnext");
+        assert_eq!(commands.find("tabnew").unwrap().name, "tabnew");
+        assert!(commands.find("tab").is_none());
+        assert!(commands.find("writes").is_none());
+    }
+}
@@ -0,0 +1,206 @@
+use crate::Point;
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub enum LineNumbers {
+    Off,
+    Absolute,
+    Relative,
+}
+
+#[derive(Debug, Clone)]
+pub struct GutterConfig {
+    pub line_numbers: LineNumbers,
+    pub diagnostic_signs: bool,
+    pub change_marks: bool,
+    pub fold_markers: bool,
+}
+
+impl Default for GutterConfig {
+    fn default() -> GutterConfig {
+        GutterConfig {
+            line_numbers: LineNumbers::Absolute,
+            diagnostic_signs: true,
+            change_marks: false,
+            fold_markers: false,
+        }
+    }
+}
+
+// Keeps short files from shifting the text every time a digit is added
+const MIN_LINE_NUMBER_WIDTH: u16 = 3;
+
+/// Screen columns used by the gutter in front of the buffer text.
+/// Columns are laid out left to right as: signs, change marks, fold markers,
+/// line numbers and then a single separating space.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct GutterLayout {
+    pub sign_x: Option<u16>,
+    pub change_mark_x: Option<u16>,
+    pub fold_marker_x: Option<u16>,
+    pub line_number_x: Option<u16>,
+    pub line_number_width: u16,
+    pub relative_numbers: bool,
+    pub width: u16,
+}
+
+fn digit_count(mut value: usize) -> u16 {
+    let mut digits = 1;
+    while value >= 10 {
+        value /= 10;
+        digits += 1;
+    }
+    digits
+}
+
+impl GutterLayout {
+    pub fn new(config: &GutterConfig, line_count: usize) -> GutterLayout {
+        let mut layout = GutterLayout::default();
+        let mut x = 0;
+        let mut next_column = |enabled: bool, width: u16| {
+            if enabled {
+                let column = x;
+                x += width;
+                Some(column)
+            } else {
+                None
+            }
+        };
+        layout.sign_x = next_column(config.diagnostic_signs, 1);
+        layout.change_mark_x = next_column(config.change_marks, 1);
+        layout.fold_marker_x = next_column(config.fold_markers, 1);
+        if config.line_numbers != LineNumbers::Off {
+            layout.line_number_width =
+                std::cmp::max(digit_count(line_count), MIN_LINE_NUMBER_WIDTH);
+        }
+        layout.line_number_x = next_column(
+            config.line_numbers != LineNumbers::Off,
+            layout.line_number_width,
+        );
+        layout.relative_numbers = config.line_numbers == LineNumbers::Relative;
+        layout.width = if x > 0 { x + 1 } else { 0 };
+        layout
+    }
+
+    /// Number of text columns left over once the gutter is drawn
+    pub fn text_width(&self, screen_width: u16) -> usize {
+        screen_width.saturating_sub(self.width) as usize
+    }
+
+    /// Maps a 0 based buffer column to the screen x it is drawn at, if it is
+    /// not scrolled off to the left
+    pub fn screen_x(&self, column: usize, start_column: usize) -> Option<u16> {
+        if column < start_column {
+            None
+        } else {
+            Some((column - start_column) as u16 + self.width)
+        }
+    }
+
+    /// Maps a 0 based buffer column and buffer line to the screen point it is drawn at
+    pub fn screen_point(
+        &self,
+        column: usize,
+        line: usize,
+        start_column: usize,
+        start_line: usize,
+    ) -> Option<Point> {
+        if line < start_line {
+            return None;
+        }
+        self.screen_x(column, start_column).map(|x| Point {
+            x,
+            y: (line - start_line) as u16,
+        })
+    }
+
+    /// Text shown in the line number column for `line`, right aligned
+    pub fn line_number_label(&self, line: usize, cursor_line: usize) -> String {
+        let number = if self.relative_numbers && line != cursor_line {
+            if line > cursor_line {
+                line - cursor_line
+            } else {
+                cursor_line - line
+            }
+        } else {
+            line + 1
+        };
+        format!(
+            "{:>width$}",
+            number,
+            width = self.line_number_width as usize
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_line_number_width_grows_with_line_count() {
+        let config = GutterConfig {
+            diagnostic_signs: false,
+            ..GutterConfig::default()
+        };
+        assert_eq!(GutterLayout::new(&config, 12).width, 4);
+        assert_eq!(GutterLayout::new(&config, 999).width, 4);
+        assert_eq!(GutterLayout::new(&config, 1000).width, 5);
+        assert_eq!(GutterLayout::new(&config, 123456).width, 7);
+    }
+
+    #[test]
+    fn test_columns_are_laid_out_in_order() {
+        let config = GutterConfig {
+            line_numbers: LineNumbers::Absolute,
+            diagnostic_signs: true,
+            change_marks: true,
+            fold_markers: true,
+        };
+        let layout = GutterLayout::new(&config, 10);
+        assert_eq!(layout.sign_x, Some(0));
+        assert_eq!(layout.change_mark_x, Some(1));
+        assert_eq!(layout.fold_marker_x, Some(2));
+        assert_eq!(layout.line_number_x, Some(3));
+        assert_eq!(layout.width, 7);
+        assert_eq!(layout.text_width(20), 13);
+        assert_eq!(layout.screen_x(0, 0), Some(7));
+        // Turning a column off moves the ones after it over
+        let layout = GutterLayout::new(
+            &GutterConfig {
+                change_marks: false,
+                ..config
+            },
+            10,
+        );
+        assert_eq!(layout.change_mark_x, None);
+        assert_eq!(layout.fold_marker_x, Some(1));
+        assert_eq!(layout.width, 6);
+    }
+
+    #[test]
+    fn test_empty_gutter() {
+        let config = GutterConfig {
+            line_numbers: LineNumbers::Off,
+            diagnostic_signs: false,
+            change_marks: false,
+            fold_markers: false,
+        };
+        let layout = GutterLayout::new(&config, 10);
+        assert_eq!(layout.width, 0);
+        assert_eq!(layout.screen_x(3, 1), Some(2));
+        assert_eq!(layout.screen_x(0, 1), None);
+    }
+
+    #[test]
+    fn test_relative_line_number_label() {
+        let config = GutterConfig {
+            line_numbers: LineNumbers::Relative,
+            ..GutterConfig::default()
+        };
+        let layout = GutterLayout::new(&config, 10);
+        assert_eq!(layout.line_number_label(2, 5), "  3");
+        assert_eq!(layout.line_number_label(5, 5), "  6");
+        assert_eq!(layout.line_number_label(7, 5), "  2");
+    }
+}
@@ -0,0 +1,87 @@
+use crate::Mode;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use termion::event::Key;
+
+/// Keys the config file binds to command lines, per mode. Keys are written the
+/// way vim writes them: `x`, `<C-s>`, `<A-j>`, `<F5>`, `<Esc>`.
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Keymaps {
+    pub normal: HashMap<String, String>,
+    pub insert: HashMap<String, String>,
+    /// Used in all three visual modes
+    pub visual: HashMap<String, String>,
+}
+
+impl Keymaps {
+    /// The command line `key` is bound to in `mode`
+    pub fn get(&self, mode: &Mode, key: &Key) -> Option<&str> {
+        let bindings = match mode {
+            Mode::Normal => &self.normal,
+            Mode::Insert => &self.insert,
+            Mode::Visual | Mode::VisualLine | Mode::VisualBlock => &self.visual,
+            _ => return None,
+        };
+        let name = key_name(key)?;
+        bindings.get(&name).map(String::as_str)
+    }
+
+    /// Adds the bindings from `other`, which win where both bind a key
+    pub fn extend(&mut self, other: Keymaps) {
+        self.normal.extend(other.normal);
+        self.insert.extend(other.insert);
+        self.visual.extend(other.visual);
+    }
+}
+
+/// How keymaps write `key`
+pub fn key_name(key: &Key) -> Option<String> {
+    let name = match key {
+        Key::Char(' ') => "<Space>".into(),
+        Key::Char('\n') => "<CR>".into(),
+        Key::Char('\t') => "<Tab>".into(),
+        Key::Char('<') => "<lt>".into(),
+        Key::Char(c) => c.to_string(),
+        Key::Ctrl(c) => format!("<C-{}>", c),
+        Key::Alt(c) => format!("<A-{}>", c),
+        Key::F(number) => format!("<F{}>", number),
+        Key::Esc => "<Esc>".into(),
+        Key::Backspace => "<BS>".into(),
+        Key::Delete => "<Del>".into(),
+        Key::Insert => "<Insert>".into(),
+        Key::Left => "<Left>".into(),
+        Key::Right => "<Right>".into(),
+        Key::Up => "<Up>".into(),
+        Key::Down => "<Down>".into(),
+        Key::Home => "<Home>".into(),
+        Key::End => "<End>".into(),
+        Key::PageUp => "<PageUp>".into(),
+        Key::PageDown => "<PageDown>".into(),
+        _ => return None,
+    };
+    Some(name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_bindings_by_mode() {
+        let mut keymaps = Keymaps::default();
+        keymaps.normal.insert("<C-s>".into(), "w".into());
+        keymaps.normal.insert("Q".into(), "qa".into());
+        keymaps.insert.insert("<F2>".into(), "w".into());
+        assert_eq!(keymaps.get(&Mode::Normal, &Key::Ctrl('s')), Some("w"));
+        assert_eq!(keymaps.get(&Mode::Normal, &Key::Char('Q')), Some("qa"));
+        assert_eq!(keymaps.get(&Mode::Insert, &Key::Char('Q')), None);
+        assert_eq!(keymaps.get(&Mode::Insert, &Key::F(2)), Some("w"));
+        assert_eq!(keymaps.get(&Mode::Command, &Key::F(2)), None);
+        keymaps.visual.insert("<C-s>".into(), "s/a/b/".into());
+        assert_eq!(
+            keymaps.get(&Mode::VisualLine, &Key::Ctrl('s')),
+            Some("s/a/b/")
+        );
+    }
+}
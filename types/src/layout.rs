@@ -0,0 +1,341 @@
+use crate::{DefaultKey, Point, Rect};
+use serde::{Deserialize, Serialize};
+
+pub type WindowIndex = DefaultKey;
+
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub enum SplitDirection {
+    /// Windows stacked on top of each other
+    Horizontal,
+    /// Windows side by side
+    Vertical,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Layout {
+    Window(WindowIndex),
+    Split {
+        direction: SplitDirection,
+        /// Fraction of the available space given to `first`
+        ratio: f32,
+        first: Box<Layout>,
+        second: Box<Layout>,
+    },
+}
+
+/// Part of the client screen a window is drawn into
+#[derive(Debug, Clone, PartialEq)]
+pub struct WindowRegion {
+    pub window: WindowIndex,
+    pub origin: Point,
+    pub size: Rect,
+}
+
+impl WindowRegion {
+    /// Translates a point relative to the window into client screen space,
+    /// `None` if it falls outside of the window
+    pub fn to_screen(&self, point: &Point) -> Option<Point> {
+        if point.x < self.size.w && point.y < self.size.h {
+            Some(Point {
+                x: point.x + self.origin.x,
+                y: point.y + self.origin.y,
+            })
+        } else {
+            None
+        }
+    }
+}
+
+/// Line drawn between the two halves of a split
+#[derive(Debug, Clone, PartialEq)]
+pub struct Separator {
+    pub direction: SplitDirection,
+    pub origin: Point,
+    pub length: u16,
+}
+
+// Splits `total` cells in two, leaving one cell in between for the separator
+fn split_extent(total: u16, ratio: f32) -> (u16, u16) {
+    let available = total.saturating_sub(1);
+    if available < 2 {
+        return (available, 0);
+    }
+    let first = (available as f32 * ratio).round() as u16;
+    let first = std::cmp::min(std::cmp::max(first, 1), available - 1);
+    (first, available - first)
+}
+
+impl Layout {
+    pub fn contains(&self, target: WindowIndex) -> bool {
+        match self {
+            Layout::Window(index) => *index == target,
+            Layout::Split { first, second, .. } => {
+                first.contains(target) || second.contains(target)
+            }
+        }
+    }
+
+    pub fn windows(&self) -> Vec<WindowIndex> {
+        match self {
+            Layout::Window(index) => vec![*index],
+            Layout::Split { first, second, .. } => {
+                let mut windows = first.windows();
+                windows.extend(second.windows());
+                windows
+            }
+        }
+    }
+
+    /// Replaces `target` with a split holding `new_window` followed by `target`
+    pub fn split(
+        &mut self,
+        target: WindowIndex,
+        new_window: WindowIndex,
+        direction: SplitDirection,
+    ) -> bool {
+        match self {
+            Layout::Window(index) => {
+                if *index != target {
+                    return false;
+                }
+                *self = Layout::Split {
+                    direction,
+                    ratio: 0.5,
+                    first: Box::new(Layout::Window(new_window)),
+                    second: Box::new(Layout::Window(target)),
+                };
+                true
+            }
+            Layout::Split { first, second, .. } => {
+                first.split(target, new_window, direction.clone())
+                    || second.split(target, new_window, direction)
+            }
+        }
+    }
+
+    /// Removes `target` and lets its sibling take over the space.
+    /// The last remaining window can not be removed.
+    pub fn remove(&mut self, target: WindowIndex) -> bool {
+        let sibling = match self {
+            Layout::Window(_) => return false,
+            Layout::Split { first, second, .. } => {
+                if **first == Layout::Window(target) {
+                    (**second).clone()
+                } else if **second == Layout::Window(target) {
+                    (**first).clone()
+                } else {
+                    return first.remove(target) || second.remove(target);
+                }
+            }
+        };
+        *self = sibling;
+        true
+    }
+
+    /// Grows (or shrinks for negative `delta`) `target` by `delta` cells along
+    /// the innermost split in `direction` that contains it
+    pub fn resize(
+        &mut self,
+        target: WindowIndex,
+        direction: &SplitDirection,
+        delta: i32,
+        size: Rect,
+    ) -> bool {
+        match self {
+            Layout::Window(_) => false,
+            Layout::Split {
+                direction: split_direction,
+                ratio,
+                first,
+                second,
+            } => {
+                let total = match split_direction {
+                    SplitDirection::Horizontal => size.h,
+                    SplitDirection::Vertical => size.w,
+                };
+                let (first_extent, second_extent) = split_extent(total, *ratio);
+                let (first_size, second_size) = match split_direction {
+                    SplitDirection::Horizontal => (
+                        Rect {
+                            w: size.w,
+                            h: first_extent,
+                        },
+                        Rect {
+                            w: size.w,
+                            h: second_extent,
+                        },
+                    ),
+                    SplitDirection::Vertical => (
+                        Rect {
+                            w: first_extent,
+                            h: size.h,
+                        },
+                        Rect {
+                            w: second_extent,
+                            h: size.h,
+                        },
+                    ),
+                };
+                let in_first = first.contains(target);
+                if !in_first && !second.contains(target) {
+                    return false;
+                }
+                let handled = if in_first {
+                    first.resize(target, direction, delta, first_size)
+                } else {
+                    second.resize(target, direction, delta, second_size)
+                };
+                if handled || split_direction != direction {
+                    return handled;
+                }
+                let available = (first_extent + second_extent) as i32;
+                if available < 2 {
+                    return true;
+                }
+                let delta = if in_first { delta } else { -delta };
+                let wanted =
+                    std::cmp::min(std::cmp::max(first_extent as i32 + delta, 1), available - 1);
+                *ratio = wanted as f32 / available as f32;
+                true
+            }
+        }
+    }
+
+    fn walk(
+        &self,
+        origin: Point,
+        size: Rect,
+        regions: &mut Vec<WindowRegion>,
+        separators: &mut Vec<Separator>,
+    ) {
+        match self {
+            Layout::Window(window) => regions.push(WindowRegion {
+                window: *window,
+                origin,
+                size,
+            }),
+            Layout::Split {
+                direction,
+                ratio,
+                first,
+                second,
+            } => match direction {
+                SplitDirection::Horizontal => {
+                    let (first_h, second_h) = split_extent(size.h, *ratio);
+                    first.walk(
+                        origin.clone(),
+                        Rect {
+                            w: size.w,
+                            h: first_h,
+                        },
+                        regions,
+                        separators,
+                    );
+                    separators.push(Separator {
+                        direction: direction.clone(),
+                        origin: Point {
+                            x: origin.x,
+                            y: origin.y + first_h,
+                        },
+                        length: size.w,
+                    });
+                    second.walk(
+                        Point {
+                            x: origin.x,
+                            y: origin.y + first_h + 1,
+                        },
+                        Rect {
+                            w: size.w,
+                            h: second_h,
+                        },
+                        regions,
+                        separators,
+                    );
+                }
+                SplitDirection::Vertical => {
+                    let (first_w, second_w) = split_extent(size.w, *ratio);
+                    first.walk(
+                        origin.clone(),
+                        Rect {
+                            w: first_w,
+                            h: size.h,
+                        },
+                        regions,
+                        separators,
+                    );
+                    separators.push(Separator {
+                        direction: direction.clone(),
+                        origin: Point {
+                            x: origin.x + first_w,
+                            y: origin.y,
+                        },
+                        length: size.h,
+                    });
+                    second.walk(
+                        Point {
+                            x: origin.x + first_w + 1,
+                            y: origin.y,
+                        },
+                        Rect {
+                            w: second_w,
+                            h: size.h,
+                        },
+                        regions,
+                        separators,
+                    );
+                }
+            },
+        }
+    }
+
+    pub fn regions(&self, origin: Point, size: Rect) -> Vec<WindowRegion> {
+        let mut regions = Vec::new();
+        self.walk(origin, size, &mut regions, &mut Vec::new());
+        regions
+    }
+
+    pub fn separators(&self, origin: Point, size: Rect) -> Vec<Separator> {
+        let mut separators = Vec::new();
+        self.walk(origin, size, &mut Vec::new(), &mut separators);
+        separators
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::SlotMap;
+
+    #[test]
+    fn test_split_and_remove() {
+        let mut keys: SlotMap<WindowIndex, ()> = SlotMap::new();
+        let (a, b, c) = (keys.insert(()), keys.insert(()), keys.insert(()));
+        let mut layout = Layout::Window(a);
+        assert!(layout.split(a, b, SplitDirection::Vertical));
+        assert!(layout.split(a, c, SplitDirection::Horizontal));
+        assert_eq!(layout.windows(), vec![b, c, a]);
+        assert!(layout.remove(c));
+        assert_eq!(layout.windows(), vec![b, a]);
+        assert!(layout.remove(b));
+        assert_eq!(layout, Layout::Window(a));
+        assert!(!layout.remove(a));
+    }
+
+    #[test]
+    fn test_regions_leave_room_for_separators() {
+        let mut keys: SlotMap<WindowIndex, ()> = SlotMap::new();
+        let (a, b) = (keys.insert(()), keys.insert(()));
+        let mut layout = Layout::Window(a);
+        layout.split(a, b, SplitDirection::Vertical);
+        let size = Rect { w: 81, h: 20 };
+        let regions = layout.regions(Point::default(), size.clone());
+        assert_eq!(regions[0].size, Rect { w: 40, h: 20 });
+        assert_eq!(regions[1].origin, Point { x: 41, y: 0 });
+        assert_eq!(regions[1].size, Rect { w: 40, h: 20 });
+        assert!(layout.resize(b, &SplitDirection::Vertical, 10, size.clone()));
+        let regions = layout.regions(Point::default(), size);
+        assert_eq!(regions[0].window, b);
+        assert_eq!(regions[0].size.w, 50);
+        assert_eq!(regions[1].size.w, 30);
+    }
+}
@@ -6,7 +6,43 @@ use std::os::unix::net::UnixStream;
 use termion::event::Event;
 
 mod commands;
-pub use commands::Cmd;
+pub use commands::{Cmd, LineEdit, NotifyLevel, Operator, WriteOptions};
+
+mod edits;
+pub use edits::{char_index, insert_at_points, point_from_char, remove_ranges};
+
+mod ex_command;
+pub use ex_command::{ArgKind, Args, ExCall, ExCommand, ExCommands};
+
+mod gutter;
+pub use gutter::{GutterConfig, GutterLayout, LineNumbers};
+
+mod keymaps;
+pub use keymaps::{key_name, Keymaps};
+
+mod layout;
+pub use layout::{Layout, Separator, SplitDirection, WindowIndex, WindowRegion};
+
+mod marks;
+pub use marks::{is_mark_name, JumpList, Marks};
+
+mod options;
+pub use options::{OptionScope, OptionSpec, OptionValue, Options};
+
+mod picker;
+pub use picker::{Picker, PickerItem, PickerSource};
+
+mod search;
+pub use search::{SearchState, Substitution};
+
+mod status_line;
+pub use status_line::{DiagnosticCounts, StatusLineConfig, StatusSegment};
+
+mod text_object;
+pub use text_object::{inside_block, is_word, TextObject, TextObjectKind};
+
+mod theme;
+pub use theme::{parse_color, ScopeSpec, ScopeStyle, Theme, ThemeFile};
 
 pub type ClientIndex = DefaultKey;
 
@@ -18,6 +54,155 @@ pub struct GlobalData {
     pub buffers: SecondaryMap<BufferIndex, Buffer>,
     pub client_keys: SlotMap<ClientIndex, ()>,
     pub clients: SecondaryMap<ClientIndex, Client>,
+    pub settings: Settings,
+    pub search: SearchState,
+    pub ex_commands: ExCommands,
+    pub options: Options,
+    pub keymaps: Keymaps,
+    pub theme: Theme,
+    /// Tabs of the last client that quit and the one that was active, handed
+    /// back to the next client that connects
+    pub detached_tabs: Option<(Vec<Tab>, usize)>,
+    /// Number given to the newest buffer, numbers are never handed out twice
+    pub last_buffer_number: usize,
+}
+
+impl GlobalData {
+    /// Stores `buffer` under a number no other buffer has had
+    pub fn add_buffer(&mut self, mut buffer: Buffer) -> BufferIndex {
+        self.last_buffer_number += 1;
+        buffer.number = self.last_buffer_number;
+        let buffer_key = self.buffer_keys.insert(());
+        self.buffers.insert(buffer_key, buffer);
+        buffer_key
+    }
+
+    /// Buffers in the order they were opened
+    pub fn buffers_by_number(&self) -> Vec<BufferIndex> {
+        let mut buffers: Vec<_> = self.buffers.iter().collect();
+        buffers.sort_by_key(|(_index, buffer)| buffer.number);
+        buffers.into_iter().map(|(index, _buffer)| index).collect()
+    }
+
+    /// Where mark `name` is as seen from `buffer`. Uppercase marks can be in any buffer.
+    pub fn find_mark(&self, buffer: BufferIndex, name: char) -> Option<(BufferIndex, Point)> {
+        if name.is_ascii_uppercase() {
+            self.buffers.iter().find_map(|(index, other)| {
+                other.marks.get(name).map(|point| (index, point.clone()))
+            })
+        } else {
+            self.buffers
+                .get(buffer)
+                .and_then(|buffer| buffer.marks.get(name))
+                .map(|point| (buffer, point.clone()))
+        }
+    }
+
+    /// Sets mark `name` in `buffer`, moving an uppercase one there from any other buffer
+    pub fn set_mark(&mut self, buffer: BufferIndex, name: char, point: Point) {
+        if name.is_ascii_uppercase() {
+            for other in self.buffers.values_mut() {
+                other.marks.remove(name);
+            }
+        }
+        if let Some(buffer) = self.buffers.get_mut(buffer) {
+            buffer.marks.set(name, point);
+        }
+    }
+
+    /// Adds a client talking over `stream`. It gets the tabs of the last client
+    /// that quit, if any, otherwise a single tab showing the first buffer.
+    pub fn attach_client(&mut self, stream: UnixStream) -> ClientIndex {
+        let first_buffer = self.buffers_by_number()[0];
+        let mut client = Client::new(stream, first_buffer);
+        if let Some((tabs, active_tab)) = self.detached_tabs.take() {
+            client.active_tab = std::cmp::min(active_tab, tabs.len() - 1);
+            client.tabs = tabs;
+        }
+        let index = self.client_keys.insert(());
+        self.clients.insert(index, client);
+        index
+    }
+
+    /// Removes `client`, keeping its tabs for the next client that connects
+    pub fn detach_client(&mut self, client: ClientIndex) -> Option<Client> {
+        self.client_keys.remove(client);
+        let mut client = self.clients.remove(client)?;
+        let tabs = std::mem::replace(&mut client.tabs, Vec::new());
+        self.detached_tabs = Some((tabs, client.active_tab));
+        Some(client)
+    }
+
+    /// Removes `buffer` along with its marks and options, moving every window
+    /// that showed it, detached ones included, over to its alternate buffer or,
+    /// failing that, any other buffer
+    pub fn remove_buffer(&mut self, buffer: BufferIndex) {
+        let remaining = self
+            .buffers_by_number()
+            .into_iter()
+            .find(|index| *index != buffer);
+        // There always has to be something to show
+        let fallback = match remaining {
+            Some(index) => index,
+            None => self.add_buffer(Buffer::default()),
+        };
+        let buffer_keys = &self.buffer_keys;
+        let buffers = &self.buffers;
+        let detached = self
+            .detached_tabs
+            .iter_mut()
+            .flat_map(|(tabs, _active_tab)| tabs.iter_mut());
+        let tabs = self
+            .clients
+            .values_mut()
+            .flat_map(|client| client.tabs.iter_mut())
+            .chain(detached);
+        for window in tabs.flat_map(|tab| tab.windows.values_mut()) {
+            if window.buffer == buffer {
+                let replacement = window
+                    .alternate
+                    .filter(|alternate| {
+                        *alternate != buffer && buffer_keys.contains_key(*alternate)
+                    })
+                    .unwrap_or(fallback);
+                window.show_buffer(replacement, &buffers[replacement].rope);
+            }
+            window.views.remove(&buffer);
+            if window.alternate == Some(buffer) {
+                window.alternate = None;
+            }
+            window.jumps.forget_buffer(buffer);
+        }
+        self.options.forget_buffer(buffer);
+        self.buffers.remove(buffer);
+        self.buffer_keys.remove(buffer);
+    }
+}
+
+/// Settings with more structure than fits an option, see `Options` for the rest
+#[derive(Debug, Clone)]
+pub struct Settings {
+    pub gutter: GutterConfig,
+    pub status_line: StatusLineConfig,
+}
+
+/// Where the previous version of a file is kept when it gets overwritten
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub enum Backup {
+    Off,
+    /// `file~` next to the file itself
+    NextToFile,
+    /// `file~` inside the given directory
+    Directory(std::path::PathBuf),
+}
+
+impl Default for Settings {
+    fn default() -> Settings {
+        Settings {
+            gutter: GutterConfig::default(),
+            status_line: StatusLineConfig::default(),
+        }
+    }
 }
 
 #[derive(Debug, Clone, Eq, PartialEq)]
@@ -46,13 +231,170 @@ pub struct Cell {
 pub struct BackBuffer {
     pub dim: Rect,
     pub cells: Vec<Cell>,
+    /// What every cell starts out as, blank in the theme's text colours
+    pub blank: Cell,
 }
 
 #[derive(Debug, Default)]
 pub struct Buffer {
+    /// Stable number shown to the user, see `GlobalData::add_buffer`
+    pub number: usize,
     pub rope: Rope,
     pub source: std::path::PathBuf,
+    /// Bumped on every edit
+    pub version: u64,
+    /// `version` at the time the buffer was last loaded or written
+    pub saved_version: u64,
+    /// Modification time of `source` when it was last loaded or written
+    pub disk_modified: Option<std::time::SystemTime>,
+    /// `source` changed on disk while the buffer had unsaved changes
+    pub changed_on_disk: bool,
+    pub diagnostics: DiagnosticCounts,
+    pub marks: Marks,
+}
+
+impl Buffer {
+    /// Edited since it was last loaded or written
+    pub fn is_modified(&self) -> bool {
+        self.version != self.saved_version
+    }
+
+    pub fn display_name(&self) -> String {
+        if self.source.as_os_str().is_empty() {
+            "[No Name]".into()
+        } else {
+            self.source.display().to_string()
+        }
+    }
+}
+
+/// A view onto a buffer, each with its own scroll offset and cursor
+#[derive(Debug, Clone)]
+pub struct Window {
+    pub buffer: BufferIndex,
+    /// Buffer shown before the current one, for jumping back and forth
+    pub alternate: Option<BufferIndex>,
     pub start_line: usize,
+    pub start_column: usize,
+    /// The newest cursor, the one the view follows
+    pub cursor: Cursor,
+    /// Cursors added before it for editing in several places at once, oldest first
+    pub extra_cursors: Vec<Cursor>,
+    /// Where big motions left from, for Ctrl-o and Ctrl-i
+    pub jumps: JumpList,
+    /// Cursor and scroll offsets the window had in each buffer it showed
+    /// before, for picking up where it left off when it comes back
+    pub views: std::collections::HashMap<BufferIndex, (Cursor, usize, usize)>,
+}
+
+impl Window {
+    pub fn new(buffer: BufferIndex) -> Window {
+        Window {
+            buffer,
+            alternate: None,
+            start_line: 0,
+            start_column: 0,
+            cursor: Cursor::default(),
+            extra_cursors: Vec::new(),
+            jumps: JumpList::default(),
+            views: std::collections::HashMap::new(),
+        }
+    }
+
+    /// Points the window at `buffer`, whose text is `rope`, going back to the
+    /// cursor and scroll offsets it last had there, or the top the first time.
+    /// The place it leaves goes on the jump list.
+    pub fn show_buffer(&mut self, buffer: BufferIndex, rope: &Rope) {
+        if self.buffer != buffer {
+            self.jumps.push(self.buffer, self.cursor.position.clone());
+            self.switch_buffer(buffer, rope);
+        }
+    }
+
+    /// Same as `show_buffer` without adding to the jump list, for moving along it
+    pub fn switch_buffer(&mut self, buffer: BufferIndex, rope: &Rope) {
+        if self.buffer != buffer {
+            let mut cursor = self.cursor.clone();
+            cursor.selection_anchor = None;
+            self.views
+                .insert(self.buffer, (cursor, self.start_line, self.start_column));
+            let (cursor, start_line, start_column) = self.views.remove(&buffer).unwrap_or_default();
+            self.alternate = Some(self.buffer);
+            self.buffer = buffer;
+            self.cursor = cursor;
+            self.extra_cursors.clear();
+            self.start_line = start_line;
+            self.start_column = start_column;
+            // The buffer may have shrunk while the window was elsewhere
+            self.clamp_to(rope);
+        }
+    }
+
+    /// Pulls the cursor back inside `rope`, which may have shrunk since the
+    /// window last looked at it
+    pub fn clamp_to(&mut self, rope: &Rope) {
+        let last_line = rope.len_lines().saturating_sub(2);
+        for cursor in self.cursors_mut() {
+            let position = &mut cursor.position;
+            if position.y as usize > last_line {
+                position.y = last_line as u16;
+            }
+            let line_length = std::cmp::max(1, rope.line(position.y as usize).len_chars() as u16);
+            if position.x > line_length {
+                position.x = line_length;
+            }
+            if cursor
+                .selection_anchor
+                .as_ref()
+                .map(|anchor| anchor.y as usize > last_line)
+                .unwrap_or(false)
+            {
+                cursor.selection_anchor = None;
+            }
+        }
+        if self.start_line > self.cursor.position.y as usize {
+            self.start_line = self.cursor.position.y as usize;
+        }
+        self.dedup_cursors();
+    }
+
+    /// Every cursor, the newest first
+    pub fn cursors_mut(&mut self) -> impl Iterator<Item = &mut Cursor> {
+        std::iter::once(&mut self.cursor).chain(self.extra_cursors.iter_mut().rev())
+    }
+
+    /// Drops cursors that ended up where a newer one is, after edits ran them together
+    pub fn dedup_cursors(&mut self) {
+        let mut kept: Vec<Cursor> = Vec::new();
+        for cursor in self.extra_cursors.drain(..).rev() {
+            let taken = std::iter::once(&self.cursor)
+                .chain(kept.iter())
+                .any(|other| other.position == cursor.position);
+            if !taken {
+                kept.push(cursor);
+            }
+        }
+        kept.reverse();
+        self.extra_cursors = kept;
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Cursor {
+    pub position: Point,
+    pub stored_x: u16,
+    pub selection_anchor: Option<Point>,
+}
+
+impl Default for Cursor {
+    fn default() -> Cursor {
+        let position = Point { x: 1, y: 0 };
+        Cursor {
+            selection_anchor: None,
+            position,
+            stored_x: 0,
+        }
+    }
 }
 
 #[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
@@ -60,6 +402,25 @@ pub enum Mode {
     Normal,
     Insert,
     Command,
+    /// Keys go to the picker overlay
+    Picker,
+    /// Keys answer the question shown in the message line
+    Confirm,
+    /// Motions extend a selection from the anchor to the cursor
+    Visual,
+    /// Like `Visual` but whole lines are selected
+    VisualLine,
+    /// Like `Visual` but the selection is the rectangle between anchor and cursor
+    VisualBlock,
+}
+
+impl Mode {
+    pub fn is_visual(&self) -> bool {
+        match self {
+            Mode::Visual | Mode::VisualLine | Mode::VisualBlock => true,
+            _ => false,
+        }
+    }
 }
 
 #[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
@@ -70,13 +431,13 @@ pub enum Direction {
     Down,
 }
 
-#[derive(Debug, Deserialize, Serialize, PartialEq)]
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
 pub enum DeleteDirection {
     Before,
     After,
 }
 
-#[derive(Debug, Deserialize, Serialize, PartialEq)]
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
 pub enum JumpType {
     EndOfLine,
     StartOfLine,
@@ -85,6 +446,8 @@ pub enum JumpType {
     StartOfWord,
     EndOfWord,
     MatchingBrace,
+    /// Line and column in the buffer, scrolled into view if it's off screen
+    Position(Point),
 }
 
 use serde::{Deserialize, Serialize};
@@ -96,13 +459,128 @@ pub struct RemoteCommand(pub ClientIndex, pub Cmd);
 #[derive(Debug, Deserialize, Serialize)]
 pub struct InitializeClient(pub ClientIndex);
 
+/// A tab page, holding its own set of windows and how they are laid out
+#[derive(Debug)]
+pub struct Tab {
+    pub name: Option<String>,
+    pub window_keys: SlotMap<WindowIndex, ()>,
+    pub windows: SecondaryMap<WindowIndex, Window>,
+    pub layout: Layout,
+    pub active_window: WindowIndex,
+}
+
+impl Tab {
+    pub fn new(buffer: BufferIndex) -> Tab {
+        let mut window_keys = SlotMap::new();
+        let active_window = window_keys.insert(());
+        let mut windows = SecondaryMap::new();
+        windows.insert(active_window, Window::new(buffer));
+        Tab {
+            name: None,
+            window_keys,
+            windows,
+            layout: Layout::Window(active_window),
+            active_window,
+        }
+    }
+
+    pub fn window(&self) -> &Window {
+        &self.windows[self.active_window]
+    }
+
+    pub fn window_mut(&mut self) -> &mut Window {
+        &mut self.windows[self.active_window]
+    }
+}
+
 #[derive(Debug)]
 pub struct Client {
     pub stream: UnixStream,
-    pub buffer: DefaultKey,
     pub mode: Mode,
     pub back_buffer: BackBuffer,
     pub size: Option<Rect>, // We don't know right away
+    pub tabs: Vec<Tab>,
+    pub active_tab: usize,
+}
+
+impl Client {
+    pub fn new(stream: UnixStream, buffer: BufferIndex) -> Client {
+        Client {
+            stream,
+            mode: Mode::Normal,
+            back_buffer: BackBuffer::default(),
+            size: None,
+            tabs: vec![Tab::new(buffer)],
+            active_tab: 0,
+        }
+    }
+
+    pub fn tab(&self) -> &Tab {
+        &self.tabs[self.active_tab]
+    }
+
+    pub fn tab_mut(&mut self) -> &mut Tab {
+        &mut self.tabs[self.active_tab]
+    }
+
+    pub fn window(&self) -> &Window {
+        self.tab().window()
+    }
+
+    pub fn window_mut(&mut self) -> &mut Window {
+        self.tab_mut().window_mut()
+    }
+
+    /// Buffer shown in the active window
+    pub fn buffer(&self) -> BufferIndex {
+        self.window().buffer
+    }
+
+    /// The tab bar takes up the top row, but only once there is more than one tab
+    pub fn shows_tab_bar(&self) -> bool {
+        self.tabs.len() > 1
+    }
+
+    /// Top left corner of the space shared by the windows
+    pub fn window_origin(&self) -> Point {
+        Point {
+            x: 0,
+            y: if self.shows_tab_bar() { 1 } else { 0 },
+        }
+    }
+
+    /// Row holding the status line, right above the command line
+    pub fn status_row(&self) -> Option<u16> {
+        self.size.as_ref().map(|size| size.h.saturating_sub(2))
+    }
+
+    /// Bottom row, used for the command line and messages
+    pub fn command_row(&self) -> Option<u16> {
+        self.size.as_ref().map(|size| size.h.saturating_sub(1))
+    }
+
+    /// Space shared by the windows, the bottom two rows are left for the status and command line
+    pub fn window_area(&self) -> Option<Rect> {
+        let origin_y = self.window_origin().y;
+        self.size.as_ref().map(|size| Rect {
+            w: size.w,
+            h: size.h.saturating_sub(2 + origin_y),
+        })
+    }
+
+    pub fn window_regions(&self) -> Vec<WindowRegion> {
+        match self.window_area() {
+            Some(area) => self.tab().layout.regions(self.window_origin(), area),
+            None => Vec::new(),
+        }
+    }
+
+    pub fn active_region(&self) -> Option<WindowRegion> {
+        let active_window = self.tab().active_window;
+        self.window_regions()
+            .into_iter()
+            .find(|region| region.window == active_window)
+    }
 }
 
 #[derive(Debug)]
@@ -111,6 +589,8 @@ pub enum Msg {
     StdinEvent(ClientIndex, Event),
     Cmd(ClientIndex, Cmd),
     NewClient(UnixStream),
+    /// A file in a directory holding an open buffer was written, created or replaced
+    FileChanged(std::path::PathBuf),
 }
 
 #[derive(Debug, Clone, Eq, PartialEq, Default, PartialOrd, Serialize, Deserialize)]
@@ -130,9 +610,112 @@ pub struct Utils {
         fn(&mut BackBuffer, &Point, &str, Option<Style>, Option<Color>, Option<Color>),
     pub style_range:
         fn(&mut BackBuffer, &Point, usize, Option<Style>, Option<Color>, Option<Color>),
-    pub style_rope_slice_range:
-        fn(&mut BackBuffer, &RopeSlice, Point, Option<Style>, Option<Color>, Option<Color>),
+    /// Resets cells to blank so overlays don't pick up what was drawn underneath
+    pub clear_range: fn(&mut BackBuffer, &Point, usize),
+    pub style_rope_slice_range: fn(
+        &mut BackBuffer,
+        &RopeSlice,
+        Point,
+        &GutterLayout,
+        Option<Style>,
+        Option<Color>,
+        Option<Color>,
+    ),
+    pub gutter_layout: fn(&GlobalData, BufferIndex) -> GutterLayout,
     pub info: fn(&str),
     pub debug: fn(&str),
     pub warn: fn(&str),
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_buffer_numbers_are_not_reused() {
+        let mut global_data = GlobalData::default();
+        let first = global_data.add_buffer(Buffer::default());
+        let second = global_data.add_buffer(Buffer::default());
+        assert_eq!(global_data.buffers[first].number, 1);
+        assert_eq!(global_data.buffers[second].number, 2);
+        global_data.remove_buffer(second);
+        let third = global_data.add_buffer(Buffer::default());
+        assert_eq!(global_data.buffers[third].number, 3);
+        assert_eq!(global_data.buffers_by_number(), vec![first, third]);
+        // Removing the last one leaves an empty buffer to show
+        global_data.remove_buffer(first);
+        global_data.remove_buffer(third);
+        assert_eq!(global_data.buffers.len(), 1);
+        assert_eq!(global_data.buffers.values().next().unwrap().number, 4);
+    }
+
+    #[test]
+    fn test_window_comes_back_to_its_place() {
+        let first = BufferIndex::from(KeyData::from_ffi(1));
+        let second = BufferIndex::from(KeyData::from_ffi(2));
+        let long = Rope::from_str(&"line\n".repeat(50));
+        let short = Rope::from_str("one\ntwo\n");
+        let mut window = Window::new(first);
+        window.cursor.position = Point { x: 3, y: 40 };
+        window.start_line = 30;
+        window.show_buffer(second, &short);
+        assert_eq!(window.cursor.position, Point { x: 1, y: 0 });
+        assert_eq!(window.start_line, 0);
+        assert_eq!(window.alternate, Some(first));
+        window.cursor.position = Point { x: 2, y: 1 };
+        window.show_buffer(first, &long);
+        assert_eq!(window.cursor.position, Point { x: 3, y: 40 });
+        assert_eq!(window.start_line, 30);
+        // Coming back to a buffer that shrunk keeps the cursor inside it
+        window.show_buffer(second, &Rope::from_str("one\n"));
+        assert_eq!(window.cursor.position, Point { x: 2, y: 0 });
+    }
+
+    #[test]
+    fn test_reattach_gets_tabs_back() {
+        let mut global_data = GlobalData::default();
+        let first = global_data.add_buffer(Buffer::default());
+        let second = global_data.add_buffer(Buffer::default());
+        let (stream, _other_end) = UnixStream::pair().unwrap();
+        let client = global_data.attach_client(stream);
+        global_data.clients[client].tabs.push(Tab::new(second));
+        global_data.clients[client].tabs[1].name = Some("notes".into());
+        global_data.clients[client].active_tab = 1;
+        global_data.clients[client].window_mut().cursor.position.y = 3;
+        assert!(global_data.detach_client(client).is_some());
+        assert!(global_data.clients.is_empty());
+
+        let (stream, _other_end) = UnixStream::pair().unwrap();
+        let client = global_data.attach_client(stream);
+        let restored = &global_data.clients[client];
+        assert_eq!(restored.tabs.len(), 2);
+        assert_eq!(restored.active_tab, 1);
+        assert_eq!(restored.tab().name, Some("notes".into()));
+        assert_eq!(restored.buffer(), second);
+        assert_eq!(restored.window().cursor.position.y, 3);
+        assert!(global_data.detached_tabs.is_none());
+
+        // Only the tabs of the last client to quit are kept
+        let (stream, _other_end) = UnixStream::pair().unwrap();
+        let other = global_data.attach_client(stream);
+        global_data.detach_client(other);
+        global_data.detach_client(client);
+        assert_eq!(global_data.detached_tabs.as_ref().unwrap().0.len(), 2);
+
+        // A window on a buffer deleted in the meantime falls back to the first one
+        global_data.remove_buffer(second);
+        let (stream, _other_end) = UnixStream::pair().unwrap();
+        let client = global_data.attach_client(stream);
+        let restored = &global_data.clients[client];
+        assert_eq!(restored.buffer(), first);
+        assert_eq!(restored.window().alternate, None);
+        assert_eq!(restored.tabs[0].window().buffer, first);
+
+        // With nothing left behind a client starts out with a single tab
+        assert!(global_data.detached_tabs.is_none());
+        let (stream, _other_end) = UnixStream::pair().unwrap();
+        let fresh = global_data.attach_client(stream);
+        assert_eq!(global_data.clients[fresh].tabs.len(), 1);
+        assert_eq!(global_data.clients[fresh].buffer(), first);
+    }
+}
@@ -0,0 +1,209 @@
+use crate::{BufferIndex, Point};
+use ropey::Rope;
+use std::collections::{BTreeSet, HashMap};
+
+// Oldest jumps are dropped once the list has this many
+const MAX_JUMPS: usize = 100;
+
+/// Positions set with `m`, by name. Lowercase marks belong to their buffer,
+/// uppercase ones are unique across buffers and kept by the buffer they are in.
+/// `<` and `>` hold the ends of the last visual selection.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct Marks {
+    positions: HashMap<char, Point>,
+}
+
+/// Whether `m` can set `name`, a to z for the buffer or A to Z across buffers
+pub fn is_mark_name(name: char) -> bool {
+    name.is_ascii_alphabetic()
+}
+
+// Start of the line `point` is on, or of the last line when it is past the end
+fn line_start(rope: &Rope, point: &Point) -> usize {
+    let line = std::cmp::min(point.y as usize, rope.len_lines().saturating_sub(1));
+    rope.line_to_char(line)
+}
+
+impl Marks {
+    pub fn get(&self, name: char) -> Option<&Point> {
+        self.positions.get(&name)
+    }
+
+    pub fn set(&mut self, name: char, point: Point) {
+        self.positions.insert(name, point);
+    }
+
+    pub fn remove(&mut self, name: char) -> Option<Point> {
+        self.positions.remove(&name)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&char, &Point)> {
+        self.positions.iter()
+    }
+
+    /// Keeps marks on their text while `text` goes in at each of `indices` of
+    /// `rope`, which is the rope before the edit. Marks only move between lines.
+    pub fn follow_insert(&mut self, rope: &Rope, indices: &[usize], text: &str) {
+        let breaks = text.matches('\n').count();
+        if breaks == 0 {
+            return;
+        }
+        let indices: BTreeSet<usize> = indices.iter().cloned().collect();
+        for point in self.positions.values_mut() {
+            let before = indices.range(..=line_start(rope, point)).count();
+            point.y += (before * breaks) as u16;
+        }
+    }
+
+    /// Same as `follow_insert` for removing each char range of `rope`, ranges
+    /// may overlap. Marks on lines that go away end up on the line they joined.
+    pub fn follow_remove(&mut self, rope: &Rope, ranges: &[std::ops::Range<usize>]) {
+        let breaks: BTreeSet<usize> = ranges
+            .iter()
+            .flat_map(|range| range.clone())
+            .filter(|index| *index < rope.len_chars() && rope.char(*index) == '\n')
+            .collect();
+        if breaks.is_empty() {
+            return;
+        }
+        for point in self.positions.values_mut() {
+            let before = breaks.range(..line_start(rope, point)).count();
+            point.y -= before as u16;
+        }
+    }
+
+    /// Keeps marks in place while lines `first` to `last`, both included, are
+    /// replaced by `lines` new ones. Marks past the new lines end up on the last of them.
+    pub fn follow_replaced_lines(&mut self, first: usize, last: usize, lines: usize) {
+        let removed = last + 1 - first;
+        for point in self.positions.values_mut() {
+            let line = point.y as usize;
+            if line > last {
+                point.y = (line + lines - removed) as u16;
+            } else if line >= first + lines {
+                point.y = (first + lines).saturating_sub(1).max(first) as u16;
+            }
+        }
+    }
+}
+
+/// Places left by big motions such as searches, `G` or switching buffers, for
+/// going back with Ctrl-o and forward again with Ctrl-i
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct JumpList {
+    entries: Vec<(BufferIndex, Point)>,
+    /// How far Ctrl-o has gone back, `entries.len()` when it hasn't
+    index: usize,
+}
+
+impl JumpList {
+    /// Remembers jumping away from `point`, which replaces an older jump from the same line
+    pub fn push(&mut self, buffer: BufferIndex, point: Point) {
+        self.entries
+            .retain(|(other_buffer, other)| *other_buffer != buffer || other.y != point.y);
+        self.entries.push((buffer, point));
+        if self.entries.len() > MAX_JUMPS {
+            self.entries.remove(0);
+        }
+        self.index = self.entries.len();
+    }
+
+    /// The place before the current one. Going back from the newest place first
+    /// remembers it, so that Ctrl-i can come back to it.
+    pub fn back(&mut self, buffer: BufferIndex, point: Point) -> Option<(BufferIndex, Point)> {
+        if self.index == 0 {
+            return None;
+        }
+        if self.index >= self.entries.len() {
+            self.push(buffer, point);
+            self.index = self.entries.len() - 1;
+            if self.index == 0 {
+                return None;
+            }
+        }
+        self.index -= 1;
+        self.entries.get(self.index).cloned()
+    }
+
+    /// The place after the current one, when Ctrl-o went back past it
+    pub fn forward(&mut self) -> Option<(BufferIndex, Point)> {
+        if self.index + 1 >= self.entries.len() {
+            return None;
+        }
+        self.index += 1;
+        self.entries.get(self.index).cloned()
+    }
+
+    /// Drops the jumps into a buffer that is gone
+    pub fn forget_buffer(&mut self, buffer: BufferIndex) {
+        let removed_before = self.entries[..std::cmp::min(self.index, self.entries.len())]
+            .iter()
+            .filter(|(other, _)| *other == buffer)
+            .count();
+        self.entries.retain(|(other, _)| *other != buffer);
+        self.index = std::cmp::min(self.index - removed_before, self.entries.len());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn point(x: u16, y: u16) -> Point {
+        Point { x, y }
+    }
+
+    #[test]
+    fn test_marks_follow_edits() {
+        let rope = Rope::from_str("one\ntwo\nthree\nfour\n");
+        let mut marks = Marks::default();
+        marks.set('a', point(2, 1));
+        marks.set('b', point(3, 3));
+        // Two lines in at the start of "two", the mark on it goes down with it
+        marks.follow_insert(&rope, &[4], "x\ny\n");
+        assert_eq!(marks.get('a'), Some(&point(2, 3)));
+        assert_eq!(marks.get('b'), Some(&point(3, 5)));
+        // Joining "two" and "three" onto "one"
+        let mut marks = Marks::default();
+        marks.set('a', point(2, 2));
+        marks.set('b', point(3, 3));
+        marks.set('c', point(1, 0));
+        marks.follow_remove(&rope, &[3..4, 7..8]);
+        assert_eq!(marks.get('a'), Some(&point(2, 0)));
+        assert_eq!(marks.get('b'), Some(&point(3, 1)));
+        assert_eq!(marks.get('c'), Some(&point(1, 0)));
+        // Three lines from the second on shrunk down to one
+        marks.set('a', point(1, 2));
+        marks.set('b', point(1, 4));
+        marks.follow_replaced_lines(1, 3, 1);
+        assert_eq!(marks.get('a'), Some(&point(1, 1)));
+        assert_eq!(marks.get('b'), Some(&point(1, 2)));
+        assert_eq!(marks.get('c'), Some(&point(1, 0)));
+    }
+
+    #[test]
+    fn test_jump_list() {
+        let buffer = BufferIndex::default();
+        let mut jumps = JumpList::default();
+        assert_eq!(jumps.back(buffer, point(1, 0)), None);
+        jumps.push(buffer, point(1, 0));
+        jumps.push(buffer, point(1, 10));
+        assert_eq!(
+            jumps.back(buffer, point(4, 20)),
+            Some((buffer, point(1, 10)))
+        );
+        assert_eq!(
+            jumps.back(buffer, point(1, 10)),
+            Some((buffer, point(1, 0)))
+        );
+        assert_eq!(jumps.back(buffer, point(1, 0)), None);
+        assert_eq!(jumps.forward(), Some((buffer, point(1, 10))));
+        assert_eq!(jumps.forward(), Some((buffer, point(4, 20))));
+        assert_eq!(jumps.forward(), None);
+        // A jump from an old line moves that line to the newest place
+        jumps.push(buffer, point(2, 0));
+        assert_eq!(jumps.back(buffer, point(1, 5)), Some((buffer, point(2, 0))));
+        jumps.forget_buffer(buffer);
+        assert_eq!(jumps.back(buffer, point(1, 5)), None);
+    }
+}
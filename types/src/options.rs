@@ -0,0 +1,333 @@
+use crate::BufferIndex;
+use serde::{Deserialize, Serialize};
+use slotmap::SecondaryMap;
+use std::collections::HashMap;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum OptionValue {
+    Bool(bool),
+    Int(i64),
+    String(String),
+    /// One of the choices the option was declared with
+    Enum(String),
+}
+
+impl std::fmt::Display for OptionValue {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            OptionValue::Bool(value) => write!(f, "{}", value),
+            OptionValue::Int(value) => write!(f, "{}", value),
+            OptionValue::String(value) | OptionValue::Enum(value) => write!(f, "{}", value),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum OptionScope {
+    Global,
+    /// Each buffer can have its own value, the global one is used until it does
+    Buffer,
+}
+
+/// An option a plugin declared, see `Options::declare`
+#[derive(Debug, Clone, PartialEq)]
+pub struct OptionSpec {
+    pub name: String,
+    /// Short name such as `nu` for `number`, as in vim
+    pub abbreviation: Option<String>,
+    pub scope: OptionScope,
+    pub default: OptionValue,
+    /// What an enum option can be set to
+    pub choices: Vec<String>,
+}
+
+impl OptionSpec {
+    pub fn new(name: &str, scope: OptionScope, default: OptionValue) -> Self {
+        OptionSpec {
+            name: name.into(),
+            abbreviation: None,
+            scope,
+            default,
+            choices: Vec::new(),
+        }
+    }
+
+    /// Fails unless `value` is of this option's type, and one of its choices for an enum
+    pub fn check(&self, value: &OptionValue) -> Result<(), String> {
+        match (&self.default, value) {
+            (OptionValue::Bool(_), OptionValue::Bool(_))
+            | (OptionValue::Int(_), OptionValue::Int(_))
+            | (OptionValue::String(_), OptionValue::String(_)) => Ok(()),
+            (OptionValue::Enum(_), OptionValue::Enum(text)) => self.parse(text).map(|_| ()),
+            _ => Err(format!("Invalid value for {}: {}", self.name, value)),
+        }
+    }
+
+    /// Reads `text` as a value of this option's type
+    pub fn parse(&self, text: &str) -> Result<OptionValue, String> {
+        match &self.default {
+            OptionValue::Bool(_) => match text {
+                "true" | "on" | "yes" | "1" => Ok(OptionValue::Bool(true)),
+                "false" | "off" | "no" | "0" => Ok(OptionValue::Bool(false)),
+                _ => Err(format!("Invalid value for {}: {}", self.name, text)),
+            },
+            OptionValue::Int(_) => text
+                .parse()
+                .map(OptionValue::Int)
+                .map_err(|_| format!("Number required after =: {}={}", self.name, text)),
+            OptionValue::String(_) => Ok(OptionValue::String(text.into())),
+            OptionValue::Enum(_) => {
+                if self.choices.iter().any(|choice| choice == text) {
+                    Ok(OptionValue::Enum(text.into()))
+                } else {
+                    Err(format!(
+                        "Invalid value for {}: {} (one of {})",
+                        self.name,
+                        text,
+                        self.choices.join(", ")
+                    ))
+                }
+            }
+        }
+    }
+}
+
+/// Every option and its values. Plugins declare the ones they read when they're
+/// loaded, `:set` and the config file change them.
+#[derive(Debug, Default)]
+pub struct Options {
+    specs: Vec<OptionSpec>,
+    global: HashMap<String, OptionValue>,
+    buffers: SecondaryMap<BufferIndex, HashMap<String, OptionValue>>,
+    /// Set before any plugin declared them, applied once one does
+    pending: HashMap<String, String>,
+    /// Pending values that turned out to be invalid once their option was declared
+    errors: Vec<String>,
+}
+
+impl Options {
+    /// Adds an option. Declaring it again, as a reloaded plugin does, keeps the
+    /// value it had.
+    pub fn declare(&mut self, spec: OptionSpec) {
+        let name = spec.name.clone();
+        let value = match self.pending.remove(&name) {
+            Some(text) => spec.parse(&text).unwrap_or_else(|err| {
+                self.errors.push(err);
+                spec.default.clone()
+            }),
+            None => self
+                .global
+                .get(&name)
+                .filter(|value| spec.check(value).is_ok())
+                .cloned()
+                .unwrap_or_else(|| spec.default.clone()),
+        };
+        self.global.insert(name.clone(), value);
+        self.specs.retain(|existing| existing.name != name);
+        self.specs.push(spec);
+    }
+
+    /// Problems with values set before their options were declared, since the
+    /// last call. The options were left at their defaults.
+    pub fn take_errors(&mut self) -> Vec<String> {
+        std::mem::replace(&mut self.errors, Vec::new())
+    }
+
+    /// The option called `name`, by its full name or its abbreviation
+    pub fn find(&self, name: &str) -> Option<&OptionSpec> {
+        self.specs.iter().find(|spec| {
+            spec.name == name || spec.abbreviation.as_ref().map(String::as_str) == Some(name)
+        })
+    }
+
+    /// Declared options sorted by name
+    pub fn specs(&self) -> Vec<&OptionSpec> {
+        let mut specs: Vec<&OptionSpec> = self.specs.iter().collect();
+        specs.sort_by(|a, b| a.name.cmp(&b.name));
+        specs
+    }
+
+    /// The value for `buffer`, or the global one if the buffer has none of its own
+    pub fn get(&self, name: &str, buffer: Option<BufferIndex>) -> Option<&OptionValue> {
+        buffer
+            .and_then(|buffer| self.buffers.get(buffer))
+            .and_then(|values| values.get(name))
+            .or_else(|| self.global.get(name))
+    }
+
+    pub fn get_bool(&self, name: &str, buffer: Option<BufferIndex>) -> Option<bool> {
+        match self.get(name, buffer) {
+            Some(OptionValue::Bool(value)) => Some(*value),
+            _ => None,
+        }
+    }
+
+    pub fn get_int(&self, name: &str, buffer: Option<BufferIndex>) -> Option<i64> {
+        match self.get(name, buffer) {
+            Some(OptionValue::Int(value)) => Some(*value),
+            _ => None,
+        }
+    }
+
+    /// Value of a string or enum option
+    pub fn get_str(&self, name: &str, buffer: Option<BufferIndex>) -> Option<&str> {
+        match self.get(name, buffer) {
+            Some(OptionValue::String(value)) | Some(OptionValue::Enum(value)) => Some(value),
+            _ => None,
+        }
+    }
+
+    /// Sets an option by its full name. Buffer options are set for `buffer`
+    /// when one is given, otherwise for every buffer without a value of its own.
+    pub fn set(
+        &mut self,
+        name: &str,
+        value: OptionValue,
+        buffer: Option<BufferIndex>,
+    ) -> Result<(), String> {
+        let spec = self
+            .find(name)
+            .ok_or_else(|| format!("Unknown option: {}", name))?;
+        spec.check(&value)?;
+        let name = spec.name.clone();
+        match (&spec.scope, buffer) {
+            (OptionScope::Buffer, Some(buffer)) => {
+                if !self.buffers.contains_key(buffer) {
+                    self.buffers.insert(buffer, HashMap::new());
+                }
+                self.buffers[buffer].insert(name, value);
+            }
+            _ => {
+                self.global.insert(name, value);
+            }
+        }
+        Ok(())
+    }
+
+    /// Sets the global value from text, as the config file does. Options no plugin
+    /// has declared yet get the value when one does.
+    pub fn set_default(&mut self, name: &str, text: &str) -> Result<(), String> {
+        match self.find(name) {
+            Some(spec) => {
+                let value = spec.parse(text)?;
+                let name = spec.name.clone();
+                self.set(&name, value, None)
+            }
+            None => {
+                self.pending.insert(name.into(), text.into());
+                Ok(())
+            }
+        }
+    }
+
+    /// Drops the values set for `buffer`, which is gone
+    pub fn forget_buffer(&mut self, buffer: BufferIndex) {
+        self.buffers.remove(buffer);
+    }
+
+    /// Undoes `set_default`, putting the global value back to the declared default.
+    /// Gives back the full name when the option is declared.
+    pub fn reset_default(&mut self, name: &str) -> Option<String> {
+        self.pending.remove(name);
+        let (name, default) = self
+            .find(name)
+            .map(|spec| (spec.name.clone(), spec.default.clone()))?;
+        self.global.insert(name.clone(), default);
+        Some(name)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use slotmap::SlotMap;
+
+    fn options() -> Options {
+        let mut options = Options::default();
+        options.declare(OptionSpec {
+            abbreviation: Some("nu".into()),
+            ..OptionSpec::new("number", OptionScope::Buffer, OptionValue::Bool(true))
+        });
+        options.declare(OptionSpec::new(
+            "sidescrolloff",
+            OptionScope::Global,
+            OptionValue::Int(5),
+        ));
+        options.declare(OptionSpec {
+            choices: vec!["off".into(), "nexttofile".into()],
+            ..OptionSpec::new(
+                "backup",
+                OptionScope::Global,
+                OptionValue::Enum("off".into()),
+            )
+        });
+        options
+    }
+
+    #[test]
+    fn test_buffer_values_fall_back_to_global() {
+        let mut options = options();
+        let mut buffers: SlotMap<BufferIndex, ()> = SlotMap::new();
+        let (first, second) = (buffers.insert(()), buffers.insert(()));
+        options
+            .set("nu", OptionValue::Bool(false), Some(first))
+            .unwrap();
+        assert_eq!(options.get_bool("number", Some(first)), Some(false));
+        assert_eq!(options.get_bool("number", Some(second)), Some(true));
+        options
+            .set("number", OptionValue::Bool(false), None)
+            .unwrap();
+        assert_eq!(options.get_bool("number", Some(second)), Some(false));
+    }
+
+    #[test]
+    fn test_values_are_checked() {
+        let mut options = options();
+        assert!(options
+            .set("sidescrolloff", OptionValue::Bool(true), None)
+            .is_err());
+        assert!(options.set_default("backup", "sometimes").is_err());
+        options.set_default("backup", "nexttofile").unwrap();
+        assert_eq!(options.get_str("backup", None), Some("nexttofile"));
+        assert!(options.set("wrap", OptionValue::Bool(true), None).is_err());
+    }
+
+    #[test]
+    fn test_values_set_before_declaring() {
+        let mut options = options();
+        options.set_default("tabstop", "8").unwrap();
+        assert_eq!(options.get_int("tabstop", None), None);
+        options.declare(OptionSpec::new(
+            "tabstop",
+            OptionScope::Buffer,
+            OptionValue::Int(4),
+        ));
+        assert_eq!(options.get_int("tabstop", None), Some(8));
+        assert!(options.take_errors().is_empty());
+        // A bad value is reported once the option shows up
+        options.set_default("shiftwidth", "wide").unwrap();
+        options.declare(OptionSpec::new(
+            "shiftwidth",
+            OptionScope::Buffer,
+            OptionValue::Int(4),
+        ));
+        assert_eq!(options.get_int("shiftwidth", None), Some(4));
+        assert_eq!(
+            options.take_errors(),
+            vec!["Number required after =: shiftwidth=wide".to_string()]
+        );
+        assert!(options.take_errors().is_empty());
+    }
+
+    #[test]
+    fn test_forget_buffer() {
+        let mut options = options();
+        let mut buffers: SlotMap<BufferIndex, ()> = SlotMap::new();
+        let buffer = buffers.insert(());
+        options
+            .set("number", OptionValue::Bool(false), Some(buffer))
+            .unwrap();
+        options.forget_buffer(buffer);
+        assert_eq!(options.get_bool("number", Some(buffer)), Some(true));
+    }
+}
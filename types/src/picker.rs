@@ -0,0 +1,62 @@
+use crate::Cmd;
+use serde::{Deserialize, Serialize};
+
+/// Lists the picker can show. Each one is filled in by the plugin that owns
+/// the data, which answers `Cmd::Pick` with `Cmd::OpenPicker`.
+#[derive(Debug, Clone, Deserialize, Serialize, PartialEq)]
+pub enum PickerSource {
+    Files,
+    Buffers,
+    Commands,
+    History,
+    Registers,
+    Diagnostics,
+    Symbols,
+}
+
+impl PickerSource {
+    pub const ALL: [PickerSource; 7] = [
+        PickerSource::Files,
+        PickerSource::Buffers,
+        PickerSource::Commands,
+        PickerSource::History,
+        PickerSource::Registers,
+        PickerSource::Diagnostics,
+        PickerSource::Symbols,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            PickerSource::Files => "files",
+            PickerSource::Buffers => "buffers",
+            PickerSource::Commands => "commands",
+            PickerSource::History => "history",
+            PickerSource::Registers => "registers",
+            PickerSource::Diagnostics => "diagnostics",
+            PickerSource::Symbols => "symbols",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<PickerSource> {
+        PickerSource::ALL
+            .iter()
+            .find(|source| source.name() == name)
+            .cloned()
+    }
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize, PartialEq)]
+pub struct PickerItem {
+    /// Text that is shown and matched against the query
+    pub label: String,
+    /// Sent for the client once the item is picked
+    pub action: Cmd,
+    /// File shown beside the list while the item is selected
+    pub preview: Option<std::path::PathBuf>,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize, PartialEq)]
+pub struct Picker {
+    pub title: String,
+    pub items: Vec<PickerItem>,
+}
@@ -0,0 +1,61 @@
+use serde::{Deserialize, Serialize};
+
+// Oldest searches are dropped once the history has this many
+const MAX_HISTORY: usize = 100;
+
+/// The last search, shared so that other plugins can reuse or highlight it
+#[derive(Debug, Default, Clone)]
+pub struct SearchState {
+    /// Regex of the last completed search
+    pub pattern: Option<String>,
+    /// Whether the last search went towards the start of the buffer
+    pub backward: bool,
+    /// What has been typed so far while a search is being entered
+    pub preview: Option<String>,
+    /// Matches stay highlighted until `:nohlsearch`
+    pub highlight: bool,
+    /// Completed searches, oldest first
+    pub history: Vec<String>,
+}
+
+impl SearchState {
+    /// Pattern to highlight right now, the one being typed wins
+    pub fn active_pattern(&self) -> Option<&str> {
+        match (&self.preview, self.highlight) {
+            (Some(preview), _) => Some(preview),
+            (None, true) => self.pattern.as_ref().map(String::as_str),
+            (None, false) => None,
+        }
+    }
+
+    /// Makes `pattern` the one `n` and `N` repeat
+    pub fn set_pattern(&mut self, pattern: &str, backward: bool) {
+        self.history.retain(|old| old != pattern);
+        self.history.push(pattern.to_string());
+        if self.history.len() > MAX_HISTORY {
+            self.history.remove(0);
+        }
+        self.pattern = Some(pattern.to_string());
+        self.backward = backward;
+        self.preview = None;
+        self.highlight = true;
+    }
+}
+
+/// A `:s` command ready to run over a range of lines
+#[derive(Debug, Clone, Deserialize, Serialize, PartialEq)]
+pub struct Substitution {
+    /// Regex to replace, the last search pattern when empty
+    pub pattern: String,
+    /// Replacement in vim's syntax, `&` and `\1` refer to the match
+    pub replacement: String,
+    /// First and last line to work on, 0 based and inclusive
+    pub first_line: usize,
+    pub last_line: usize,
+    /// Replace every match on a line instead of only the first
+    pub global: bool,
+    /// Ask before replacing each match
+    pub confirm: bool,
+    /// Forced by the `i` and `I` flags, smart case otherwise
+    pub ignore_case: Option<bool>,
+}
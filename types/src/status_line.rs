@@ -0,0 +1,81 @@
+use serde::{Deserialize, Serialize};
+
+/// One piece of information shown in the status line
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub enum StatusSegment {
+    Mode,
+    FilePath,
+    Modified,
+    /// Cursor line and column, both 1 based
+    Position,
+    /// How far through the file the cursor line is
+    Percentage,
+    FileType,
+    LineEnding,
+    /// Error and warning counts reported by language plugins
+    Diagnostics,
+    Text(String),
+}
+
+impl StatusSegment {
+    /// Reads a segment as the config file names it, `mode` or `position` say,
+    /// with `text:` in front of text to show as it is
+    pub fn parse(name: &str) -> Result<StatusSegment, String> {
+        use StatusSegment::*;
+        if name.starts_with("text:") {
+            return Ok(Text(name["text:".len()..].into()));
+        }
+        Ok(match name {
+            "mode" => Mode,
+            "file_path" => FilePath,
+            "modified" => Modified,
+            "position" => Position,
+            "percentage" => Percentage,
+            "file_type" => FileType,
+            "line_ending" => LineEnding,
+            "diagnostics" => Diagnostics,
+            _ => return Err(format!("Unknown status line segment: {}", name)),
+        })
+    }
+}
+
+/// Segments drawn from the left edge and from the right edge of the status line
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct StatusLineConfig {
+    pub left: Vec<StatusSegment>,
+    pub right: Vec<StatusSegment>,
+}
+
+impl Default for StatusLineConfig {
+    fn default() -> StatusLineConfig {
+        use StatusSegment::*;
+        StatusLineConfig {
+            left: vec![Mode, FilePath, Modified],
+            right: vec![Diagnostics, FileType, LineEnding, Position, Percentage],
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct DiagnosticCounts {
+    pub errors: usize,
+    pub warnings: usize,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_segment() {
+        assert_eq!(
+            StatusSegment::parse("file_path"),
+            Ok(StatusSegment::FilePath)
+        );
+        assert_eq!(
+            StatusSegment::parse("text: | "),
+            Ok(StatusSegment::Text(" | ".into()))
+        );
+        assert!(StatusSegment::parse("path").is_err());
+    }
+}
@@ -0,0 +1,437 @@
+use ropey::Rope;
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, Deserialize, Serialize, PartialEq)]
+pub enum TextObjectKind {
+    Word,
+    Sentence,
+    Paragraph,
+    /// Between two of the quote on the cursor's line
+    Quote(char),
+    /// Between the opening and closing bracket around the cursor
+    Bracket(char, char),
+    /// Between an XML or HTML tag and the one closing it
+    Tag,
+    Function,
+    Impl,
+    /// An argument of a call or a parameter of a function
+    Argument,
+    Expression,
+}
+
+/// Something `i` or `a` picks out around the cursor, to select or to run an operator on
+#[derive(Debug, Clone, Deserialize, Serialize, PartialEq)]
+pub struct TextObject {
+    pub kind: TextObjectKind,
+    /// `a` rather than `i`: takes in the whitespace, quotes or brackets around it
+    pub around: bool,
+}
+
+impl TextObjectKind {
+    /// The object typed after `i` or `a`, `c` is for impl blocks as it is for classes elsewhere
+    pub fn from_key(key: char) -> Option<TextObjectKind> {
+        use TextObjectKind::*;
+        let kind = match key {
+            'w' => Word,
+            's' => Sentence,
+            'p' => Paragraph,
+            '"' | '\'' | '`' => Quote(key),
+            '(' | ')' | 'b' => Bracket('(', ')'),
+            '{' | '}' | 'B' => Bracket('{', '}'),
+            '[' | ']' => Bracket('[', ']'),
+            '<' | '>' => Bracket('<', '>'),
+            't' => Tag,
+            'f' => Function,
+            'c' => Impl,
+            'a' => Argument,
+            'e' => Expression,
+            _ => return None,
+        };
+        Some(kind)
+    }
+
+    /// Found from a syntax tree by a language plugin rather than from the text
+    pub fn is_syntax(&self) -> bool {
+        use TextObjectKind::*;
+        match self {
+            Function | Impl | Argument | Expression => true,
+            _ => false,
+        }
+    }
+}
+
+/// Chars that make up words for `iw`, `*` and matching whole words
+pub fn is_word(c: char) -> bool {
+    c.is_alphanumeric() || c == '_'
+}
+
+fn is_blank(c: char) -> bool {
+    c == ' ' || c == '\t'
+}
+
+impl TextObject {
+    /// First and last char of the object around `index`, `None` for syntax objects
+    pub fn find(&self, rope: &Rope, index: usize) -> Option<(usize, usize)> {
+        let text: Vec<char> = rope.chars().collect();
+        if index >= text.len() {
+            return None;
+        }
+        match self.kind {
+            TextObjectKind::Word => word(&text, index, self.around),
+            TextObjectKind::Sentence => sentence(&text, index, self.around),
+            TextObjectKind::Paragraph => paragraph(rope, index, self.around),
+            TextObjectKind::Quote(quote_char) => quote(&text, index, quote_char, self.around),
+            TextObjectKind::Bracket(open, close) => {
+                let (open, close) = enclosing_brackets(&text, index, open, close)?;
+                if self.around {
+                    Some((open, close))
+                } else {
+                    inside_block(&text, open, close)
+                }
+            }
+            TextObjectKind::Tag => tag(&text, index, self.around),
+            _ => None,
+        }
+    }
+}
+
+/// What `i{` and the like select between the brackets at `open` and `close`. A
+/// bracket that ends its line leaves that line break out, and a closing bracket
+/// that starts its line leaves its indentation out, so blocks delete cleanly.
+pub fn inside_block(text: &[char], open: usize, close: usize) -> Option<(usize, usize)> {
+    let mut start = open + 1;
+    if text.get(start) == Some(&'\n') {
+        start += 1;
+    }
+    let indent_start = (start..close)
+        .rev()
+        .take_while(|at| is_blank(text[*at]))
+        .last()
+        .unwrap_or(close);
+    let end = if indent_start > start && text[indent_start - 1] == '\n' {
+        indent_start
+    } else {
+        close
+    };
+    if start < end {
+        Some((start, end - 1))
+    } else {
+        None
+    }
+}
+
+fn word(text: &[char], index: usize, around: bool) -> Option<(usize, usize)> {
+    // Words, blanks and other characters each make runs of their own
+    let class = |c: char| {
+        if is_word(c) {
+            0
+        } else if is_blank(c) {
+            1
+        } else {
+            2
+        }
+    };
+    if text[index] == '\n' {
+        return None;
+    }
+    let run = |start: usize| {
+        let kind = class(text[start]);
+        let mut end = start;
+        while end + 1 < text.len() && text[end + 1] != '\n' && class(text[end + 1]) == kind {
+            end += 1;
+        }
+        end
+    };
+    let kind = class(text[index]);
+    let mut start = index;
+    while start > 0 && text[start - 1] != '\n' && class(text[start - 1]) == kind {
+        start -= 1;
+    }
+    let mut end = run(index);
+    if !around {
+        return Some((start, end));
+    }
+    let follows = |end: usize| end + 1 < text.len() && text[end + 1] != '\n';
+    if kind == 1 {
+        // On blanks the word after them comes along
+        if follows(end) {
+            end = run(end + 1);
+        }
+    } else if follows(end) && is_blank(text[end + 1]) {
+        end = run(end + 1);
+    } else {
+        // Blanks before the word when there are none after it
+        while start > 0 && is_blank(text[start - 1]) {
+            start -= 1;
+        }
+    }
+    Some((start, end))
+}
+
+fn sentence(text: &[char], index: usize, around: bool) -> Option<(usize, usize)> {
+    let ends_sentence = |at: usize| {
+        (text[at] == '.' || text[at] == '!' || text[at] == '?')
+            && text.get(at + 1).map_or(true, |c| c.is_whitespace())
+    };
+    // Blank lines end sentences as well
+    let blank_line = |at: usize| text[at] == '\n' && text.get(at + 1) == Some(&'\n');
+    let mut start = (0..index)
+        .rev()
+        .find(|at| ends_sentence(*at) || blank_line(*at))
+        .map_or(0, |at| at + 1);
+    while start < text.len() && text[start].is_whitespace() {
+        start += 1;
+    }
+    let mut end = (start..text.len())
+        .find(|at| ends_sentence(*at) || blank_line(*at))
+        .unwrap_or(text.len() - 1);
+    while end > start && text[end].is_whitespace() {
+        end -= 1;
+    }
+    if start > end || start >= text.len() {
+        return None;
+    }
+    if around {
+        while end + 1 < text.len() && text[end + 1].is_whitespace() && !blank_line(end + 1) {
+            end += 1;
+        }
+    }
+    Some((start, end))
+}
+
+fn paragraph(rope: &Rope, index: usize, around: bool) -> Option<(usize, usize)> {
+    let lines = match rope.line(rope.len_lines() - 1).len_chars() {
+        0 => rope.len_lines() - 1,
+        _ => rope.len_lines(),
+    };
+    let blank = |line: usize| rope.line(line).chars().all(char::is_whitespace);
+    let line = rope.char_to_line(index);
+    let kind = blank(line);
+    let (mut first, mut last) = (line, line);
+    while first > 0 && blank(first - 1) == kind {
+        first -= 1;
+    }
+    while last + 1 < lines && blank(last + 1) == kind {
+        last += 1;
+    }
+    if around {
+        // The blank lines after it, or before it at the end of the file
+        if last + 1 < lines {
+            while last + 1 < lines && blank(last + 1) != kind {
+                last += 1;
+            }
+        } else {
+            while first > 0 && blank(first - 1) != kind {
+                first -= 1;
+            }
+        }
+    }
+    let start = rope.line_to_char(first);
+    let end = rope.line_to_char(last) + rope.line(last).len_chars();
+    if start < end {
+        Some((start, end - 1))
+    } else {
+        None
+    }
+}
+
+fn quote(text: &[char], index: usize, quote_char: char, around: bool) -> Option<(usize, usize)> {
+    let line_start = (0..index)
+        .rev()
+        .find(|at| text[*at] == '\n')
+        .map_or(0, |at| at + 1);
+    let line_end = (index..text.len())
+        .find(|at| text[*at] == '\n')
+        .unwrap_or_else(|| text.len());
+    // Quotes pair up from the start of the line, escaped ones don't count
+    let quotes: Vec<usize> = (line_start..line_end)
+        .filter(|at| text[*at] == quote_char && (*at == 0 || text[at - 1] != '\\'))
+        .collect();
+    let (open, close) = quotes
+        .chunks(2)
+        .filter(|pair| pair.len() == 2)
+        .map(|pair| (pair[0], pair[1]))
+        .find(|(_, close)| index <= *close)?;
+    if around {
+        let mut end = close;
+        while end + 1 < line_end && is_blank(text[end + 1]) {
+            end += 1;
+        }
+        Some((open, end))
+    } else if open + 1 < close {
+        Some((open + 1, close - 1))
+    } else {
+        None
+    }
+}
+
+fn enclosing_brackets(
+    text: &[char],
+    index: usize,
+    open_char: char,
+    close_char: char,
+) -> Option<(usize, usize)> {
+    // Walk out to the left skipping pairs on the way, a closing bracket under the
+    // cursor belongs to the pair being looked for
+    let mut depth = 0;
+    let open = (0..=index).rev().find(|at| {
+        if text[*at] == close_char && *at != index {
+            depth += 1;
+        } else if text[*at] == open_char {
+            if depth == 0 {
+                return true;
+            }
+            depth -= 1;
+        }
+        false
+    })?;
+    let mut depth = 0;
+    let close = (open + 1..text.len()).find(|at| {
+        if text[*at] == open_char {
+            depth += 1;
+        } else if text[*at] == close_char {
+            if depth == 0 {
+                return true;
+            }
+            depth -= 1;
+        }
+        false
+    })?;
+    Some((open, close))
+}
+
+fn tag(text: &[char], index: usize, around: bool) -> Option<(usize, usize)> {
+    // Opening tags wait on a stack for the closing tag of the same name
+    let mut open_tags: Vec<(String, usize, usize)> = Vec::new();
+    let mut pairs: Vec<(usize, usize, usize, usize)> = Vec::new();
+    let mut at = 0;
+    while at < text.len() {
+        if text[at] == '<' {
+            if let Some(end) = (at + 1..text.len()).find(|end| text[*end] == '>') {
+                let inside = &text[at + 1..end];
+                let closing = inside.first() == Some(&'/');
+                let name: String = inside
+                    .iter()
+                    .skip(if closing { 1 } else { 0 })
+                    .take_while(|c| c.is_alphanumeric() || **c == '-' || **c == '_' || **c == ':')
+                    .collect();
+                if !name.is_empty() {
+                    if closing {
+                        if let Some(found) =
+                            open_tags.iter().rposition(|(open, _, _)| *open == name)
+                        {
+                            let (_, open_start, open_end) = open_tags[found].clone();
+                            open_tags.truncate(found);
+                            pairs.push((open_start, open_end, at, end));
+                        }
+                    } else if inside.last() != Some(&'/') {
+                        open_tags.push((name, at, end));
+                    }
+                    at = end;
+                }
+            }
+        }
+        at += 1;
+    }
+    let (open_start, open_end, close_start, close_end) = pairs
+        .into_iter()
+        .filter(|(open_start, _, _, close_end)| *open_start <= index && index <= *close_end)
+        .min_by_key(|(open_start, _, _, close_end)| close_end - open_start)?;
+    if around {
+        Some((open_start, close_end))
+    } else if open_end + 1 < close_start {
+        Some((open_end + 1, close_start - 1))
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // The text an object covers with the cursor on the first `at` in `text`
+    fn object_text(text: &str, at: &str, key: char, around: bool) -> Option<String> {
+        let rope = Rope::from_str(text);
+        let index = rope.byte_to_char(text.find(at).unwrap());
+        let object = TextObject {
+            kind: TextObjectKind::from_key(key).unwrap(),
+            around,
+        };
+        object
+            .find(&rope, index)
+            .map(|(start, end)| rope.slice(start..=end).to_string())
+    }
+
+    #[test]
+    fn test_words_and_sentences() {
+        let text = "let foo_bar = 1;  // Set it. Then go!\n";
+        assert_eq!(
+            object_text(text, "_bar", 'w', false),
+            Some("foo_bar".into())
+        );
+        assert_eq!(
+            object_text(text, "_bar", 'w', true),
+            Some("foo_bar ".into())
+        );
+        assert_eq!(object_text(text, ";", 'w', false), Some(";".into()));
+        assert_eq!(object_text(text, "go", 'w', true), Some(" go".into()));
+        assert_eq!(
+            object_text(text, "it", 's', false),
+            Some("let foo_bar = 1;  // Set it.".into())
+        );
+        assert_eq!(
+            object_text(text, "go", 's', true),
+            Some("Then go!\n".into())
+        );
+    }
+
+    #[test]
+    fn test_paragraphs() {
+        let text = "one\ntwo\n\n\nthree\n";
+        assert_eq!(
+            object_text(text, "two", 'p', false),
+            Some("one\ntwo\n".into())
+        );
+        assert_eq!(
+            object_text(text, "two", 'p', true),
+            Some("one\ntwo\n\n\n".into())
+        );
+        assert_eq!(
+            object_text(text, "three", 'p', true),
+            Some("\n\nthree\n".into())
+        );
+    }
+
+    #[test]
+    fn test_quotes_brackets_and_tags() {
+        let text = "call(\"a \\\" b\", vec![x(1)]) <b>bold <i>it</i></b>";
+        assert_eq!(object_text(text, "a ", '"', false), Some("a \\\" b".into()));
+        assert_eq!(
+            object_text(text, "a ", '"', true),
+            Some("\"a \\\" b\"".into())
+        );
+        assert_eq!(object_text(text, "1", '(', false), Some("1".into()));
+        assert_eq!(
+            object_text(text, "vec", 'b', false),
+            Some("\"a \\\" b\", vec![x(1)]".into())
+        );
+        assert_eq!(object_text(text, "x(", ']', true), Some("[x(1)]".into()));
+        assert_eq!(
+            object_text(text, "bold", 't', false),
+            Some("bold <i>it</i>".into())
+        );
+        assert_eq!(object_text(text, "it", 't', true), Some("<i>it</i>".into()));
+        assert_eq!(object_text(text, "call", '{', false), None);
+    }
+
+    #[test]
+    fn test_inside_blocks_leave_out_line_breaks() {
+        let text = "fn main() {\n    go();\n}\n";
+        assert_eq!(
+            object_text(text, "go", '{', false),
+            Some("    go();\n".into())
+        );
+        assert_eq!(object_text("{\n}", "{", '{', false), None);
+    }
+}
@@ -0,0 +1,224 @@
+use crate::{Color, Style};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+// Further than any sensible theme goes, and stops scopes that inherit in a loop
+const MAX_INHERIT_DEPTH: usize = 16;
+
+/// One scope in a theme file, colours written as `#rrggbb`
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ScopeSpec {
+    pub fg: Option<String>,
+    pub bg: Option<String>,
+    /// `bold`, `underlined` or `highlighted`
+    pub style: Option<String>,
+    /// Scope to take whatever is left out from, instead of the one before the last dot
+    pub inherits: Option<String>,
+}
+
+/// A theme file: scopes by name, and optionally another theme it starts from
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ThemeFile {
+    pub inherits: Option<String>,
+    #[serde(flatten)]
+    pub scopes: HashMap<String, ScopeSpec>,
+}
+
+/// How to draw something, parts left as `None` keep what's already there
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct ScopeStyle {
+    pub fg: Option<Color>,
+    pub bg: Option<Color>,
+    pub style: Option<Style>,
+}
+
+#[derive(Debug, Default, Clone, PartialEq)]
+struct Scope {
+    style: ScopeStyle,
+    inherits: Option<String>,
+}
+
+/// Colours for everything drawn, by scope: `keyword`, `ui.selection`,
+/// `diagnostic.error` and so on. A scope without colours of its own gets them
+/// from the scope it inherits, `keyword.control` from `keyword` unless it says
+/// otherwise. `ui.text` holds the colours for plain text.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct Theme {
+    pub name: String,
+    scopes: HashMap<String, Scope>,
+}
+
+pub fn parse_color(text: &str) -> Result<Color, String> {
+    let invalid = || format!("Invalid colour: {}", text);
+    if !text.starts_with('#') || text.len() != 7 || !text.is_ascii() {
+        return Err(invalid());
+    }
+    let channel = |start: usize| u8::from_str_radix(&text[start..start + 2], 16);
+    match (channel(1), channel(3), channel(5)) {
+        (Ok(r), Ok(g), Ok(b)) => Ok(Color { r, g, b }),
+        _ => Err(invalid()),
+    }
+}
+
+fn parse_style(text: &str) -> Result<Style, String> {
+    match text {
+        "bold" => Ok(Style::Bold),
+        "underlined" => Ok(Style::Underlined),
+        "highlighted" => Ok(Style::Highlighted),
+        _ => Err(format!("Invalid style: {}", text)),
+    }
+}
+
+impl Theme {
+    /// Builds the theme `name` from its file, over `base` when the file inherits one
+    pub fn from_file(name: &str, file: ThemeFile, base: Option<Theme>) -> Result<Theme, String> {
+        let mut scopes = base.map(|base| base.scopes).unwrap_or_default();
+        for (scope_name, spec) in file.scopes {
+            let parse = |color: &Option<String>| color.as_ref().map(|c| parse_color(c)).transpose();
+            let scope = Scope {
+                style: ScopeStyle {
+                    fg: parse(&spec.fg).map_err(|err| format!("{}: {}", scope_name, err))?,
+                    bg: parse(&spec.bg).map_err(|err| format!("{}: {}", scope_name, err))?,
+                    style: spec
+                        .style
+                        .as_ref()
+                        .map(|style| parse_style(style))
+                        .transpose()
+                        .map_err(|err| format!("{}: {}", scope_name, err))?,
+                },
+                inherits: spec.inherits,
+            };
+            scopes.insert(scope_name, scope);
+        }
+        Ok(Theme {
+            name: name.into(),
+            scopes,
+        })
+    }
+
+    /// How to draw `scope`, with whatever it leaves out filled in from what it inherits
+    pub fn get(&self, scope: &str) -> ScopeStyle {
+        self.resolve(scope, 0)
+    }
+
+    fn resolve(&self, scope: &str, depth: usize) -> ScopeStyle {
+        if depth > MAX_INHERIT_DEPTH {
+            return ScopeStyle::default();
+        }
+        let own = self.scopes.get(scope);
+        let parent = own
+            .and_then(|own| own.inherits.as_ref().map(String::as_str))
+            .or_else(|| scope.rfind('.').map(|dot| &scope[..dot]));
+        let inherited = parent
+            .map(|parent| self.resolve(parent, depth + 1))
+            .unwrap_or_default();
+        match own {
+            Some(own) => ScopeStyle {
+                fg: own.style.fg.clone().or(inherited.fg),
+                bg: own.style.bg.clone().or(inherited.bg),
+                style: own.style.style.clone().or(inherited.style),
+            },
+            None => inherited,
+        }
+    }
+
+    pub fn fg(&self, scope: &str) -> Option<Color> {
+        self.get(scope).fg
+    }
+
+    pub fn bg(&self, scope: &str) -> Option<Color> {
+        self.get(scope).bg
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn spec(fg: Option<&str>, bg: Option<&str>, inherits: Option<&str>) -> ScopeSpec {
+        ScopeSpec {
+            fg: fg.map(String::from),
+            bg: bg.map(String::from),
+            style: None,
+            inherits: inherits.map(String::from),
+        }
+    }
+
+    fn theme_file(scopes: Vec<(&str, ScopeSpec)>) -> ThemeFile {
+        ThemeFile {
+            inherits: None,
+            scopes: scopes
+                .into_iter()
+                .map(|(name, spec)| (name.to_string(), spec))
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn test_scopes_inherit() {
+        let file = theme_file(vec![
+            ("keyword", spec(Some("#85dc85"), Some("#000000"), None)),
+            ("keyword.control", spec(Some("#ff0000"), None, None)),
+            (
+                "ui.statusline",
+                spec(None, Some("#303030"), Some("keyword")),
+            ),
+        ]);
+        let theme = Theme::from_file("test", file, None).unwrap();
+        let red = Color { r: 255, g: 0, b: 0 };
+        let black = Color { r: 0, g: 0, b: 0 };
+        assert_eq!(theme.fg("keyword.control"), Some(red.clone()));
+        assert_eq!(theme.bg("keyword.control"), Some(black));
+        assert_eq!(theme.fg("keyword.control.flow"), Some(red));
+        assert_eq!(theme.fg("ui.statusline"), theme.fg("keyword"));
+        assert_eq!(theme.get("string"), ScopeStyle::default());
+    }
+
+    #[test]
+    fn test_themes_build_on_a_base() {
+        let base = Theme::from_file(
+            "dark",
+            theme_file(vec![
+                ("string", spec(Some("#cfcfb0"), None, None)),
+                ("comment", spec(Some("#8e908c"), None, None)),
+            ]),
+            None,
+        )
+        .unwrap();
+        let theme = Theme::from_file(
+            "mine",
+            theme_file(vec![("comment", spec(Some("#ffffff"), None, None))]),
+            Some(base.clone()),
+        )
+        .unwrap();
+        assert_eq!(theme.fg("string"), base.fg("string"));
+        assert_eq!(
+            theme.fg("comment"),
+            Some(Color {
+                r: 255,
+                g: 255,
+                b: 255
+            })
+        );
+    }
+
+    #[test]
+    fn test_invalid_theme() {
+        assert!(parse_color("#12345").is_err());
+        assert!(parse_color("#12345g").is_err());
+        assert!(parse_color("123456").is_err());
+        let file = theme_file(vec![("ui.text", spec(Some("white"), None, None))]);
+        assert_eq!(
+            Theme::from_file("bad", file, None),
+            Err("ui.text: Invalid colour: white".to_string())
+        );
+        // Scopes that inherit in a loop end up without colours rather than hanging
+        let file = theme_file(vec![
+            ("a", spec(None, None, Some("b"))),
+            ("b", spec(None, None, Some("a"))),
+        ]);
+        let theme = Theme::from_file("loop", file, None).unwrap();
+        assert_eq!(theme.get("a"), ScopeStyle::default());
+    }
+}
@@ -0,0 +1,279 @@
+use std::ffi::c_void;
+use types::{
+    BackBuffer, Client, ClientIndex, Cmd, Direction, GlobalData, Msg, Point, Rope, SplitDirection,
+    Tab, Utils, WindowIndex, WindowRegion,
+};
+
+#[derive(Debug, Default)]
+struct State {}
+
+#[no_mangle]
+pub fn render(
+    global_data: &GlobalData,
+    client: &ClientIndex,
+    back_buffer: &mut BackBuffer,
+    utils: &Utils,
+    _data_ptr: *mut c_void,
+) {
+    let client = &global_data.clients[*client];
+    if client.shows_tab_bar() {
+        render_tab_bar(global_data, client, back_buffer, utils);
+    }
+    if let Some(area) = client.window_area() {
+        let separator_color = global_data.theme.fg("ui.separator");
+        for separator in client.tab().layout.separators(client.window_origin(), area) {
+            match separator.direction {
+                SplitDirection::Horizontal => {
+                    (utils.write_to_buffer)(
+                        back_buffer,
+                        &separator.origin,
+                        &"─".repeat(separator.length as usize),
+                        None,
+                        separator_color.clone(),
+                        None,
+                    );
+                }
+                SplitDirection::Vertical => {
+                    for offset in 0..separator.length {
+                        (utils.write_to_buffer)(
+                            back_buffer,
+                            &Point {
+                                x: separator.origin.x,
+                                y: separator.origin.y + offset,
+                            },
+                            "│",
+                            None,
+                            separator_color.clone(),
+                            None,
+                        );
+                    }
+                }
+            }
+        }
+    }
+}
+
+fn tab_label(global_data: &GlobalData, tab: &Tab) -> String {
+    if let Some(name) = &tab.name {
+        return name.clone();
+    }
+    global_data.buffers[tab.window().buffer]
+        .source
+        .file_name()
+        .map(|file_name| file_name.to_string_lossy().into_owned())
+        .unwrap_or_else(|| "[No Name]".into())
+}
+
+fn render_tab_bar(
+    global_data: &GlobalData,
+    client: &Client,
+    back_buffer: &mut BackBuffer,
+    utils: &Utils,
+) {
+    let width = client.size.as_ref().map(|size| size.w).unwrap_or(0) as usize;
+    let mut x = 0;
+    for (index, tab) in client.tabs.iter().enumerate() {
+        if x >= width {
+            break;
+        }
+        let label: String = format!(" {} {} ", index + 1, tab_label(global_data, tab))
+            .chars()
+            .take(width - x)
+            .collect();
+        let scope = if index == client.active_tab {
+            "ui.tabline.active"
+        } else {
+            "ui.tabline"
+        };
+        let style = global_data.theme.get(scope);
+        (utils.write_to_buffer)(
+            back_buffer,
+            &Point { x: x as u16, y: 0 },
+            &label,
+            style.style,
+            style.fg,
+            style.bg,
+        );
+        x += label.chars().count();
+    }
+}
+
+fn overlaps(start: u16, length: u16, other_start: u16, other_length: u16) -> bool {
+    start < other_start + other_length && other_start < start + length
+}
+
+fn distance(a: u16, b: u16) -> u16 {
+    if a > b {
+        a - b
+    } else {
+        b - a
+    }
+}
+
+// Closest window in `direction` that shares some rows (or columns) with `active`
+fn find_neighbour(
+    regions: &[WindowRegion],
+    active: &WindowRegion,
+    direction: &Direction,
+) -> Option<WindowIndex> {
+    let (origin, size) = (&active.origin, &active.size);
+    regions
+        .iter()
+        .filter(|region| region.window != active.window)
+        .filter_map(|region| {
+            let (other, other_size) = (&region.origin, &region.size);
+            let gap = match direction {
+                Direction::Left if other.x + other_size.w <= origin.x => {
+                    Some(origin.x - (other.x + other_size.w))
+                }
+                Direction::Right if other.x >= origin.x + size.w => {
+                    Some(other.x - (origin.x + size.w))
+                }
+                Direction::Up if other.y + other_size.h <= origin.y => {
+                    Some(origin.y - (other.y + other_size.h))
+                }
+                Direction::Down if other.y >= origin.y + size.h => {
+                    Some(other.y - (origin.y + size.h))
+                }
+                _ => None,
+            }?;
+            let (aligned, offset) = match direction {
+                Direction::Left | Direction::Right => (
+                    overlaps(origin.y, size.h, other.y, other_size.h),
+                    distance(origin.y, other.y),
+                ),
+                Direction::Up | Direction::Down => (
+                    overlaps(origin.x, size.w, other.x, other_size.w),
+                    distance(origin.x, other.x),
+                ),
+            };
+            if aligned {
+                Some(((gap, offset), region.window))
+            } else {
+                None
+            }
+        })
+        .min_by_key(|(score, _)| *score)
+        .map(|(_, window)| window)
+}
+
+// The buffer may have changed through another window while this one was in the background
+fn clamp_cursor(client: &mut Client, rope: &Rope) {
+    client.window_mut().clamp_to(rope);
+}
+
+#[no_mangle]
+pub fn update(
+    global_data: &mut GlobalData,
+    msg: &Msg,
+    _utils: &Utils,
+    _send_cmd: &Box<Fn(ClientIndex, Cmd)>,
+    _data_ptr: *mut c_void,
+) {
+    match msg {
+        Msg::Cmd(client_index, cmd) => {
+            let client = &mut global_data.clients[*client_index];
+            match cmd {
+                Cmd::SplitWindow(direction) => {
+                    let tab = client.tab_mut();
+                    let new_window = tab.window_keys.insert(());
+                    let copy = tab.window().clone();
+                    tab.windows.insert(new_window, copy);
+                    tab.layout
+                        .split(tab.active_window, new_window, direction.clone());
+                    tab.active_window = new_window;
+                }
+                Cmd::CloseWindow => {
+                    let closed = client.tab().active_window;
+                    let closed_origin = match client.active_region() {
+                        Some(region) => region.origin,
+                        None => Point::default(),
+                    };
+                    if client.tab_mut().layout.remove(closed) {
+                        let tab = client.tab_mut();
+                        tab.windows.remove(closed);
+                        tab.window_keys.remove(closed);
+                        // Focus whichever window took over the closed one's space
+                        let regions = client.window_regions();
+                        let tab = client.tab_mut();
+                        tab.active_window = regions
+                            .iter()
+                            .find(|region| {
+                                overlaps(region.origin.x, region.size.w, closed_origin.x, 1)
+                                    && overlaps(region.origin.y, region.size.h, closed_origin.y, 1)
+                            })
+                            .map(|region| region.window)
+                            .unwrap_or_else(|| tab.layout.windows()[0]);
+                        let buffer = client.buffer();
+                        clamp_cursor(client, &global_data.buffers[buffer].rope);
+                    }
+                }
+                Cmd::FocusWindow(direction) => {
+                    let regions = client.window_regions();
+                    if let Some(active) = client.active_region() {
+                        if let Some(neighbour) = find_neighbour(&regions, &active, direction) {
+                            client.tab_mut().active_window = neighbour;
+                            let buffer = client.buffer();
+                            clamp_cursor(client, &global_data.buffers[buffer].rope);
+                        }
+                    }
+                }
+                Cmd::ResizeWindow(direction, delta) => {
+                    let area = client.window_area().unwrap_or_default();
+                    let tab = client.tab_mut();
+                    tab.layout
+                        .resize(tab.active_window, direction, *delta as i32, area);
+                }
+                Cmd::NewTab => {
+                    // The new tab starts out on the buffer that was being looked at
+                    let tab = Tab::new(client.buffer());
+                    client.active_tab += 1;
+                    client.tabs.insert(client.active_tab, tab);
+                }
+                Cmd::CloseTab => {
+                    if client.tabs.len() > 1 {
+                        client.tabs.remove(client.active_tab);
+                        if client.active_tab >= client.tabs.len() {
+                            client.active_tab = client.tabs.len() - 1;
+                        }
+                        let buffer = client.buffer();
+                        clamp_cursor(client, &global_data.buffers[buffer].rope);
+                    }
+                }
+                Cmd::NextTab => {
+                    client.active_tab = (client.active_tab + 1) % client.tabs.len();
+                    let buffer = client.buffer();
+                    clamp_cursor(client, &global_data.buffers[buffer].rope);
+                }
+                Cmd::PreviousTab => {
+                    client.active_tab =
+                        (client.active_tab + client.tabs.len() - 1) % client.tabs.len();
+                    let buffer = client.buffer();
+                    clamp_cursor(client, &global_data.buffers[buffer].rope);
+                }
+                Cmd::RenameTab(name) => {
+                    client.tab_mut().name = if name.is_empty() {
+                        None
+                    } else {
+                        Some(name.clone())
+                    };
+                }
+                _ => {}
+            }
+        }
+        _ => {}
+    }
+}
+
+#[no_mangle]
+pub fn init() -> *mut c_void {
+    Box::into_raw(Box::new(State::default())) as *mut c_void
+}
+
+#[no_mangle]
+pub fn cleanup(data: *mut c_void) {
+    unsafe {
+        let ptr = Box::from_raw(data as *mut State);
+        drop(ptr);
+    }
+}